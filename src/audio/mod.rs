@@ -0,0 +1,323 @@
+use rodio::{Sink, Source};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub const DEFAULT_FREQUENCY: u32 = 440;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+const SAMPLE_RATE: u32 = 44100;
+// Time the tone takes to fade in when ST is set and out when it runs out
+const RAMP: Duration = Duration::from_millis(5);
+
+// XO-CHIP sample buffer, 128 1-bit samples played from the highest bit of the
+// first byte on
+pub type AudioPattern = [u8; 16];
+// Pitch register value the pattern plays at 4000 bits per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+// Bits per second the pattern plays at, an octave up every 48 pitch steps
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+// Steps through a pattern at its pitch, one output sample at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternPlayer {
+    pub pattern: AudioPattern,
+    step: f32,     // Pattern bits per output sample
+    position: f32, // Bit being played, fractional
+}
+
+impl PatternPlayer {
+    pub fn new(pattern: AudioPattern, pitch: u8, sample_rate: u32) -> PatternPlayer {
+        PatternPlayer {
+            pattern: pattern,
+            step: pattern_rate(pitch) / sample_rate as f32,
+            position: 0.0,
+        }
+    }
+
+    // 1 for a set bit and -1 for a clear one, the pattern loops
+    pub fn next(&mut self) -> f32 {
+        let bit = self.position as usize % 128;
+        self.position = (self.position + self.step) % 128.0;
+        if self.pattern[bit / 8] & (0b10000000 >> (bit % 8)) != 0 {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+}
+
+// Output gain set from the GUI, independent of the sound timer. Muting only
+// silences the output, ST counts down and the ROM sees no difference.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub muted: bool,
+    pub volume: f32, // 0 to 1
+}
+
+impl Default for AudioSettings {
+    fn default() -> AudioSettings {
+        AudioSettings {
+            muted: false,
+            volume: DEFAULT_VOLUME,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn gain(&self) -> f32 {
+        if self.muted {
+            0.0
+        } else {
+            self.volume.max(0.0).min(1.0)
+        }
+    }
+}
+
+// What the tone plays, a square wave until a ROM loads a pattern
+#[derive(Debug, Clone, PartialEq)]
+struct Voice {
+    pattern: Option<AudioPattern>,
+    pitch: u8,
+    settings: AudioSettings,
+}
+
+// Linear attack and release, starting or stopping the square wave at full
+// volume clicks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    gain: f32,
+    step: f32, // Gain change per sample
+}
+
+impl Envelope {
+    pub fn new(sample_rate: u32, ramp: Duration) -> Envelope {
+        let samples = sample_rate as f32 * ramp.as_secs_f32();
+        Envelope {
+            gain: 0.0,
+            step: 1.0 / samples.max(1.0),
+        }
+    }
+
+    // Gain of the next sample, moving towards 1 while on and 0 while off
+    pub fn next(&mut self, on: bool) -> f32 {
+        self.gain = if on {
+            (self.gain + self.step).min(1.0)
+        } else {
+            (self.gain - self.step).max(0.0)
+        };
+        self.gain
+    }
+}
+
+// Endless square wave or pattern shaped by the envelope, silent while the
+// gate is off
+struct Tone {
+    frequency: u32,
+    sample: u64,
+    envelope: Envelope,
+    gate: Arc<AtomicBool>,
+    voice: Arc<Mutex<Voice>>,
+    player: Option<PatternPlayer>,
+    pitch: u8,
+    gain: f32,
+}
+
+impl Tone {
+    fn new(frequency: u32, gate: Arc<AtomicBool>, voice: Arc<Mutex<Voice>>) -> Tone {
+        Tone {
+            frequency: frequency,
+            sample: 0,
+            envelope: Envelope::new(SAMPLE_RATE, RAMP),
+            gate: gate,
+            voice: voice,
+            player: None,
+            pitch: DEFAULT_PITCH,
+            gain: 0.0,
+        }
+    }
+
+    // Picks up a pattern or pitch the ROM changed, restarting the pattern
+    fn update_voice(&mut self) {
+        let voice = match self.voice.lock() {
+            Ok(voice) => voice.clone(),
+            Err(_) => return,
+        };
+        self.gain = voice.settings.gain();
+        let changed = match &self.player {
+            Some(player) => voice.pattern != Some(player.pattern) || voice.pitch != self.pitch,
+            None => voice.pattern.is_some(),
+        };
+        if changed {
+            self.pitch = voice.pitch;
+            self.player = voice
+                .pattern
+                .map(|pattern| PatternPlayer::new(pattern, voice.pitch, SAMPLE_RATE));
+        }
+    }
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        // Checked a few hundred times a second rather than every sample
+        if self.sample % 128 == 0 {
+            self.update_voice();
+        }
+        let level = match &mut self.player {
+            Some(player) => player.next(),
+            None => {
+                let half_periods = self.sample * self.frequency as u64 * 2 / SAMPLE_RATE as u64;
+                if half_periods % 2 == 0 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        };
+        self.sample = self.sample.wrapping_add(1);
+        Some(level * self.envelope.next(self.gate.load(Ordering::Relaxed)) * self.gain)
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// The sound timer's tone. It plays all the time and only the gate changes, so
+// the envelope can ramp it instead of the sink cutting it off.
+pub struct Beeper {
+    _sink: Sink, // Dropping it stops the tone
+    gate: Arc<AtomicBool>,
+    voice: Arc<Mutex<Voice>>,
+}
+
+impl Beeper {
+    // None when there's no audio device
+    pub fn new(frequency: u32, settings: AudioSettings) -> Option<Beeper> {
+        let device = rodio::default_output_device()?;
+        let sink = Sink::new(&device);
+        let gate = Arc::new(AtomicBool::new(false));
+        let voice = Arc::new(Mutex::new(Voice {
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            settings: settings,
+        }));
+        sink.append(Tone::new(frequency, gate.clone(), voice.clone()));
+        Some(Beeper {
+            _sink: sink,
+            gate: gate,
+            voice: voice,
+        })
+    }
+
+    // XO-CHIP pattern to play instead of the square wave, None goes back to it
+    pub fn set_pattern(&self, pattern: Option<AudioPattern>, pitch: u8) {
+        if let Ok(mut voice) = self.voice.lock() {
+            voice.pattern = pattern;
+            voice.pitch = pitch;
+        }
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        self.gate.store(playing, Ordering::Relaxed);
+    }
+
+    pub fn set_settings(&self, settings: AudioSettings) {
+        if let Ok(mut voice) = self.voice.lock() {
+            voice.settings = settings;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_ramp() {
+        // 4 samples to full volume
+        let mut envelope = Envelope::new(1000, Duration::from_millis(4));
+
+        let attack: Vec<f32> = (0..5).map(|_| envelope.next(true)).collect();
+        assert_eq!(vec![0.25, 0.5, 0.75, 1.0, 1.0], attack);
+        let release: Vec<f32> = (0..5).map(|_| envelope.next(false)).collect();
+        assert_eq!(vec![0.75, 0.5, 0.25, 0.0, 0.0], release);
+    }
+
+    #[test]
+    fn pattern_expansion() {
+        let mut pattern = [0u8; 16];
+        pattern[0] = 0b10110000;
+        pattern[15] = 0b00000001;
+
+        // Pitch 64 plays 4000 bits a second, one bit per sample at 4000Hz
+        assert_eq!(4000.0, pattern_rate(DEFAULT_PITCH));
+        let mut player = PatternPlayer::new(pattern, DEFAULT_PITCH, 4000);
+        let samples: Vec<f32> = (0..129).map(|_| player.next()).collect();
+        assert_eq!(vec![1.0, -1.0, 1.0, 1.0, -1.0], samples[0..5].to_vec());
+        assert_eq!(1.0, samples[127]);
+        assert_eq!(1.0, samples[128]);
+
+        // An octave up plays each bit for half a sample, an octave down for two
+        assert_eq!(8000.0, pattern_rate(DEFAULT_PITCH + 48));
+        let mut player = PatternPlayer::new(pattern, DEFAULT_PITCH - 48, 4000);
+        let samples: Vec<f32> = (0..8).map(|_| player.next()).collect();
+        assert_eq!(vec![1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 1.0], samples);
+    }
+
+    fn tone(settings: AudioSettings) -> Tone {
+        let voice = Voice {
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            settings: settings,
+        };
+        // The gate is what ST > 0 opens
+        Tone::new(
+            DEFAULT_FREQUENCY,
+            Arc::new(AtomicBool::new(true)),
+            Arc::new(Mutex::new(voice)),
+        )
+    }
+
+    #[test]
+    fn muted_tone() {
+        let muted = AudioSettings {
+            muted: true,
+            volume: 1.0,
+        };
+        assert_eq!(true, tone(muted).take(1000).all(|sample| sample == 0.0));
+
+        let unmuted = AudioSettings {
+            muted: false,
+            volume: 0.5,
+        };
+        let samples: Vec<f32> = tone(unmuted).take(1000).collect();
+        assert_eq!(true, samples.iter().any(|sample| *sample != 0.0));
+        assert_eq!(true, samples.iter().all(|sample| sample.abs() <= 0.5));
+    }
+
+    #[test]
+    fn envelope_without_ramp() {
+        let mut envelope = Envelope::new(44100, Duration::from_millis(0));
+        assert_eq!(1.0, envelope.next(true));
+        assert_eq!(0.0, envelope.next(false));
+    }
+}
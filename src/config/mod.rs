@@ -0,0 +1,772 @@
+use crate::audio::{DEFAULT_FREQUENCY, DEFAULT_VOLUME};
+use crate::cpu::DEFAULT_IPS;
+use crate::display::{Palette, DEFAULT_PALETTE};
+use crate::quirks::Profile;
+use crate::rom::Byteswap;
+use crate::savestate::SLOTS;
+use crate::state::{
+    BootSplash, PcOverflow, DEFAULT_RAM_SIZE, DEFAULT_STACK_DEPTH, MAX_STACK_DEPTH,
+};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+pub const DEFAULT_HEADLESS_CYCLES: usize = 1_000_000;
+pub const DEFAULT_STEP_REPEAT_MS: u64 = 50;
+pub const DEFAULT_GRID_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 0.25];
+
+pub static USAGE: &str = "Usage: chipster8 [options] path_to_rom
+
+Options:
+    --profile <name>      Compatibility profile: vip, chip48, schip or xochip
+    --ram-size <bytes>    Size of the emulated RAM (default 4096, or the
+                          profile's size)
+    --stack-depth <count> Return addresses CALL can nest (default 16, or the
+                          profile's depth, max 255)
+    --palette <colors>    Four comma separated RRGGBB colors for the pixel values
+    --double-buffer       Show sprites at the next 60Hz vblank instead of as
+                          soon as they're drawn
+    --present-every <n>   Show the screen only every nth 60Hz vblank, merges
+                          sprites erased and drawn again over a few ticks
+                          (default 1, needs double buffering)
+    --boot-splash <name>  What's on the screen before the ROM starts: blank
+                          (default) or font, the digits like a self test
+    --persistence         Let pixels fade out over a few frames instead of
+                          going dark at once, hides flicker
+    --auto-persistence    Turn persistence on when the ROM is seen flickering
+    --pixel-aspect <ratio>
+                          Height of a pixel relative to its width, e.g. 1.2
+                          for taller pixels (default 1.0)
+    --grid-color <color>  RRGGBB or RRGGBBAA color of the display grid lines
+    --tone <hz>           Frequency of the sound timer's beep (default 440)
+    --volume <percent>    Beep volume from 0 to 100 (default 25)
+    --mute                Start with the sound muted, the sound timer still runs
+    --paused              Wait for Run before executing the ROM (default)
+    --autorun             Start running the ROM straight away, also
+                          --start-running
+    --no-focus-pause      Keep running when the window loses focus
+    --ips <count>         Instructions executed per second (default 540)
+    --pc-overflow <mode>  When PC moves past the end of RAM: error (default) to
+                          stop, or wrap to continue from address 0
+    --enforce-alignment   Stop when a jump, call or return lands on an odd
+                          address, off since some ROMs do it on purpose
+    --dump-on-fault       Write the RAM and registers to fault_ram.bin and
+                          fault_registers.txt when an instruction fails
+    --headless            Run without a window, exits with 0 unless an
+                          instruction fails
+    --cycles <count>      Instructions to run in headless mode (default 1000000)
+    --verify <hash>       Run headless and compare the final frame hash, in hex,
+                          exits with 2 when it doesn't match
+    --byteswap            Swap the bytes of every word of the ROM, for dumps
+                          saved little endian
+    --byteswap-auto       Swap them when far more of the words decode that way
+    --disasm <path>       Write the ROM's disassembly to a text file and exit
+                          without running it
+    --max-cycles <count>  Pause once this many instructions have run, headless
+                          mode exits
+    --state-slot <slot>   Quick save slot 0-9 Save and Load State start with,
+                          slot 3 of pong.ch8 is pong.ch8.state3 (default 0)
+    --deterministic       Run exactly one frame of instructions and one timer
+                          tick per rendered frame and draw RND from a seed,
+                          so a run can be replayed
+    --seed <number>       Seed RND draws from, implies --deterministic
+                          (default 0)
+    --record <path>       Record the keys of every frame to a file, written
+                          when the window is closed, implies --deterministic.
+                          Binary for inputs.bin, editable text otherwise
+    --replay <path>       Play back recorded keys with the recorded seed,
+                          headless mode runs as many frames as were recorded
+    --autosave <path>     Resume from this save state if it exists and write
+                          it when the window is closed
+    --autosave-interval <seconds>
+                          Save the state every so many seconds of running to
+                          rom.autosave0 and rom.autosave1 in turn
+    --poke <addr=byte,..> Write bytes to RAM after loading the ROM, both in
+                          hex, e.g. 0x200=12,0x201=34
+    --step-repeat <ms>    Interval between steps while Step or F10 is held
+                          (default 50)
+    --log-collisions      Print the coordinates of every pixel a sprite resets
+    --live-input          Apply key events as they arrive instead of once per
+                          frame, a key tapped within one frame can be missed
+    --keymap <path>       Key bindings file, lines of key name = hex key,
+                          e.g. Q = \"4\". The default layout is used when the
+                          file doesn't exist
+    --gamepad             Read the keypad from a controller too, needs a build
+                          with the gamepad feature
+    --gamepad-map <path>  Controller bindings, lines of button = hex key,
+                          e.g. South = \"5\"";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub rom_path: PathBuf,
+    pub profile: Option<Profile>,
+    pub ram_size: usize,
+    pub stack_depth: usize,
+    pub palette: Palette,
+    pub grid_color: [f32; 4],
+    pub pixel_aspect: f32,
+    pub double_buffer: bool,
+    pub present_interval: u32,
+    pub persistence: bool,
+    pub auto_persistence: bool,
+    pub tone_hz: u32,
+    pub volume: f32,
+    pub muted: bool,
+    pub start_running: bool,
+    pub pause_on_focus_loss: bool,
+    pub ips: u32,
+    pub dump_on_fault: bool,
+    pub enforce_alignment: bool,
+    pub pc_overflow: PcOverflow,
+    pub boot_splash: BootSplash,
+    pub headless: bool,
+    pub cycles: usize,
+    pub max_cycles: Option<u64>,
+    pub verify_hash: Option<u64>,
+    pub disasm_path: Option<PathBuf>,
+    pub byteswap: Byteswap,
+    pub autosave_path: Option<PathBuf>,
+    pub autosave_interval: Option<u64>, // Seconds
+    pub deterministic: bool,
+    pub seed: u64,
+    pub record_path: Option<PathBuf>,
+    pub replay_path: Option<PathBuf>,
+    pub state_slot: u8,
+    pub pokes: Vec<(u16, u8)>,
+    pub keymap_path: Option<PathBuf>,
+    pub live_input: bool,
+    pub gamepad: bool,
+    pub gamepad_map_path: Option<PathBuf>,
+    pub log_collisions: bool,
+    pub step_repeat_ms: u64,
+}
+
+impl Config {
+    // Parses the command line arguments, without the program name
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        let mut rom_path = None;
+        let mut profile = None;
+        let mut ram_size = None;
+        let mut stack_depth = None;
+        let mut palette = DEFAULT_PALETTE;
+        let mut grid_color = DEFAULT_GRID_COLOR;
+        let mut pixel_aspect = 1.0;
+        let mut double_buffer = false;
+        let mut present_interval = 1;
+        let mut persistence = false;
+        let mut auto_persistence = false;
+        let mut tone_hz = DEFAULT_FREQUENCY;
+        let mut volume = DEFAULT_VOLUME;
+        let mut muted = false;
+        let mut start_running = false;
+        let mut pause_on_focus_loss = true;
+        let mut ips = DEFAULT_IPS;
+        let mut dump_on_fault = false;
+        let mut enforce_alignment = false;
+        let mut pc_overflow = PcOverflow::Error;
+        let mut boot_splash = BootSplash::Blank;
+        let mut headless = false;
+        let mut cycles = DEFAULT_HEADLESS_CYCLES;
+        let mut max_cycles = None;
+        let mut verify_hash = None;
+        let mut disasm_path = None;
+        let mut byteswap = Byteswap::Off;
+        let mut autosave_path = None;
+        let mut autosave_interval = None;
+        let mut deterministic = false;
+        let mut seed = None;
+        let mut record_path = None;
+        let mut replay_path = None;
+        let mut state_slot = 0;
+        let mut pokes = Vec::new();
+        let mut keymap_path = None;
+        let mut live_input = false;
+        let mut gamepad = false;
+        let mut gamepad_map_path = None;
+        let mut log_collisions = false;
+        let mut step_repeat_ms = DEFAULT_STEP_REPEAT_MS;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--profile" => {
+                    let name = next_value(&mut args, arg)?;
+                    profile = Some(
+                        Profile::from_name(name)
+                            .ok_or_else(|| format!("Unknown profile: {}", name))?,
+                    );
+                }
+                "--ram-size" => {
+                    let size = parse_number(next_value(&mut args, arg)?)?;
+                    if size < 0x200 || size > 0x10000 {
+                        return Err(format!("RAM size out of range: {}", size));
+                    }
+                    ram_size = Some(size);
+                }
+                "--stack-depth" => {
+                    let depth = parse_number(next_value(&mut args, arg)?)?;
+                    if depth == 0 || depth > MAX_STACK_DEPTH {
+                        return Err(format!("Stack depth out of range: {}", depth));
+                    }
+                    stack_depth = Some(depth);
+                }
+                "--palette" => palette = parse_palette(next_value(&mut args, arg)?)?,
+                "--grid-color" => grid_color = parse_color(next_value(&mut args, arg)?)?,
+                "--pixel-aspect" => {
+                    let value = next_value(&mut args, arg)?;
+                    pixel_aspect = value
+                        .parse::<f32>()
+                        .map_err(|_| format!("Invalid number: {}", value))?;
+                    if !(0.25..=4.0).contains(&pixel_aspect) {
+                        return Err(format!("Pixel aspect out of range: {}", value));
+                    }
+                }
+                "--double-buffer" => double_buffer = true,
+                "--present-every" => {
+                    present_interval = parse_u32(next_value(&mut args, arg)?)?;
+                    if present_interval == 0 {
+                        return Err(String::from("Present interval can't be 0"));
+                    }
+                }
+                "--persistence" => persistence = true,
+                "--auto-persistence" => auto_persistence = true,
+                "--mute" => muted = true,
+                "--tone" => {
+                    tone_hz = parse_u32(next_value(&mut args, arg)?)?;
+                    if tone_hz == 0 || tone_hz > 20000 {
+                        return Err(format!("Tone frequency out of range: {}", tone_hz));
+                    }
+                }
+                "--volume" => {
+                    let percent = parse_number(next_value(&mut args, arg)?)?;
+                    if percent > 100 {
+                        return Err(format!("Volume out of range: {}", percent));
+                    }
+                    volume = percent as f32 / 100.0;
+                }
+                "--paused" => start_running = false,
+                "--autorun" | "--start-running" => start_running = true,
+                "--no-focus-pause" => pause_on_focus_loss = false,
+                "--ips" => {
+                    ips = parse_u32(next_value(&mut args, arg)?)?;
+                    if ips == 0 {
+                        return Err(String::from("Instructions per second can't be 0"));
+                    }
+                }
+                "--step-repeat" => {
+                    step_repeat_ms = parse_number(next_value(&mut args, arg)?)? as u64;
+                    if step_repeat_ms == 0 {
+                        return Err(String::from("Step repeat interval can't be 0"));
+                    }
+                }
+                "--dump-on-fault" => dump_on_fault = true,
+                "--enforce-alignment" => enforce_alignment = true,
+                "--pc-overflow" => {
+                    let value = next_value(&mut args, arg)?;
+                    pc_overflow = PcOverflow::from_name(value)
+                        .ok_or_else(|| format!("Unknown PC overflow mode: {}", value))?;
+                }
+                "--boot-splash" => {
+                    let value = next_value(&mut args, arg)?;
+                    boot_splash = BootSplash::from_name(value)
+                        .ok_or_else(|| format!("Unknown boot splash: {}", value))?;
+                }
+                "--headless" => headless = true,
+                "--log-collisions" => log_collisions = true,
+                "--live-input" => live_input = true,
+                "--gamepad" => gamepad = true,
+                "--cycles" => cycles = parse_number(next_value(&mut args, arg)?)?,
+                "--max-cycles" => {
+                    max_cycles = Some(parse_number(next_value(&mut args, arg)?)? as u64)
+                }
+                "--verify" => {
+                    let value = next_value(&mut args, arg)?;
+                    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+                    let hash = u64::from_str_radix(digits, 16)
+                        .map_err(|_| format!("Invalid frame hash: {}", value))?;
+                    verify_hash = Some(hash);
+                }
+                "--poke" => pokes.extend(parse_pokes(next_value(&mut args, arg)?)?),
+                "--state-slot" => {
+                    let slot = parse_number(next_value(&mut args, arg)?)?;
+                    if slot >= SLOTS as usize {
+                        return Err(format!("State slot out of range: {}", slot));
+                    }
+                    state_slot = slot as u8;
+                }
+                "--byteswap" => byteswap = Byteswap::On,
+                "--byteswap-auto" => byteswap = Byteswap::Auto,
+                "--disasm" => disasm_path = Some(PathBuf::from(next_value(&mut args, arg)?)),
+                "--deterministic" => deterministic = true,
+                "--seed" => seed = Some(parse_number(next_value(&mut args, arg)?)? as u64),
+                "--record" => record_path = Some(PathBuf::from(next_value(&mut args, arg)?)),
+                "--replay" => replay_path = Some(PathBuf::from(next_value(&mut args, arg)?)),
+                "--autosave" => autosave_path = Some(PathBuf::from(next_value(&mut args, arg)?)),
+                "--autosave-interval" => {
+                    let seconds = parse_number(next_value(&mut args, arg)?)? as u64;
+                    if seconds == 0 {
+                        return Err(String::from("Autosave interval can't be 0"));
+                    }
+                    autosave_interval = Some(seconds);
+                }
+                "--keymap" => keymap_path = Some(PathBuf::from(next_value(&mut args, arg)?)),
+                "--gamepad-map" => {
+                    gamepad_map_path = Some(PathBuf::from(next_value(&mut args, arg)?))
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                _ => rom_path = Some(PathBuf::from(arg)),
+            }
+        }
+
+        Ok(Config {
+            rom_path: rom_path.ok_or_else(|| String::from("No ROM given"))?,
+            profile: profile,
+            ram_size: ram_size
+                .unwrap_or_else(|| profile.map_or(DEFAULT_RAM_SIZE, |profile| profile.ram_size())),
+            stack_depth: stack_depth.unwrap_or_else(|| {
+                profile.map_or(DEFAULT_STACK_DEPTH, |profile| profile.stack_depth())
+            }),
+            palette: palette,
+            grid_color: grid_color,
+            pixel_aspect: pixel_aspect,
+            double_buffer: double_buffer,
+            present_interval: present_interval,
+            persistence: persistence,
+            auto_persistence: auto_persistence,
+            tone_hz: tone_hz,
+            volume: volume,
+            muted: muted,
+            start_running: start_running,
+            pause_on_focus_loss: pause_on_focus_loss,
+            ips: ips,
+            dump_on_fault: dump_on_fault,
+            enforce_alignment: enforce_alignment,
+            pc_overflow: pc_overflow,
+            boot_splash: boot_splash,
+            // Verifying only makes sense without a window
+            headless: headless || verify_hash.is_some(),
+            cycles: cycles,
+            max_cycles: max_cycles,
+            verify_hash: verify_hash,
+            disasm_path: disasm_path,
+            byteswap: byteswap,
+            autosave_path: autosave_path,
+            autosave_interval: autosave_interval,
+            deterministic: deterministic
+                || seed.is_some()
+                || record_path.is_some()
+                || replay_path.is_some(),
+            seed: seed.unwrap_or(0),
+            record_path: record_path,
+            replay_path: replay_path,
+            state_slot: state_slot,
+            pokes: pokes,
+            keymap_path: keymap_path,
+            live_input: live_input,
+            gamepad: gamepad,
+            gamepad_map_path: gamepad_map_path,
+            log_collisions: log_collisions,
+            step_repeat_ms: step_repeat_ms,
+        })
+    }
+}
+
+fn next_value<'a>(
+    args: &mut std::slice::Iter<'a, String>,
+    option: &str,
+) -> Result<&'a String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for {}", option))
+}
+
+// Accepts both decimal and 0x prefixed hex numbers
+fn parse_number(value: &str) -> Result<usize, String> {
+    let parsed = if value.starts_with("0x") || value.starts_with("0X") {
+        usize::from_str_radix(&value[2..], 16)
+    } else {
+        value.parse::<usize>()
+    };
+    parsed.map_err(|_| format!("Invalid number: {}", value))
+}
+
+// Like parse_number, but too big for a u32 is an error instead of wrapping
+fn parse_u32(value: &str) -> Result<u32, String> {
+    u32::try_from(parse_number(value)?).map_err(|_| format!("Number out of range: {}", value))
+}
+
+fn parse_hex(value: &str) -> Result<u32, String> {
+    let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+    u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid hex number: {}", value))
+}
+
+// Parses addr=byte pairs separated by commas, both in hex
+fn parse_pokes(value: &str) -> Result<Vec<(u16, u8)>, String> {
+    let mut pokes = Vec::new();
+    for poke in value.split(',') {
+        let mut parts = poke.splitn(2, '=');
+        let addr = parse_hex(parts.next().unwrap_or("").trim())?;
+        let byte = parse_hex(
+            parts
+                .next()
+                .ok_or_else(|| format!("Missing value in poke: {}", poke))?
+                .trim(),
+        )?;
+        if addr > 0xFFFF || byte > 0xFF {
+            return Err(format!("Poke out of range: {}", poke));
+        }
+        pokes.push((addr as u16, byte as u8));
+    }
+    Ok(pokes)
+}
+
+fn parse_palette(value: &str) -> Result<Palette, String> {
+    let colors: Vec<&str> = value.split(',').collect();
+    if colors.len() != 4 {
+        return Err(format!("Palette needs 4 colors: {}", value));
+    }
+
+    let mut palette = DEFAULT_PALETTE;
+    for (color, hex) in palette.iter_mut().zip(colors) {
+        *color = parse_color(hex)?;
+    }
+    Ok(palette)
+}
+
+// RRGGBB or RRGGBBAA with an optional #, opaque when there's no alpha
+fn parse_color(hex: &str) -> Result<[f32; 4], String> {
+    let digits = hex.trim_start_matches('#');
+    let value = u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid color: {}", hex))?;
+    let rgba = match digits.len() {
+        6 => value << 8 | 0xFF,
+        8 => value,
+        _ => return Err(format!("Invalid color: {}", hex)),
+    };
+    Ok([
+        ((rgba >> 24) & 0xFF) as f32 / 255.0,
+        ((rgba >> 16) & 0xFF) as f32 / 255.0,
+        ((rgba >> 8) & 0xFF) as f32 / 255.0,
+        (rgba & 0xFF) as f32 / 255.0,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| String::from(*arg)).collect()
+    }
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_args(&args(&["pong.ch8"])).unwrap();
+        assert_eq!(PathBuf::from("pong.ch8"), config.rom_path);
+        assert_eq!(DEFAULT_RAM_SIZE, config.ram_size);
+        assert_eq!(None, config.profile);
+        assert_eq!(None, config.autosave_path);
+        assert_eq!(None, config.autosave_interval);
+        assert_eq!(true, config.pause_on_focus_loss);
+        assert_eq!(DEFAULT_IPS, config.ips);
+        assert_eq!(false, config.dump_on_fault);
+        assert_eq!(false, config.log_collisions);
+        assert_eq!(false, config.double_buffer);
+        assert_eq!(false, config.live_input);
+        assert_eq!(false, config.enforce_alignment);
+    }
+
+    #[test]
+    fn byteswap() {
+        assert_eq!(
+            Byteswap::Off,
+            Config::from_args(&args(&["a"])).unwrap().byteswap
+        );
+        let config = Config::from_args(&args(&["--byteswap", "a"])).unwrap();
+        assert_eq!(Byteswap::On, config.byteswap);
+        let config = Config::from_args(&args(&["--byteswap-auto", "a"])).unwrap();
+        assert_eq!(Byteswap::Auto, config.byteswap);
+    }
+
+    #[test]
+    fn disasm() {
+        let config = Config::from_args(&args(&["--disasm", "pong.txt", "pong.ch8"])).unwrap();
+        assert_eq!(Some(PathBuf::from("pong.txt")), config.disasm_path);
+        assert_eq!(None, Config::from_args(&args(&["a"])).unwrap().disasm_path);
+        assert!(Config::from_args(&args(&["a", "--disasm"])).is_err());
+    }
+
+    #[test]
+    fn deterministic() {
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(false, config.deterministic);
+        assert_eq!(0, config.seed);
+        let config = Config::from_args(&args(&["--seed", "0x2a", "a"])).unwrap();
+        assert_eq!(true, config.deterministic);
+        assert_eq!(42, config.seed);
+        let config = Config::from_args(&args(&["--record", "keys.txt", "a"])).unwrap();
+        assert_eq!(true, config.deterministic);
+        assert_eq!(Some(PathBuf::from("keys.txt")), config.record_path);
+        let config = Config::from_args(&args(&["--replay", "keys.txt", "a"])).unwrap();
+        assert_eq!(true, config.deterministic);
+        assert_eq!(Some(PathBuf::from("keys.txt")), config.replay_path);
+    }
+
+    #[test]
+    fn pc_overflow() {
+        let config = Config::from_args(&args(&["--pc-overflow", "wrap", "a"])).unwrap();
+        assert_eq!(PcOverflow::Wrap, config.pc_overflow);
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(PcOverflow::Error, config.pc_overflow);
+        assert!(Config::from_args(&args(&["--pc-overflow", "clamp", "a"])).is_err());
+    }
+
+    #[test]
+    fn boot_splash() {
+        let config = Config::from_args(&args(&["--boot-splash", "font", "a"])).unwrap();
+        assert_eq!(BootSplash::Font, config.boot_splash);
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(BootSplash::Blank, config.boot_splash);
+        assert!(Config::from_args(&args(&["--boot-splash", "logo", "a"])).is_err());
+    }
+
+    #[test]
+    fn enforce_alignment() {
+        let config = Config::from_args(&args(&["--enforce-alignment", "a"])).unwrap();
+        assert_eq!(true, config.enforce_alignment);
+    }
+
+    #[test]
+    fn live_input() {
+        let config = Config::from_args(&args(&["--live-input", "a"])).unwrap();
+        assert_eq!(true, config.live_input);
+    }
+
+    #[test]
+    fn pixel_aspect() {
+        let config = Config::from_args(&args(&["--pixel-aspect", "1.2", "a"])).unwrap();
+        assert_eq!(1.2, config.pixel_aspect);
+        assert_eq!(1.0, Config::from_args(&args(&["a"])).unwrap().pixel_aspect);
+        assert!(Config::from_args(&args(&["--pixel-aspect", "tall", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--pixel-aspect", "0", "a"])).is_err());
+    }
+
+    #[test]
+    fn persistence() {
+        let config = Config::from_args(&args(&["--auto-persistence", "a"])).unwrap();
+        assert_eq!(false, config.persistence);
+        assert_eq!(true, config.auto_persistence);
+        let config = Config::from_args(&args(&["--persistence", "a"])).unwrap();
+        assert_eq!(true, config.persistence);
+        assert_eq!(false, config.auto_persistence);
+    }
+
+    #[test]
+    fn double_buffer() {
+        let config = Config::from_args(&args(&["--double-buffer", "a"])).unwrap();
+        assert_eq!(true, config.double_buffer);
+    }
+
+    #[test]
+    fn present_every() {
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(1, config.present_interval);
+        let config = Config::from_args(&args(&["--present-every", "3", "a"])).unwrap();
+        assert_eq!(3, config.present_interval);
+        assert!(Config::from_args(&args(&["--present-every", "0", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--present-every", "4294967297", "a"])).is_err());
+    }
+
+    #[test]
+    fn autosave_interval() {
+        let config = Config::from_args(&args(&["--autosave-interval", "30", "a"])).unwrap();
+        assert_eq!(Some(30), config.autosave_interval);
+        assert!(Config::from_args(&args(&["--autosave-interval", "0", "a"])).is_err());
+    }
+
+    #[test]
+    fn log_collisions() {
+        let config = Config::from_args(&args(&["--log-collisions", "a"])).unwrap();
+        assert_eq!(true, config.log_collisions);
+    }
+
+    #[test]
+    fn ips() {
+        let config = Config::from_args(&args(&["--ips", "1000", "pong.ch8"])).unwrap();
+        assert_eq!(1000, config.ips);
+        assert!(Config::from_args(&args(&["--ips", "0", "pong.ch8"])).is_err());
+        assert!(Config::from_args(&args(&["--ips", "0x100000000", "pong.ch8"])).is_err());
+    }
+
+    #[test]
+    fn tone() {
+        let config = Config::from_args(&args(&["--tone", "880", "--volume", "50", "a"])).unwrap();
+        assert_eq!(880, config.tone_hz);
+        assert_eq!(0.5, config.volume);
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(DEFAULT_FREQUENCY, config.tone_hz);
+        assert_eq!(DEFAULT_VOLUME, config.volume);
+        assert_eq!(false, config.muted);
+        assert_eq!(
+            true,
+            Config::from_args(&args(&["--mute", "a"])).unwrap().muted
+        );
+        assert!(Config::from_args(&args(&["--tone", "0", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--tone", "4294967736", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--volume", "101", "a"])).is_err());
+    }
+
+    #[test]
+    fn step_repeat() {
+        let config = Config::from_args(&args(&["--step-repeat", "100", "a"])).unwrap();
+        assert_eq!(100, config.step_repeat_ms);
+        assert_eq!(
+            DEFAULT_STEP_REPEAT_MS,
+            Config::from_args(&args(&["a"])).unwrap().step_repeat_ms
+        );
+        assert!(Config::from_args(&args(&["--step-repeat", "0", "a"])).is_err());
+    }
+
+    #[test]
+    fn start_running() {
+        assert_eq!(
+            false,
+            Config::from_args(&args(&["a"])).unwrap().start_running
+        );
+        for flag in ["--autorun", "--start-running"].iter() {
+            let config = Config::from_args(&args(&[flag, "a"])).unwrap();
+            assert_eq!(true, config.start_running);
+        }
+        let config = Config::from_args(&args(&["--autorun", "--paused", "a"])).unwrap();
+        assert_eq!(false, config.start_running);
+    }
+
+    #[test]
+    fn no_focus_pause() {
+        let config = Config::from_args(&args(&["--no-focus-pause", "pong.ch8"])).unwrap();
+        assert_eq!(false, config.pause_on_focus_loss);
+    }
+
+    #[test]
+    fn ram_size() {
+        let config = Config::from_args(&args(&["--ram-size", "0x10000", "pong.ch8"])).unwrap();
+        assert_eq!(65536, config.ram_size);
+        assert!(Config::from_args(&args(&["--ram-size", "100", "pong.ch8"])).is_err());
+        assert!(Config::from_args(&args(&["--ram-size"])).is_err());
+    }
+
+    #[test]
+    fn state_slot() {
+        let config = Config::from_args(&args(&["--state-slot", "7", "a"])).unwrap();
+        assert_eq!(7, config.state_slot);
+        assert_eq!(0, Config::from_args(&args(&["a"])).unwrap().state_slot);
+        assert!(Config::from_args(&args(&["--state-slot", "10", "a"])).is_err());
+    }
+
+    #[test]
+    fn stack_depth() {
+        let config = Config::from_args(&args(&["--stack-depth", "32", "a"])).unwrap();
+        assert_eq!(32, config.stack_depth);
+        assert_eq!(16, Config::from_args(&args(&["a"])).unwrap().stack_depth);
+        assert!(Config::from_args(&args(&["--stack-depth", "0", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--stack-depth", "256", "a"])).is_err());
+        let config = Config::from_args(&args(&["--profile", "vip", "a"])).unwrap();
+        assert_eq!(12, config.stack_depth);
+        let config =
+            Config::from_args(&args(&["--profile", "vip", "--stack-depth", "24", "a"])).unwrap();
+        assert_eq!(24, config.stack_depth);
+    }
+
+    #[test]
+    fn profile() {
+        let config = Config::from_args(&args(&["--profile", "xochip", "a"])).unwrap();
+        assert_eq!(Some(Profile::XoChip), config.profile);
+        assert_eq!(0x10000, config.ram_size);
+
+        let config =
+            Config::from_args(&args(&["--ram-size", "8192", "--profile", "xochip", "a"])).unwrap();
+        assert_eq!(8192, config.ram_size);
+        assert!(Config::from_args(&args(&["--profile", "chip-9", "a"])).is_err());
+    }
+
+    #[test]
+    fn palette() {
+        let config =
+            Config::from_args(&args(&["--palette", "000000,FFFFFF,#FF0000,00ff00", "a"])).unwrap();
+        assert_eq!([1.0, 1.0, 1.0, 1.0], config.palette[1]);
+        assert_eq!([1.0, 0.0, 0.0, 1.0], config.palette[2]);
+        assert_eq!([0.0, 1.0, 0.0, 1.0], config.palette[3]);
+        assert!(Config::from_args(&args(&["--palette", "000000,FFFFFF", "a"])).is_err());
+    }
+
+    #[test]
+    fn grid_color() {
+        let config = Config::from_args(&args(&["--grid-color", "#FF000080", "a"])).unwrap();
+        assert_eq!([1.0, 0.0, 0.0, 128.0 / 255.0], config.grid_color);
+        let config = Config::from_args(&args(&["--grid-color", "00FF00", "a"])).unwrap();
+        assert_eq!([0.0, 1.0, 0.0, 1.0], config.grid_color);
+        assert_eq!(
+            DEFAULT_GRID_COLOR,
+            Config::from_args(&args(&["a"])).unwrap().grid_color
+        );
+        assert!(Config::from_args(&args(&["--grid-color", "FFF", "a"])).is_err());
+    }
+
+    #[test]
+    fn verify() {
+        let config = Config::from_args(&args(&["--verify", "0xcbf29ce484222325", "a"])).unwrap();
+        assert_eq!(Some(0xcbf29ce484222325), config.verify_hash);
+        assert_eq!(true, config.headless);
+        let config = Config::from_args(&args(&["--verify", "FF", "a"])).unwrap();
+        assert_eq!(Some(0xFF), config.verify_hash);
+        assert_eq!(None, Config::from_args(&args(&["a"])).unwrap().verify_hash);
+        assert!(Config::from_args(&args(&["--verify", "hash", "a"])).is_err());
+    }
+
+    #[test]
+    fn headless() {
+        let config = Config::from_args(&args(&["--headless", "--cycles", "500", "a"])).unwrap();
+        assert_eq!(true, config.headless);
+        assert_eq!(500, config.cycles);
+        assert_eq!(
+            DEFAULT_HEADLESS_CYCLES,
+            Config::from_args(&args(&["a"])).unwrap().cycles
+        );
+    }
+
+    #[test]
+    fn max_cycles() {
+        let config = Config::from_args(&args(&["--max-cycles", "0x100", "a"])).unwrap();
+        assert_eq!(Some(0x100), config.max_cycles);
+        assert_eq!(None, Config::from_args(&args(&["a"])).unwrap().max_cycles);
+    }
+
+    #[test]
+    fn pokes() {
+        let config = Config::from_args(&args(&["--poke", "0x200=12,201=0x34", "a"])).unwrap();
+        assert_eq!(vec![(0x200, 0x12), (0x201, 0x34)], config.pokes);
+        assert!(Config::from_args(&args(&["--poke", "0x200", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--poke", "0x200=100", "a"])).is_err());
+        assert!(Config::from_args(&args(&["--poke", "0x10000=1", "a"])).is_err());
+    }
+
+    #[test]
+    fn keymap() {
+        let config = Config::from_args(&args(&["--keymap", "keys.toml", "a"])).unwrap();
+        assert_eq!(Some(PathBuf::from("keys.toml")), config.keymap_path);
+        assert_eq!(None, Config::from_args(&args(&["a"])).unwrap().keymap_path);
+    }
+
+    #[test]
+    fn gamepad() {
+        let config =
+            Config::from_args(&args(&["--gamepad", "--gamepad-map", "pad.toml", "a"])).unwrap();
+        assert_eq!(true, config.gamepad);
+        assert_eq!(Some(PathBuf::from("pad.toml")), config.gamepad_map_path);
+        let config = Config::from_args(&args(&["a"])).unwrap();
+        assert_eq!(false, config.gamepad);
+        assert_eq!(None, config.gamepad_map_path);
+    }
+
+    #[test]
+    fn missing_rom() {
+        assert!(Config::from_args(&args(&[])).is_err());
+    }
+}
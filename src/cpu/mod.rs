@@ -0,0 +1,692 @@
+use crate::instruction;
+use crate::state::{ExecError, State, TRACE_LEN};
+use std::time::Duration;
+
+// Instructions executed per 60Hz timer tick
+pub const CYCLES_PER_TICK: usize = 9;
+pub const TIMER_HZ: u32 = 60;
+pub const DEFAULT_IPS: u32 = CYCLES_PER_TICK as u32 * TIMER_HZ;
+
+// Longest stretch of time made up for after a stall, anything above is dropped
+// so a long pause doesn't turn into a burst of catch-up work
+const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+// Converts elapsed wall clock time into instructions and timer ticks to run.
+// Leftover fractions are carried to the next call so no time is lost.
+pub struct Scheduler {
+    pub ips: u32,
+    cycle_remainder: u128, // In nanoseconds * ips
+    tick_remainder: u128,  // In nanoseconds * TIMER_HZ
+}
+
+impl Scheduler {
+    pub fn new(ips: u32) -> Scheduler {
+        Scheduler {
+            ips: ips,
+            cycle_remainder: 0,
+            tick_remainder: 0,
+        }
+    }
+
+    // Returns the number of instructions and timer ticks due
+    pub fn advance(&mut self, elapsed: Duration) -> (u32, u32) {
+        let elapsed = elapsed.min(MAX_CATCH_UP).as_nanos();
+
+        self.cycle_remainder += elapsed * self.ips as u128;
+        self.tick_remainder += elapsed * TIMER_HZ as u128;
+        let cycles = self.cycle_remainder / NANOS_PER_SEC;
+        let ticks = self.tick_remainder / NANOS_PER_SEC;
+        self.cycle_remainder %= NANOS_PER_SEC;
+        self.tick_remainder %= NANOS_PER_SEC;
+
+        (cycles as u32, ticks as u32)
+    }
+}
+
+// Turns a held Step button into steps: one straight away on the press, then
+// one per interval for as long as it's held
+pub struct RepeatTimer {
+    pub interval: Duration,
+    held: bool,
+    remainder: Duration,
+}
+
+impl RepeatTimer {
+    pub fn new(interval: Duration) -> RepeatTimer {
+        RepeatTimer {
+            interval: interval,
+            held: false,
+            remainder: Duration::from_secs(0),
+        }
+    }
+
+    // Returns the number of steps due
+    pub fn update(&mut self, held: bool, elapsed: Duration) -> u32 {
+        if !held {
+            self.held = false;
+            return 0;
+        }
+        if !self.held {
+            self.held = true;
+            self.remainder = Duration::from_secs(0);
+            return 1;
+        }
+
+        self.remainder += elapsed.min(MAX_CATCH_UP);
+        let interval = self.interval.as_nanos().max(1);
+        let steps = self.remainder.as_nanos() / interval;
+        self.remainder = Duration::from_nanos((self.remainder.as_nanos() % interval) as u64);
+        steps as u32
+    }
+}
+
+// Opcode at PC
+pub fn fetch(state: &State) -> Result<u16, ExecError> {
+    state.opcode_at(state.pc as usize)
+}
+
+pub fn execute(state: &mut State) -> bool {
+    if state.waiting_for_vblank {
+        return true;
+    }
+
+    let instruction = match state.current_instruction() {
+        Ok(instruction) => instruction,
+        Err(error) => {
+            state.fault = Some(error);
+            return report_fault(state, &format!("{:04X}", state.pc));
+        }
+    };
+
+    if state.trace.len() == TRACE_LEN {
+        state.trace.pop_front();
+    }
+    state.trace.push_back(state.pc);
+    state.fault = None;
+    if !state.enabled_ops.is_enabled(instruction.opcode) {
+        state.fault = Some(ExecError::Disabled(instruction.opcode));
+        return report_fault(state, &instruction.code);
+    }
+    if !instruction.function(state) {
+        return report_fault(state, &instruction.code);
+    }
+    state.instruction_count += 1;
+    if let Some(index) = instruction::family_index(instruction::family(instruction.opcode)) {
+        state.family_counts[index] += 1;
+    }
+    // Everything else adds 2, only a jump, call or return can make PC odd
+    if state.enforce_alignment && state.pc % 2 != 0 {
+        state.fault = Some(ExecError::MisalignedPc(state.pc));
+        return report_fault(state, &instruction.code);
+    }
+    true
+}
+
+// Prints the fault the instruction left in state.fault, returns false for
+// execute to pass on. Every way execute can fail ends here.
+fn report_fault(state: &State, code: &str) -> bool {
+    match state.fault {
+        Some(fault) => println!("Failed to execute {}: {}", code, fault),
+        None => println!("Failed to execute instruction!"),
+    }
+    false
+}
+
+pub fn update_timers(state: &mut State) {
+    // The vblank and the beeper update still happen, only the countdown
+    // stops. The screen shows what was drawn up to the last vblank.
+    state.waiting_for_vblank = false;
+    state.display.vblank();
+    if let Some(audio_output) = &state.audio_output {
+        audio_output.set_pattern(state.audio_pattern, state.pitch);
+        audio_output.set_playing(state.st > 0);
+    }
+    if state.timers_frozen {
+        return;
+    }
+
+    if state.dt > 0 {
+        state.dt -= 1;
+    }
+    if state.st > 0 {
+        state.st -= 1;
+    }
+}
+
+// A single GUI step, one instruction followed by a timer tick. Stepping forces
+// past a halt or EXIT: the flags are cleared and only set again when the
+// instruction halts again. Running stops at a halt instead.
+pub fn step(state: &mut State) -> bool {
+    state.halted = false;
+    state.exited = false;
+    let result = execute(state);
+    update_timers(state);
+    result
+}
+
+// Moves PC past the instruction without executing it, for data that was run
+// as code. Clears the fault and halt the instruction caused. Past the end of
+// RAM it goes like advance_pc, returns false with the fault it sets.
+pub fn skip(state: &mut State) -> bool {
+    state.fault = None;
+    if !state.advance_pc(2) {
+        return false;
+    }
+    state.halted = false;
+    true
+}
+
+// Steps up to n instructions one at a time like Step, stopping early when the
+// program halts or PC gets to a breakpoint. A breakpoint is only checked after
+// a step, so Step N can move on from the one it's paused at. Returns the
+// number of instructions executed and false if an instruction failed.
+pub fn step_n(state: &mut State, n: usize) -> (usize, bool) {
+    for count in 0..n {
+        if !step(state) {
+            return (count, false);
+        }
+        if state.halted || state.at_breakpoint() || state.cycle_limit_reached() {
+            return (count + 1, true);
+        }
+    }
+    (n, true)
+}
+
+// Runs a CALL until it returns, anything else is a normal step. Gives up
+// after max_cycles so a subroutine that never returns can't hang the GUI.
+pub fn step_over(state: &mut State, max_cycles: usize) -> bool {
+    match fetch(state) {
+        Ok(opcode) if opcode & 0xF000 == 0x2000 => {}
+        _ => return step(state),
+    }
+
+    state.halted = false;
+    state.exited = false;
+    // A CALL at the very end of RAM has nowhere to return to
+    let return_pc = match state.pc_for(state.pc as usize + 2) {
+        Ok(pc) => pc,
+        Err(error) => {
+            state.fault = Some(error);
+            return report_fault(state, &state.current_mnemonic());
+        }
+    };
+    let sp = state.sp;
+    for cycle in 0..max_cycles {
+        if !execute(state) {
+            return false;
+        }
+        if cycle % CYCLES_PER_TICK == 0 {
+            update_timers(state);
+        }
+        if (state.pc == return_pc && state.sp == sp) || state.halted || state.cycle_limit_reached()
+        {
+            break;
+        }
+    }
+    true
+}
+
+// Called with the address and opcode of the instruction about to execute
+pub type PreExecuteHook = Box<dyn FnMut(u16, u16)>;
+// Called with the address, opcode and result once the instruction executed
+pub type PostExecuteHook = Box<dyn FnMut(u16, u16, bool)>;
+
+// Executes instructions like execute but calls the registered hooks around
+// each one, for tracing, profiling or breaking without touching the core
+pub struct Runner {
+    pre_execute: Vec<PreExecuteHook>,
+    post_execute: Vec<PostExecuteHook>,
+}
+
+impl Default for Runner {
+    fn default() -> Runner {
+        Runner::new()
+    }
+}
+
+impl Runner {
+    pub fn new() -> Runner {
+        Runner {
+            pre_execute: Vec::new(),
+            post_execute: Vec::new(),
+        }
+    }
+
+    // Hooks are called in the order they were registered
+    pub fn on_pre_execute<F: FnMut(u16, u16) + 'static>(&mut self, hook: F) {
+        self.pre_execute.push(Box::new(hook));
+    }
+
+    pub fn on_post_execute<F: FnMut(u16, u16, bool) + 'static>(&mut self, hook: F) {
+        self.post_execute.push(Box::new(hook));
+    }
+
+    // Nothing executes while waiting for the vblank, so no hook is called
+    pub fn execute(&mut self, state: &mut State) -> bool {
+        if state.waiting_for_vblank {
+            return true;
+        }
+
+        let pc = state.pc;
+        let opcode = match fetch(state) {
+            Ok(opcode) => opcode,
+            // execute reports it, there's no instruction for the hooks
+            Err(_) => return execute(state),
+        };
+        for hook in self.pre_execute.iter_mut() {
+            hook(pc, opcode);
+        }
+        let result = execute(state);
+        for hook in self.post_execute.iter_mut() {
+            hook(pc, opcode, result);
+        }
+        result
+    }
+
+    // Same as the run_cycles function with the hooks called
+    pub fn run_cycles(&mut self, state: &mut State, cycles: usize) -> bool {
+        for cycle in 0..cycles {
+            if state.halted || state.cycle_limit_reached() {
+                break;
+            }
+            if !self.execute(state) {
+                return false;
+            }
+            if cycle % CYCLES_PER_TICK == 0 {
+                update_timers(state);
+            }
+        }
+        true
+    }
+}
+
+// Runs up to `cycles` instructions without a window, ticking the timers every
+// CYCLES_PER_TICK instructions like the GUI does. Stops early when the program
+// halts or the cycle limit is reached, returns false if an instruction failed.
+pub fn run_cycles(state: &mut State, cycles: usize) -> bool {
+    Runner::new().run_cycles(state, cycles)
+}
+
+// Runs the instructions and timer ticks handed out by the Scheduler, spreading
+// the ticks evenly between the instructions. Stops executing when the program
+// halts, PC gets to a breakpoint or the cycle limit is reached, returns false
+// if an instruction failed.
+pub fn run_scheduled(state: &mut State, cycles: u32, ticks: u32) -> bool {
+    let mut ticked = 0;
+    for cycle in 0..cycles {
+        while ticked < ticks && ticked * cycles <= cycle * ticks {
+            update_timers(state);
+            ticked += 1;
+        }
+        if state.halted || state.cycle_limit_reached() {
+            break;
+        }
+        if !execute(state) {
+            return false;
+        }
+        // Checked after executing so Run can leave the breakpoint it's at
+        if state.at_breakpoint() {
+            break;
+        }
+    }
+    for _ in ticked..ticks {
+        update_timers(state);
+    }
+    true
+}
+
+// Instructions executed in one 60Hz frame at the given speed
+pub fn cycles_per_frame(ips: u32) -> u32 {
+    ips / TIMER_HZ
+}
+
+// Runs exactly one frame, a frame's worth of instructions and one timer tick
+pub fn run_frame(state: &mut State, ips: u32) -> bool {
+    run_scheduled(state, cycles_per_frame(ips), 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_cycles() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        state.max_cycles = Some(7);
+        assert_eq!(true, run_cycles(&mut state, 100));
+        assert_eq!(7, state.instruction_count);
+        assert_eq!(4, state.v[0]);
+        assert_eq!(true, state.cycle_limit_reached());
+
+        state.max_cycles = Some(10);
+        assert_eq!(false, state.cycle_limit_reached());
+        assert_eq!(true, run_scheduled(&mut state, 100, 1));
+        assert_eq!(10, state.instruction_count);
+        assert_eq!(true, state.cycle_limit_reached());
+    }
+
+    #[test]
+    fn profile() {
+        let mut state = State::new();
+
+        // LD V0, 05; ADD V0, 01; JMP 202
+        state
+            .load_rom(&[0x60, 0x05, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        assert_eq!(true, run_cycles(&mut state, 7));
+        assert_eq!(
+            vec![
+                (String::from("1nnn"), 3),
+                (String::from("7xkk"), 3),
+                (String::from("6xkk"), 1),
+            ],
+            state.profile()
+        );
+
+        state.reset();
+        assert_eq!(true, state.profile().is_empty());
+    }
+
+    #[test]
+    fn disabled_ops() {
+        let mut state = State::new();
+
+        // LD V0, 05; DRW V0, V0, 1
+        state.load_rom(&[0x60, 0x05, 0xD0, 0x01]).unwrap();
+        assert_eq!(true, state.enabled_ops.set("Dxyn", false).is_ok());
+        assert_eq!(false, state.enabled_ops.is_pattern_enabled("Dxyn"));
+        assert_eq!(true, execute(&mut state));
+        assert_eq!(false, execute(&mut state));
+        assert_eq!(Some(ExecError::Disabled(0xD001)), state.fault);
+        assert_eq!(0x202, state.pc);
+        assert_eq!(true, state.display.is_clear());
+
+        // Unknown patterns are refused
+        assert_eq!(true, state.enabled_ops.set("Dxy0", false).is_err());
+        assert_eq!(true, state.enabled_ops.set("dxyn", true).is_ok());
+        assert_eq!(true, state.enabled_ops.all_enabled());
+        assert_eq!(true, execute(&mut state));
+    }
+
+    #[test]
+    fn step_n_test() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        assert_eq!((50, true), step_n(&mut state, 50));
+        assert_eq!(50, state.instruction_count);
+        assert_eq!(25, state.v[0]);
+
+        // Stops after the JMP lands on the breakpoint, and moves on from it
+        state.breakpoints.insert(0x200);
+        assert_eq!((2, true), step_n(&mut state, 50));
+        assert_eq!(52, state.instruction_count);
+        assert_eq!(0x200, state.pc);
+        assert_eq!((2, true), step_n(&mut state, 50));
+        assert_eq!(54, state.instruction_count);
+    }
+
+    #[test]
+    fn run_to_breakpoint() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        state.toggle_breakpoint(0x202);
+        assert_eq!(true, run_scheduled(&mut state, 100, 1));
+        assert_eq!(1, state.instruction_count);
+        assert_eq!(true, run_scheduled(&mut state, 100, 1));
+        assert_eq!(3, state.instruction_count);
+        state.toggle_breakpoint(0x202);
+        assert_eq!(true, run_scheduled(&mut state, 100, 1));
+        assert_eq!(103, state.instruction_count);
+    }
+
+    #[test]
+    fn enforce_alignment() {
+        let mut state = State::new();
+
+        // LD V0, 01; JP V0, 300
+        state.load_rom(&[0x60, 0x01, 0xB3, 0x00]).unwrap();
+        assert_eq!(true, run_cycles(&mut state, 2));
+        assert_eq!(0x301, state.pc);
+
+        state.reload(&[0x60, 0x01, 0xB3, 0x00]).unwrap();
+        state.enforce_alignment = true;
+        assert_eq!(false, run_cycles(&mut state, 2));
+        assert_eq!(Some(ExecError::MisalignedPc(0x301)), state.fault);
+        assert_eq!(2, state.instruction_count);
+    }
+
+    #[test]
+    fn run_cycles_test() {
+        let mut state = State::new();
+
+        // LD V0, 01; ADD V0, 01; JMP 204
+        state
+            .load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x04])
+            .unwrap();
+        assert_eq!(true, run_cycles(&mut state, 100));
+        assert_eq!(2, state.v[0]);
+        assert_eq!(true, state.halted);
+    }
+
+    #[test]
+    fn runner_hooks() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut state = State::new();
+        let executed = Rc::new(RefCell::new(Vec::new()));
+        let results = Rc::new(RefCell::new(Vec::new()));
+        let mut runner = Runner::new();
+        let log = executed.clone();
+        runner.on_pre_execute(move |pc, opcode| log.borrow_mut().push((pc, opcode)));
+        let log = results.clone();
+        runner.on_post_execute(move |_, _, result| log.borrow_mut().push(result));
+
+        // LD V0, 01; ADD V0, 01; JMP 204
+        state
+            .load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x04])
+            .unwrap();
+        assert_eq!(true, runner.run_cycles(&mut state, 100));
+        assert_eq!(
+            vec![(0x200, 0x6001), (0x202, 0x7001), (0x204, 0x1204)],
+            *executed.borrow()
+        );
+        assert_eq!(vec![true, true, true], *results.borrow());
+        assert_eq!(state.instruction_count, executed.borrow().len() as u64);
+
+        // Once per instruction, also when stepping one at a time
+        state
+            .load_rom(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x04])
+            .unwrap();
+        state.waiting_for_vblank = true;
+        assert_eq!(true, runner.execute(&mut state));
+        assert_eq!(3, executed.borrow().len());
+        state.waiting_for_vblank = false;
+        assert_eq!(true, runner.execute(&mut state));
+        assert_eq!(4, executed.borrow().len());
+        assert_eq!(4, results.borrow().len());
+    }
+
+    #[test]
+    fn step_while_halted() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 202
+        state.load_rom(&[0x70, 0x01, 0x12, 0x02]).unwrap();
+        state.halted = true;
+        assert_eq!(true, run_cycles(&mut state, 10));
+        assert_eq!(0, state.v[0]);
+
+        assert_eq!(true, step(&mut state));
+        assert_eq!(1, state.v[0]);
+        assert_eq!(false, state.halted);
+        assert_eq!(true, step(&mut state));
+        assert_eq!(0x202, state.pc);
+        assert_eq!(true, state.halted);
+    }
+
+    #[test]
+    fn skip_unknown_opcode() {
+        let mut state = State::new();
+
+        // Unknown opcode; ADD V0, 01
+        state.load_rom(&[0xE1, 0x00, 0x70, 0x01]).unwrap();
+        assert_eq!(false, run_scheduled(&mut state, 10, 0));
+        assert_eq!(0x200, state.pc);
+        assert_eq!(Some(ExecError::IllegalOpcode(0xE100)), state.fault);
+
+        assert_eq!(true, skip(&mut state));
+        assert_eq!(0x202, state.pc);
+        assert_eq!(None, state.fault);
+        assert_eq!(true, step(&mut state));
+        assert_eq!(1, state.v[0]);
+
+        // Nothing to skip to past the end of RAM
+        state.pc = 0xFFE;
+        assert_eq!(false, skip(&mut state));
+        assert_eq!(Some(ExecError::PcOutOfRange(0x1000)), state.fault);
+        assert_eq!(0xFFE, state.pc);
+    }
+
+    #[test]
+    fn step_over_test() {
+        let mut state = State::new();
+
+        // CALL 206; LD V1, 01; JMP 204; ADD V0, 01; ADD V0, 01; RET
+        let rom = [
+            0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x70, 0x01, 0x70, 0x01, 0x00, 0xEE,
+        ];
+        state.load_rom(&rom).unwrap();
+        assert_eq!(true, step_over(&mut state, 100));
+        assert_eq!(0x202, state.pc);
+        assert_eq!(2, state.v[0]);
+        assert_eq!(true, step_over(&mut state, 100));
+        assert_eq!(0x204, state.pc);
+        assert_eq!(1, state.v[1]);
+        assert_eq!(5, state.instruction_count);
+
+        // CALL 206 in the last word of 64KB, the return address is past the end
+        let mut state = State::with_ram_size(0x10000);
+        state.pc = 0xFFFE;
+        state.ram[0xFFFE] = 0x22;
+        state.ram[0xFFFF] = 0x06;
+        assert_eq!(false, step_over(&mut state, 100));
+        assert_eq!(Some(ExecError::PcOutOfRange(0x10000)), state.fault);
+        assert_eq!(0xFFFE, state.pc);
+    }
+
+    #[test]
+    fn trace_test() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        run_cycles(&mut state, TRACE_LEN + 3);
+        assert_eq!(TRACE_LEN, state.trace.len());
+        assert_eq!(Some(&0x200), state.trace.back());
+        assert_eq!(Some(&0x202), state.trace.front());
+    }
+
+    #[test]
+    fn scheduler_test() {
+        let mut scheduler = Scheduler::new(600);
+
+        assert_eq!((30, 3), scheduler.advance(Duration::from_millis(50)));
+        // Fractions are carried over, 0.6 + 0.6 cycles make one
+        assert_eq!((0, 0), scheduler.advance(Duration::from_millis(1)));
+        assert_eq!((1, 0), scheduler.advance(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn scheduler_catch_up_test() {
+        let mut scheduler = Scheduler::new(DEFAULT_IPS);
+
+        // A long stall only catches up MAX_CATCH_UP worth of work
+        assert_eq!((54, 6), scheduler.advance(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn repeat_timer_test() {
+        let mut repeat = RepeatTimer::new(Duration::from_millis(50));
+        let frame = Duration::from_millis(30);
+
+        assert_eq!(0, repeat.update(false, frame));
+        assert_eq!(1, repeat.update(true, frame));
+        assert_eq!(0, repeat.update(true, frame));
+        assert_eq!(1, repeat.update(true, frame));
+        assert_eq!(0, repeat.update(true, frame));
+        assert_eq!(1, repeat.update(true, frame));
+        // Releasing stops the repeat, the next press steps straight away
+        assert_eq!(0, repeat.update(false, frame));
+        assert_eq!(0, repeat.update(false, frame));
+        assert_eq!(1, repeat.update(true, frame));
+        assert_eq!(0, repeat.update(true, frame));
+    }
+
+    #[test]
+    fn run_scheduled_test() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        state.dt = 10;
+        assert_eq!(true, run_scheduled(&mut state, 10, 3));
+        assert_eq!(5, state.v[0]);
+        assert_eq!(7, state.dt);
+    }
+
+    #[test]
+    fn run_frame_test() {
+        let mut state = State::new();
+
+        // ADD V0, 01; JMP 200
+        state.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        state.dt = 10;
+        assert_eq!(true, run_frame(&mut state, DEFAULT_IPS));
+        assert_eq!(CYCLES_PER_TICK as u64, state.instruction_count);
+        assert_eq!(9, state.dt);
+        assert_eq!(true, run_frame(&mut state, 600));
+        assert_eq!(CYCLES_PER_TICK as u64 + 10, state.instruction_count);
+    }
+
+    #[test]
+    fn update_timers_test() {
+        let mut state = State::new();
+
+        state.dt = 2;
+        state.st = 1;
+        update_timers(&mut state);
+        assert_eq!(1, state.dt);
+        assert_eq!(0, state.st);
+        update_timers(&mut state);
+        update_timers(&mut state);
+        assert_eq!(0, state.dt);
+    }
+
+    #[test]
+    fn timers_frozen_test() {
+        let mut state = State::new();
+
+        state.dt = 5;
+        state.st = 5;
+        state.timers_frozen = true;
+        state.waiting_for_vblank = true;
+        update_timers(&mut state);
+        update_timers(&mut state);
+        assert_eq!(5, state.dt);
+        assert_eq!(5, state.st);
+        assert_eq!(false, state.waiting_for_vblank);
+
+        state.timers_frozen = false;
+        update_timers(&mut state);
+        assert_eq!(4, state.dt);
+    }
+}
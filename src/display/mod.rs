@@ -1,14 +1,134 @@
 use std::fmt;
+use std::mem;
+
+// RGBA colors for each of the 4 possible pixel values
+pub type Palette = [[f32; 4]; 4];
+
+pub const DEFAULT_PALETTE: Palette = [
+    [0.0, 0.0, 0.0, 0.0],
+    [1.0, 1.0, 1.0, 1.0],
+    [0.67, 0.27, 0.0, 1.0],
+    [1.0, 0.67, 0.0, 1.0],
+];
+
+// Outcome of drawing a sprite
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct DrawResult {
+    pub collided: bool,   // Some pixel was reset
+    pub rows_clipped: u8, // Sprite rows cut off at the bottom edge when not wrapping Y
+}
+
+// Pixel reset by a sprite, recorded when collision logging is on
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Collision {
+    pub sprite_x: u8,
+    pub sprite_y: u8,
+    pub x: u8,
+    pub y: u8,
+    pub plane: u8,
+}
+
+// Screen area a sprite was drawn over, recorded when draw logging is on. X and
+// Y are the on screen origin, the rest may be off the edges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DrawRect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+// Frame hash compared with the one expected by --verify
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Verification {
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.expected == self.actual
+    }
+
+    pub fn message(&self) -> String {
+        if self.passed() {
+            format!("Frame hash matches: {:016x}", self.actual)
+        } else {
+            format!(
+                "Frame hash mismatch: expected {:016x}, got {:016x}",
+                self.expected, self.actual
+            )
+        }
+    }
+}
+
+// Copy of the shown pixels, pinned in the GUI to compare later frames with
+#[derive(Clone, Debug, PartialEq)]
+pub struct DisplaySnapshot {
+    pub data: [[u8; 64]; 32],
+}
+
+// How a pixel changed since the snapshot
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PixelDiff {
+    Same,
+    Lit,     // Off in the snapshot, on now
+    Cleared, // On in the snapshot, off now
+    Changed, // On in both but in other planes
+}
+
+impl PixelDiff {
+    pub fn classify(pinned: u8, current: u8) -> PixelDiff {
+        match (pinned, current) {
+            (pinned, current) if pinned == current => PixelDiff::Same,
+            (0, _) => PixelDiff::Lit,
+            (_, 0) => PixelDiff::Cleared,
+            _ => PixelDiff::Changed,
+        }
+    }
+}
+
+// Packs a palette color into RGB565, alpha is dropped
+pub fn rgb565(color: [f32; 4]) -> u16 {
+    let channel = |value: f32, max: f32| (value.max(0.0).min(1.0) * max).round() as u16;
+    channel(color[0], 31.0) << 11 | channel(color[1], 63.0) << 5 | channel(color[2], 31.0)
+}
+
+// Collisions kept until someone drains them, later ones are dropped
+const MAX_COLLISIONS: usize = 4096;
+// Sprites logged per frame, a ROM drawing more than this is hard to read anyway
+const MAX_DRAWS: usize = 1024;
 
 pub struct Display {
-    pub data: [[u8; 64]; 32], // 64 x 32 pixels, 1b == 1pix
+    // 64 x 32 pixels, bit 0 is the first plane and bit 1 the second (XO-CHIP)
+    // Everything draws here, with double buffering this is the back buffer
+    pub data: [[u8; 64]; 32],
+    front: [[u8; 64]; 32],
+    pub double_buffered: bool, // Renderers see data only after present()
+    pub present_interval: u32, // vblank() presents every this many ticks
+    vblanks_since_present: u32,
+    pub plane_mask: u8, // Planes drawn into, only the first one by default
+    pub dirty: bool,    // Pixels changed since the renderer last cleared it
+    pub collision_logging: bool,
+    pub collisions: Vec<Collision>,
+    pub draw_logging: bool,
+    pub draws: Vec<DrawRect>, // Sprites drawn since the last present(), in order
+    pub shown_draws: Vec<DrawRect>, // The ones drawn before the last present()
 }
 
+// Lit pixels are printed as blocks, {:#?} prints the raw pixel values instead
 impl fmt::Debug for Display {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let raw = f.alternate();
         for row in self.data.iter() {
             for pixel in row.iter() {
-                let _ = write!(f, "{}", pixel);
+                let _ = if raw {
+                    write!(f, "{}", pixel)
+                } else if *pixel != 0 {
+                    write!(f, "█")
+                } else {
+                    write!(f, " ")
+                };
             }
             let _ = write!(f, "\n");
         }
@@ -20,35 +140,307 @@ impl Display {
     pub fn new() -> Display {
         Display {
             data: [[0u8; 64]; 32],
+            front: [[0u8; 64]; 32],
+            double_buffered: false,
+            present_interval: 1,
+            vblanks_since_present: 0,
+            plane_mask: 1,
+            dirty: true,
+            collision_logging: false,
+            collisions: Vec::new(),
+            draw_logging: false,
+            draws: Vec::new(),
+            shown_draws: Vec::new(),
+        }
+    }
+
+    // Pixels to show. The back buffer as of the last present() with double
+    // buffering, otherwise the live pixels.
+    pub fn front(&self) -> &[[u8; 64]; 32] {
+        if self.double_buffered {
+            &self.front
+        } else {
+            &self.data
+        }
+    }
+
+    // Makes everything drawn so far visible. The draw log starts over, the
+    // finished frame's draws are kept for the overlay.
+    pub fn present(&mut self) {
+        self.vblanks_since_present = 0;
+        if self.double_buffered && self.front != self.data {
+            self.front = self.data;
+            self.dirty = true;
         }
+        if self.draw_logging {
+            self.shown_draws = mem::replace(&mut self.draws, Vec::new());
+        }
+    }
+
+    // The 60Hz tick, presents every present_interval-th one. A sprite erased
+    // and drawn again in between never shows up half done, which hides the
+    // flicker of ROMs that redraw over several ticks. Only has an effect with
+    // double buffering, single buffered the renderers see every draw.
+    pub fn vblank(&mut self) {
+        self.vblanks_since_present += 1;
+        if self.vblanks_since_present >= self.present_interval.max(1) {
+            self.present();
+        }
+    }
+
+    pub fn clear_draw_log(&mut self) {
+        self.draws.clear();
+        self.shown_draws.clear();
+    }
+
+    // Selects the planes that sprites are drawn into and CLS clears
+    pub fn select_plane(&mut self, mask: u8) {
+        self.plane_mask = mask & 0b11;
+    }
+
+    // Number of sprite bytes a sprite of given height takes with the selected planes
+    pub fn sprite_len(&self, height: usize) -> usize {
+        height * self.plane_mask.count_ones() as usize
     }
 
-    // Wraps sprites if x and y are bigger than 63 or 31
+    // Draws with wrapping, x and y past 63 or 31 wrap around too
     // Returns true if there was a collision (some pixel was reset)
     pub fn display_sprite(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
-        let mut collision: bool = false;
-        for (row, byte) in sprite.iter().enumerate() {
-            let y = ((y + row as u8) % 32) as usize;
-            for i in 0..7 {
-                let x = ((x + i) % 64) as usize;
-                self.data[y][x] ^= if (0b10000000 >> i & byte) > 0 {
-                    1
-                } else {
-                    collision = true;
-                    0
-                };
+        self.draw_sprite(x, y, sprite, true)
+    }
+
+    // Draws into the selected planes. The origin always wraps onto the screen,
+    // wrap only decides what happens to the pixels past the edges.
+    // Returns true if there was a collision (some pixel was reset)
+    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8], wrap: bool) -> bool {
+        self.draw(x % 64, y % 32, sprite, self.plane_mask, wrap, wrap)
+            .collided
+    }
+
+    // With wrap_x and wrap_y set pixels past the left/right and top/bottom
+    // edges wrap around, otherwise they are clipped. X and y have to be on the
+    // screen already, draw_sprite and Dxyn wrap them there first.
+    // With both planes in the mask the sprite holds the first plane's rows
+    // followed by the second plane's rows
+    pub fn draw(
+        &mut self,
+        x: u8,
+        y: u8,
+        sprite: &[u8],
+        plane_mask: u8,
+        wrap_x: bool,
+        wrap_y: bool,
+    ) -> DrawResult {
+        debug_assert!(x < 64 && y < 32, "sprite origin off the screen");
+        let (sprite_x, sprite_y) = (x, y);
+        let (x, y) = (x as usize, y as usize);
+        let mut result = DrawResult::default();
+        let plane_mask = plane_mask & 0b11;
+        let planes = plane_mask.count_ones() as usize;
+        if planes == 0 {
+            return result;
+        }
+
+        self.dirty = true;
+        let height = sprite.len() / planes;
+        if self.draw_logging && self.draws.len() < MAX_DRAWS {
+            self.draws.push(DrawRect {
+                x: x as u8,
+                y: y as u8,
+                width: 8,
+                height: height as u8,
+            });
+        }
+        if !wrap_y {
+            result.rows_clipped = (y + height).saturating_sub(32) as u8;
+        }
+        let mut rows = sprite.chunks(height.max(1));
+        for plane in [1u8, 2u8].iter().filter(|plane| plane_mask & **plane > 0) {
+            let plane_sprite = rows.next().unwrap_or(&[]);
+            for (row, byte) in plane_sprite.iter().enumerate() {
+                if !wrap_y && y + row >= 32 {
+                    break;
+                }
+                let y = (y + row) % 32;
+                for i in 0..8 {
+                    if (0b10000000 >> i & byte) == 0 || (!wrap_x && x + i >= 64) {
+                        continue;
+                    }
+                    let x = (x + i) % 64;
+                    if self.data[y][x] & plane > 0 {
+                        result.collided = true;
+                        if self.collision_logging && self.collisions.len() < MAX_COLLISIONS {
+                            self.collisions.push(Collision {
+                                sprite_x: sprite_x,
+                                sprite_y: sprite_y,
+                                x: x as u8,
+                                y: y as u8,
+                                plane: *plane,
+                            });
+                        }
+                    }
+                    self.data[y][x] ^= plane;
+                }
             }
         }
-        collision
+        result
     }
 
-    // Resets the display to all 0
-    pub fn reset(&mut self) {
+    // Clears only the selected planes
+    pub fn clear_planes(&mut self) {
+        let mask = !self.plane_mask;
+        for row in self.data.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= mask;
+            }
+        }
+        self.dirty = true;
+    }
+
+    // Flips every pixel of the selected planes, for debugging the renderer
+    pub fn invert(&mut self) {
+        let mask = self.plane_mask;
+        for row in self.data.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel ^= mask;
+            }
+        }
+        self.dirty = true;
+    }
+
+    // Sets every pixel to the value, ignoring the selected planes
+    pub fn fill(&mut self, value: u8) {
         for row in self.data.iter_mut() {
             for pixel in row.iter_mut() {
-                *pixel = 0;
+                *pixel = value;
+            }
+        }
+        self.dirty = true;
+    }
+
+    // Resets the display to all 0
+    pub fn reset(&mut self) {
+        self.fill(0);
+    }
+
+    // Number of pixels lit in any plane
+    pub fn count_lit_pixels(&self) -> usize {
+        self.data
+            .iter()
+            .map(|row| row.iter().filter(|pixel| **pixel != 0).count())
+            .sum()
+    }
+
+    // Pixels of a w x h rectangle row by row. Coordinates past the edges wrap
+    // around like the pixels of a wrapped sprite.
+    pub fn get_region(&self, x: usize, y: usize, w: usize, h: usize) -> Vec<u8> {
+        let mut region = Vec::with_capacity(w * h);
+        for row in y..(y + h) {
+            for column in x..(x + w) {
+                region.push(self.data[row % 32][column % 64]);
+            }
+        }
+        region
+    }
+
+    // Prints each 2x4 pixel block as one braille character, the whole screen
+    // fits in 32x8 characters. Any lit plane counts as a lit pixel.
+    pub fn render_braille(&self) -> String {
+        // Dot bits of the braille pattern by [row][column] within the block
+        const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+        let mut text = String::with_capacity(32 * 8 * 3 + 8);
+        for y in (0..32).step_by(4) {
+            for x in (0..64).step_by(2) {
+                let mut dots = 0;
+                for (row, bits) in DOTS.iter().enumerate() {
+                    for (column, bit) in bits.iter().enumerate() {
+                        if self.data[y + row][x + column] != 0 {
+                            dots |= bit;
+                        }
+                    }
+                }
+                text.push(std::char::from_u32(0x2800 + dots).unwrap_or(' '));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Packs the display row by row, 8 pixels per byte with the leftmost pixel
+    // in the highest bit. Any lit plane counts as a lit pixel.
+    pub fn to_monochrome_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(64 * 32 / 8);
+        for row in self.data.iter() {
+            for chunk in row.chunks(8) {
+                let mut byte = 0u8;
+                for (i, pixel) in chunk.iter().enumerate() {
+                    if *pixel != 0 {
+                        byte |= 0b10000000 >> i;
+                    }
+                }
+                bytes.push(byte);
+            }
+        }
+        bytes
+    }
+
+    // Native resolution RGB565 framebuffer for small embedded displays, row by
+    // row. Any lit plane is drawn in fg.
+    pub fn to_rgb565(&self, fg: u16, bg: u16) -> Vec<u16> {
+        let mut pixels = Vec::with_capacity(64 * 32);
+        for row in self.data.iter() {
+            for pixel in row.iter() {
+                pixels.push(if *pixel != 0 { fg } else { bg });
+            }
+        }
+        pixels
+    }
+
+    // FNV-1a over the pixel values, stable across runs and platforms
+    pub fn frame_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for row in self.data.iter() {
+            for pixel in row.iter() {
+                hash ^= *pixel as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    // Compares the frame with the hash expected by --verify
+    pub fn verify(&self, expected: u64) -> Verification {
+        Verification {
+            expected: expected,
+            actual: self.frame_hash(),
+        }
+    }
+
+    pub fn snapshot(&self) -> DisplaySnapshot {
+        DisplaySnapshot {
+            data: *self.front(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &DisplaySnapshot) {
+        self.data = snapshot.data;
+        self.dirty = true;
+        self.present();
+    }
+
+    // Pixels shown now that differ from the snapshot, as (x, y, how)
+    pub fn diff(&self, snapshot: &DisplaySnapshot) -> Vec<(usize, usize, PixelDiff)> {
+        let mut diff = Vec::new();
+        for (y, (row, pinned_row)) in self.front().iter().zip(snapshot.data.iter()).enumerate() {
+            for (x, (pixel, pinned)) in row.iter().zip(pinned_row.iter()).enumerate() {
+                match PixelDiff::classify(*pinned, *pixel) {
+                    PixelDiff::Same => (),
+                    change => diff.push((x, y, change)),
+                }
             }
         }
+        diff
     }
 
     // Check if the display is clean, mostly used in tests
@@ -64,6 +456,43 @@ impl Display {
     }
 }
 
+// Compares the top left corner of the display, as big as the expected grid,
+// against rows of # for lit and . for unlit pixels. Blank lines and
+// indentation are ignored. Panics with both grids side by side on a mismatch.
+#[cfg(test)]
+pub fn assert_display_eq(actual: &Display, expected_ascii: &str) {
+    let expected: Vec<&str> = expected_ascii
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect();
+    assert!(
+        expected.len() <= 32,
+        "Expected grid is taller than the display"
+    );
+
+    let mut mismatch = false;
+    let mut diff = String::new();
+    for (y, line) in expected.iter().enumerate() {
+        assert!(line.len() <= 64, "Expected grid is wider than the display");
+        let actual_line: String = (0..line.len())
+            .map(|x| if actual.data[y][x] != 0 { '#' } else { '.' })
+            .collect();
+        let marker = if actual_line != *line {
+            mismatch = true;
+            " <"
+        } else {
+            ""
+        };
+        diff += &format!("{}  {}{}\n", line, actual_line, marker);
+    }
+    assert!(
+        !mismatch,
+        "Display doesn't match, expected vs actual:\n{}",
+        diff
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,6 +508,74 @@ mod tests {
         display.display_sprite(64, 32, &sprite);
 
         assert_eq!(false, display.is_clear());
+        // The third sprite wraps onto the first one and erases it
+        assert_display_eq(
+            &display,
+            "
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..............
+            ..........####
+            ..........#..#
+            ..........#..#
+            ..........#..#
+            ..........####
+            ..............
+            ",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Display doesn't match")]
+    fn assert_display_eq_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(1, 0, &[0x80]);
+        assert_display_eq(&display, "#.");
+    }
+
+    #[test]
+    fn wrap_at_right_edge() {
+        let mut display: Display = Display::new();
+
+        // 0xB5 = #.##.#.#, the first column stays at 63 and the rest wrap to 0-6
+        display.display_sprite(63, 4, &[0xB5]);
+        assert_eq!(1, display.data[4][63]);
+        assert_eq!([0, 1, 1, 0, 1, 0, 1], display.data[4][0..7]);
+        assert_eq!(5, display.count_lit_pixels());
+        assert_eq!(true, display.data[3].iter().all(|pixel| *pixel == 0));
+        assert_eq!(true, display.data[5].iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn wrap_at_bottom_edge() {
+        let mut display: Display = Display::new();
+
+        // The first row stays at 31 and the rest wrap to 0 and 1
+        display.display_sprite(10, 31, &[0x80, 0x40, 0x20]);
+        assert_eq!(1, display.data[31][10]);
+        assert_eq!(1, display.data[0][11]);
+        assert_eq!(1, display.data[1][12]);
+        assert_eq!(3, display.count_lit_pixels());
+    }
+
+    #[test]
+    fn wrap_start_position() {
+        let mut display: Display = Display::new();
+
+        // (64, 32) is the top left corner, (127, 63) the bottom right one
+        display.display_sprite(64, 32, &[0x80]);
+        assert_eq!(1, display.data[0][0]);
+        display.display_sprite(127, 63, &[0x80]);
+        assert_eq!(1, display.data[31][63]);
+        assert_eq!(2, display.count_lit_pixels());
     }
 
     #[test]
@@ -94,4 +591,408 @@ mod tests {
 
         assert_eq!(true, display.is_clear());
     }
+
+    #[test]
+    fn debug_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(1, 0, &[0xA0]);
+        let blocks = format!("{:?}", display);
+        let raw = format!("{:#?}", display);
+        assert_eq!(
+            format!(" █ █{}", " ".repeat(60)),
+            blocks.lines().next().unwrap()
+        );
+        assert_eq!(" ".repeat(64), blocks.lines().nth(1).unwrap());
+        assert_eq!(32, blocks.lines().count());
+        assert!(raw.starts_with("01010000"));
+    }
+
+    #[test]
+    fn render_braille() {
+        let mut display: Display = Display::new();
+
+        // Left column and the bottom right pixel of the first block
+        display.display_sprite(0, 0, &[0x80, 0x80, 0x80, 0xC0]);
+        let braille = display.render_braille();
+        assert_eq!(8, braille.lines().count());
+        assert_eq!(
+            format!("\u{28C7}{}", "\u{2800}".repeat(31)),
+            braille.lines().next().unwrap()
+        );
+        assert_eq!("\u{2800}".repeat(32), braille.lines().nth(1).unwrap());
+
+        display.fill(1);
+        assert!(display
+            .render_braille()
+            .lines()
+            .all(|line| line == "\u{28FF}".repeat(32)));
+    }
+
+    #[test]
+    fn to_monochrome_bytes_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(4, 1, &[0xFF]);
+        let bytes = display.to_monochrome_bytes();
+        assert_eq!(256, bytes.len());
+        assert_eq!([0x0F, 0xF0], bytes[8..10]);
+        assert_eq!(2, bytes.iter().filter(|byte| **byte != 0).count());
+    }
+
+    #[test]
+    fn to_rgb565_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(62, 1, &[0xC0]);
+        let pixels = display.to_rgb565(0xFFFF, 0x0841);
+        assert_eq!(64 * 32, pixels.len());
+        assert_eq!([0x0841, 0xFFFF, 0xFFFF], pixels[64 + 61..64 + 64]);
+        assert_eq!(2, pixels.iter().filter(|pixel| **pixel == 0xFFFF).count());
+
+        assert_eq!(0xFFFF, rgb565([1.0, 1.0, 1.0, 1.0]));
+        assert_eq!(0xF800, rgb565([1.0, 0.0, 0.0, 0.0]));
+        assert_eq!(0x0000, rgb565(DEFAULT_PALETTE[0]));
+    }
+
+    #[test]
+    fn frame_hash_test() {
+        let mut first: Display = Display::new();
+        let mut second: Display = Display::new();
+        let sprite = [0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+        first.display_sprite(3, 4, &sprite);
+        second.display_sprite(3, 4, &sprite);
+        assert_eq!(first.frame_hash(), second.frame_hash());
+
+        second.data[31][63] = 1;
+        assert_ne!(first.frame_hash(), second.frame_hash());
+        assert_ne!(Display::new().frame_hash(), first.frame_hash());
+    }
+
+    #[test]
+    fn verify_test() {
+        let mut display: Display = Display::new();
+        display.display_sprite(3, 4, &[0xF0]);
+        let hash = display.frame_hash();
+
+        let verification = display.verify(hash);
+        assert_eq!(true, verification.passed());
+        let message = verification.message();
+        assert_eq!(true, message.contains(&format!("{:016x}", hash)));
+        let verification = display.verify(hash ^ 1);
+        assert_eq!(false, verification.passed());
+        let message = verification.message();
+        assert_eq!(true, message.contains(&format!("{:016x}", hash)));
+        assert_eq!(true, message.contains(&format!("{:016x}", hash ^ 1)));
+    }
+
+    #[test]
+    fn collision_test() {
+        let mut display: Display = Display::new();
+        let sprite = [0xF0, 0x90, 0x90, 0x90, 0xF0];
+
+        assert_eq!(false, display.display_sprite(0, 0, &sprite));
+        assert_eq!(false, display.display_sprite(4, 0, &sprite));
+        assert_eq!(true, display.display_sprite(0, 0, &sprite));
+    }
+
+    #[test]
+    fn collision_logging_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(0, 0, &[0xC0]);
+        display.display_sprite(1, 0, &[0xC0]);
+        assert_eq!(0, display.collisions.len());
+
+        display.collision_logging = true;
+        display.display_sprite(1, 0, &[0xC0, 0x80]);
+        let collision = Collision {
+            sprite_x: 1,
+            sprite_y: 0,
+            x: 2,
+            y: 0,
+            plane: 1,
+        };
+        assert_eq!(vec![collision], display.collisions);
+    }
+
+    #[test]
+    fn draw_log_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(0, 0, &[0xC0]);
+        assert_eq!(0, display.draws.len());
+
+        display.draw_logging = true;
+        display.display_sprite(2, 3, &[0xC0, 0x80]);
+        display.select_plane(3);
+        display.draw_sprite(8, 8, &[0xFF, 0xFF, 0xFF, 0xFF], false);
+        let first = DrawRect {
+            x: 2,
+            y: 3,
+            width: 8,
+            height: 2,
+        };
+        let second = DrawRect {
+            x: 8,
+            y: 8,
+            width: 8,
+            height: 2,
+        };
+        assert_eq!(vec![first, second], display.draws);
+
+        // Present keeps them for the overlay and starts the next frame's log
+        display.present();
+        assert_eq!(true, display.draws.is_empty());
+        assert_eq!(vec![first, second], display.shown_draws);
+        display.present();
+        assert_eq!(true, display.shown_draws.is_empty());
+    }
+
+    #[test]
+    fn clip_test() {
+        let mut display: Display = Display::new();
+
+        display.draw_sprite(62, 31, &[0xFF, 0xFF], false);
+        assert_eq!([1, 1], display.data[31][62..64]);
+        assert_eq!(0, display.data[31][0]);
+        assert_eq!(0, display.data[0][62]);
+
+        display.reset();
+        display.draw_sprite(62, 31, &[0xFF, 0xFF], true);
+        assert_eq!(1, display.data[31][0]);
+        assert_eq!(1, display.data[0][62]);
+
+        // The origin wraps even when the pixels are clipped
+        display.reset();
+        display.draw_sprite(70, 33, &[0x80], false);
+        assert_eq!(1, display.data[1][6]);
+    }
+
+    #[test]
+    fn get_region() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(10, 5, &[0xF0, 0x90, 0xF0]);
+        assert_eq!(
+            vec![1, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1],
+            display.get_region(10, 5, 4, 3)
+        );
+        assert_eq!(vec![0, 1, 0, 1], display.get_region(9, 6, 2, 2));
+
+        display.display_sprite(63, 31, &[0xC0, 0x80]);
+        assert_eq!(vec![1, 1, 1, 0], display.get_region(63, 31, 2, 2));
+        assert_eq!(Vec::<u8>::new(), display.get_region(0, 0, 0, 4));
+    }
+
+    #[test]
+    fn present_test() {
+        let mut display: Display = Display::new();
+
+        // Single buffered the front is the live buffer
+        display.display_sprite(0, 0, &[0x80]);
+        assert_eq!(1, display.front()[0][0]);
+
+        display.double_buffered = true;
+        assert_eq!(0, display.front()[0][0]);
+        display.present();
+        assert_eq!(1, display.front()[0][0]);
+
+        display.display_sprite(1, 0, &[0x80]);
+        assert_eq!(1, display.data[0][1]);
+        assert_eq!(0, display.front()[0][1]);
+        display.dirty = false;
+        display.present();
+        assert_eq!(true, display.dirty);
+        assert_eq!(1, display.front()[0][1]);
+    }
+
+    #[test]
+    fn present_cadence_test() {
+        let mut display: Display = Display::new();
+        display.double_buffered = true;
+
+        // Every tick by default
+        display.display_sprite(0, 0, &[0x80]);
+        display.vblank();
+        assert_eq!(1, display.front()[0][0]);
+
+        // Every third tick, an erase and redraw in between isn't seen
+        display.present_interval = 3;
+        display.display_sprite(0, 0, &[0x80]);
+        display.vblank();
+        display.vblank();
+        assert_eq!(1, display.front()[0][0]);
+        display.display_sprite(0, 0, &[0x80]);
+        display.display_sprite(1, 0, &[0x80]);
+        display.vblank();
+        assert_eq!([1, 1], display.front()[0][0..2]);
+
+        // Presenting directly starts the count over
+        display.display_sprite(2, 0, &[0x80]);
+        display.vblank();
+        display.present();
+        display.vblank();
+        display.vblank();
+        assert_eq!(1, display.front()[0][2]);
+        display.display_sprite(2, 0, &[0x80]);
+        display.vblank();
+        assert_eq!(0, display.front()[0][2]);
+    }
+
+    #[test]
+    fn fill_test() {
+        let mut display: Display = Display::new();
+
+        assert_eq!(0, display.count_lit_pixels());
+        display.dirty = false;
+        display.fill(1);
+        assert_eq!(true, display.dirty);
+        assert_eq!(false, display.is_clear());
+        assert_eq!(64 * 32, display.count_lit_pixels());
+        display.reset();
+        assert_eq!(true, display.is_clear());
+    }
+
+    #[test]
+    fn pixel_diff() {
+        assert_eq!(PixelDiff::Same, PixelDiff::classify(0, 0));
+        assert_eq!(PixelDiff::Same, PixelDiff::classify(3, 3));
+        assert_eq!(PixelDiff::Lit, PixelDiff::classify(0, 1));
+        assert_eq!(PixelDiff::Cleared, PixelDiff::classify(2, 0));
+        assert_eq!(PixelDiff::Changed, PixelDiff::classify(1, 3));
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let mut display: Display = Display::new();
+        display.display_sprite(0, 0, &[0xC0]);
+        let pinned = display.snapshot();
+        assert_eq!(0, display.diff(&pinned).len());
+
+        // Moved one pixel to the right
+        display.reset();
+        display.display_sprite(1, 0, &[0xC0]);
+        assert_eq!(
+            vec![(0, 0, PixelDiff::Cleared), (2, 0, PixelDiff::Lit)],
+            display.diff(&pinned)
+        );
+
+        display.restore(&pinned);
+        assert_eq!(0, display.diff(&pinned).len());
+        assert_eq!(true, display.dirty);
+    }
+
+    #[test]
+    fn invert_test() {
+        let mut display: Display = Display::new();
+
+        display.dirty = false;
+        display.invert();
+        assert_eq!(true, display.dirty);
+        assert_eq!(
+            true,
+            display.data.iter().all(|row| row.iter().all(|p| *p == 1))
+        );
+
+        display.display_sprite(0, 0, &[0xF0]);
+        let hash = display.frame_hash();
+        display.invert();
+        display.invert();
+        assert_eq!(hash, display.frame_hash());
+    }
+
+    #[test]
+    fn wrap_per_axis() {
+        let mut display: Display = Display::new();
+        let sprite = [0xF0, 0xF0];
+
+        // The sprite covers x 62 to 65 and y 31 to 32
+        display.draw(62, 31, &sprite, 1, true, true);
+        assert_eq!([1, 1], display.data[31][62..64]);
+        assert_eq!([1, 1], display.data[31][0..2]);
+        assert_eq!([1, 1], display.data[0][62..64]);
+        assert_eq!([1, 1], display.data[0][0..2]);
+        assert_eq!(8, display.count_lit_pixels());
+
+        display.reset();
+        let result = display.draw(62, 31, &sprite, 1, true, false);
+        assert_eq!(1, result.rows_clipped);
+        assert_eq!([1, 1], display.data[31][62..64]);
+        assert_eq!([1, 1], display.data[31][0..2]);
+        assert_eq!(4, display.count_lit_pixels());
+
+        display.reset();
+        let result = display.draw(62, 31, &sprite, 1, false, true);
+        assert_eq!(0, result.rows_clipped);
+        assert_eq!([1, 1], display.data[31][62..64]);
+        assert_eq!([1, 1], display.data[0][62..64]);
+        assert_eq!(4, display.count_lit_pixels());
+
+        display.reset();
+        display.draw(62, 31, &sprite, 1, false, false);
+        assert_eq!([1, 1], display.data[31][62..64]);
+        assert_eq!(2, display.count_lit_pixels());
+    }
+
+    #[test]
+    fn draw_test() {
+        let mut display: Display = Display::new();
+
+        // No planes, nothing drawn
+        let result = display.draw(0, 0, &[0xFF], 0, true, true);
+        assert_eq!(DrawResult::default(), result);
+        assert_eq!(true, display.is_clear());
+
+        // Wrapping, nothing clipped
+        let result = display.draw(0, 30, &[0x80, 0x80, 0x80], 1, true, true);
+        assert_eq!(false, result.collided);
+        assert_eq!(0, result.rows_clipped);
+        assert_eq!(1, display.data[0][0]);
+
+        // Clipping, the last row falls off the bottom
+        display.reset();
+        let result = display.draw(0, 30, &[0x80, 0x80, 0x80], 1, false, false);
+        assert_eq!(1, result.rows_clipped);
+        assert_eq!(0, display.data[0][0]);
+
+        // Collision in the second plane only
+        let result = display.draw(0, 30, &[0x80], 2, false, false);
+        assert_eq!(false, result.collided);
+        let result = display.draw(0, 30, &[0x80], 2, false, false);
+        assert_eq!(true, result.collided);
+        assert_eq!(1, display.data[30][0]);
+
+        // Both planes take one row each from the sprite
+        let result = display.draw(0, 31, &[0x80, 0x80], 3, false, false);
+        assert_eq!(true, result.collided);
+        assert_eq!(0, result.rows_clipped);
+        assert_eq!(2, display.data[31][0]);
+
+        // The selected plane is left alone
+        assert_eq!(1, display.plane_mask);
+    }
+
+    #[test]
+    fn plane_test() {
+        let mut display: Display = Display::new();
+
+        display.display_sprite(0, 0, &[0x80]);
+        display.select_plane(2);
+        display.display_sprite(0, 0, &[0xC0]);
+        assert_eq!([3, 2, 0, 0], display.data[0][0..4]);
+
+        // Both planes, first byte goes to plane 1 and the second to plane 2
+        display.select_plane(3);
+        assert_eq!(2, display.sprite_len(1));
+        assert_eq!(true, display.display_sprite(0, 0, &[0x80, 0x40]));
+        assert_eq!([2, 0, 0, 0], display.data[0][0..4]);
+
+        display.select_plane(1);
+        display.display_sprite(3, 0, &[0x80]);
+        assert_eq!([2, 0, 0, 1], display.data[0][0..4]);
+        display.clear_planes();
+        assert_eq!([2, 0, 0, 0], display.data[0][0..4]);
+    }
 }
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+
+type Frame = [[u8; 64]; 32];
+
+// Frames the toggle counts cover, half a second at 60Hz
+pub const FLICKER_WINDOW: usize = 30;
+// Share of the frames a pixel has to change in to count as flickering. A
+// sprite erased and drawn again every other frame changes in all of them,
+// one that moves across the screen in a handful.
+pub const FLICKER_RATE: f32 = 0.25;
+// Flickering pixels it takes to turn persistence on, about one small sprite
+pub const FLICKER_PIXELS: usize = 8;
+// Brightness a pixel keeps each frame after it goes dark
+pub const PHOSPHOR_DECAY: f32 = 0.5;
+// Below this a fading pixel is drawn dark
+const PHOSPHOR_CUTOFF: f32 = 0.05;
+
+// Counts how often each pixel changed over the last FLICKER_WINDOW frames
+pub struct FlickerDetector {
+    last: Option<Frame>,
+    changes: VecDeque<Vec<(usize, usize)>>, // Pixels changed in each frame, newest last
+    counts: [[u16; 64]; 32],                // Per pixel sum of changes
+}
+
+impl FlickerDetector {
+    pub fn new() -> FlickerDetector {
+        FlickerDetector {
+            last: None,
+            changes: VecDeque::with_capacity(FLICKER_WINDOW + 1),
+            counts: [[0; 64]; 32],
+        }
+    }
+
+    // Compares the frame with the one before, call once per shown frame
+    pub fn observe(&mut self, frame: &Frame) {
+        let last = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some(*frame);
+                return;
+            }
+        };
+
+        let mut changed = Vec::new();
+        for y in 0..32 {
+            for x in 0..64 {
+                if (frame[y][x] != 0) != (last[y][x] != 0) {
+                    self.counts[y][x] += 1;
+                    changed.push((x, y));
+                }
+            }
+        }
+        self.changes.push_back(changed);
+        if self.changes.len() > FLICKER_WINDOW {
+            for (x, y) in self.changes.pop_front().unwrap() {
+                self.counts[y][x] -= 1;
+            }
+        }
+        self.last = Some(*frame);
+    }
+
+    // Share of the observed frames the pixel changed in, 0 to 1
+    pub fn toggle_rate(&self, x: usize, y: usize) -> f32 {
+        if self.changes.is_empty() {
+            return 0.0;
+        }
+        self.counts[y][x] as f32 / self.changes.len() as f32
+    }
+
+    // Pixels changing at FLICKER_RATE or more. Nothing counts before the
+    // window is full, a single change would be a rate of 1.
+    pub fn flickering_pixels(&self) -> usize {
+        if self.changes.len() < FLICKER_WINDOW {
+            return 0;
+        }
+        let mut pixels = 0;
+        for y in 0..32 {
+            for x in 0..64 {
+                if self.toggle_rate(x, y) >= FLICKER_RATE {
+                    pixels += 1;
+                }
+            }
+        }
+        pixels
+    }
+
+    pub fn is_flickering(&self) -> bool {
+        self.flickering_pixels() >= FLICKER_PIXELS
+    }
+
+    // Forgets everything, for a new ROM
+    pub fn reset(&mut self) {
+        *self = FlickerDetector::new();
+    }
+}
+
+// Pixels that go dark fade out over a few frames like a CRT's phosphor, so a
+// sprite that's only drawn every other frame looks steady
+pub struct Phosphor {
+    levels: [[f32; 64]; 32], // Brightness, 1 while lit
+    values: Frame,           // Pixel value it was lit with, for the color
+}
+
+impl Phosphor {
+    pub fn new() -> Phosphor {
+        Phosphor {
+            levels: [[0.0; 64]; 32],
+            values: [[0; 64]; 32],
+        }
+    }
+
+    // Moves the fade on by a frame, returns true while a pixel is still
+    // fading and the screen has to be drawn again
+    pub fn update(&mut self, frame: &Frame) -> bool {
+        let mut fading = false;
+        for y in 0..32 {
+            for x in 0..64 {
+                if frame[y][x] != 0 {
+                    self.levels[y][x] = 1.0;
+                    self.values[y][x] = frame[y][x];
+                } else if self.levels[y][x] > 0.0 {
+                    self.levels[y][x] *= PHOSPHOR_DECAY;
+                    if self.levels[y][x] < PHOSPHOR_CUTOFF {
+                        self.levels[y][x] = 0.0;
+                    }
+                    fading = true;
+                }
+            }
+        }
+        fading
+    }
+
+    // Pixel value last lit with and how bright it still is
+    pub fn pixel(&self, x: usize, y: usize) -> (u8, f32) {
+        (self.values[y][x], self.levels[y][x])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_rate() {
+        let mut detector = FlickerDetector::new();
+        let mut frame = [[0u8; 64]; 32];
+        frame[0][1] = 1;
+
+        // Pixels 8 to 15 of row 5 drawn every other frame, pixel 20 lit once
+        for frame_no in 0..=FLICKER_WINDOW {
+            for x in 8..16 {
+                frame[5][x] = (frame_no % 2) as u8;
+            }
+            frame[5][20] = (frame_no == 10) as u8;
+            detector.observe(&frame);
+            if frame_no < FLICKER_WINDOW {
+                assert_eq!(false, detector.is_flickering());
+            }
+        }
+        assert_eq!(1.0, detector.toggle_rate(8, 5));
+        assert_eq!(0.0, detector.toggle_rate(1, 0));
+        assert_eq!(2.0 / FLICKER_WINDOW as f32, detector.toggle_rate(20, 5));
+        assert_eq!(8, detector.flickering_pixels());
+        assert_eq!(true, detector.is_flickering());
+
+        // Changes older than the window are dropped
+        for _ in 0..FLICKER_WINDOW {
+            detector.observe(&frame);
+        }
+        assert_eq!(0.0, detector.toggle_rate(8, 5));
+        assert_eq!(false, detector.is_flickering());
+    }
+
+    #[test]
+    fn phosphor_fade() {
+        let mut phosphor = Phosphor::new();
+        let mut frame = [[0u8; 64]; 32];
+        frame[3][4] = 2;
+
+        assert_eq!(false, phosphor.update(&frame));
+        assert_eq!((2, 1.0), phosphor.pixel(4, 3));
+        frame[3][4] = 0;
+        assert_eq!(true, phosphor.update(&frame));
+        assert_eq!((2, 0.5), phosphor.pixel(4, 3));
+        // Dark once it drops below the cutoff
+        let frames = (0..10).take_while(|_| phosphor.update(&frame)).count();
+        assert_eq!(4, frames);
+        assert_eq!(0.0, phosphor.pixel(4, 3).1);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[cfg(feature = "gamepad")]
+use crate::input::FrameInput;
+use crate::keymap::parse_bindings;
+#[cfg(feature = "gamepad")]
+use crate::state::State;
+
+// Controller buttons by their gilrs Button name. The d-pad moves with the
+// usual CHIP-8 direction keys 2 4 6 8 and the face buttons cover the keys
+// games tend to use for actions.
+const DEFAULT_LAYOUT: [(&str, u8); 12] = [
+    ("DPadUp", 0x2),
+    ("DPadLeft", 0x4),
+    ("DPadRight", 0x6),
+    ("DPadDown", 0x8),
+    ("South", 0x5),
+    ("East", 0x0),
+    ("West", 0xA),
+    ("North", 0xB),
+    ("LeftTrigger", 0x7),
+    ("RightTrigger", 0x9),
+    ("Select", 0xE),
+    ("Start", 0xF),
+];
+
+// Maps controller buttons to CHIP-8 keys. Unlike the keyboard not every key
+// needs a button and several buttons can share a key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GamepadMap {
+    buttons: HashMap<String, u8>,
+}
+
+impl Default for GamepadMap {
+    fn default() -> GamepadMap {
+        let buttons = DEFAULT_LAYOUT
+            .iter()
+            .map(|(name, key)| (name.to_string(), *key))
+            .collect();
+        GamepadMap { buttons: buttons }
+    }
+}
+
+impl GamepadMap {
+    // Same format as the keyboard bindings, `button = key` lines:
+    //     South = "5"
+    //     DPadUp = 0x2
+    pub fn parse(text: &str) -> Result<GamepadMap, String> {
+        let mut buttons = HashMap::new();
+        for (name, key) in parse_bindings(text)? {
+            if buttons.insert(name.to_string(), key).is_some() {
+                return Err(format!("{} is bound twice", name));
+            }
+        }
+        Ok(GamepadMap { buttons: buttons })
+    }
+
+    pub fn load(path: &Path) -> Result<GamepadMap, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                GamepadMap::parse(&text).map_err(|why| format!("{}: {}", path.display(), why))
+            }
+            Err(why) => Err(format!("Couldn't read {}: {}", path.display(), why)),
+        }
+    }
+
+    // CHIP-8 key bound to the button, if any
+    pub fn resolve(&self, button: &str) -> Option<u8> {
+        self.buttons.get(button).cloned()
+    }
+}
+
+// Polls connected controllers, button events are merged with the keyboard's
+// through the same FrameInput
+#[cfg(feature = "gamepad")]
+pub struct Gamepad {
+    gilrs: gilrs::Gilrs,
+    map: GamepadMap,
+}
+
+#[cfg(feature = "gamepad")]
+impl Gamepad {
+    // None when the platform's controller backend isn't available
+    pub fn new(map: GamepadMap) -> Option<Gamepad> {
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(Gamepad {
+                gilrs: gilrs,
+                map: map,
+            }),
+            Err(why) => {
+                println!("Couldn't open gamepads: {}", why);
+                None
+            }
+        }
+    }
+
+    pub fn poll(&mut self, state: &mut State, input: &mut FrameInput) {
+        while let Some(gilrs::Event { event, .. }) = self.gilrs.next_event() {
+            let (button, pressed) = match event {
+                gilrs::EventType::ButtonPressed(button, _) => (button, true),
+                gilrs::EventType::ButtonReleased(button, _) => (button, false),
+                _ => continue,
+            };
+            if let Some(key) = self.map.resolve(&format!("{:?}", button)) {
+                input.key_event(state, key, pressed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout() {
+        let map = GamepadMap::default();
+
+        assert_eq!(Some(0x2), map.resolve("DPadUp"));
+        assert_eq!(Some(0x8), map.resolve("DPadDown"));
+        assert_eq!(Some(0x5), map.resolve("South"));
+        assert_eq!(None, map.resolve("Mode"));
+    }
+
+    #[test]
+    fn parse() {
+        let text = "# Two button games\n[buttons]\nSouth = \"6\"\nEast = 0x6\nDPadUp = 1\n";
+        let map = GamepadMap::parse(text).unwrap();
+
+        assert_eq!(Some(0x6), map.resolve("South"));
+        assert_eq!(Some(0x6), map.resolve("East"));
+        assert_eq!(Some(0x1), map.resolve("DPadUp"));
+        assert_eq!(None, map.resolve("DPadDown"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(GamepadMap::parse("South = 10").is_err());
+        assert!(GamepadMap::parse("South").is_err());
+        assert!(GamepadMap::parse("South = 1\nSouth = 2").is_err());
+    }
+}
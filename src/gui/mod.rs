@@ -1,16 +1,45 @@
-use crate::instruction::Instruction;
+use crate::audio::AudioSettings;
+use crate::config::DEFAULT_GRID_COLOR;
+use crate::display::{DisplaySnapshot, PixelDiff};
+use crate::instruction::{self, EnabledOps, Instruction};
+use crate::keymap::KeyMap;
+use crate::numbase::{self, NumberBase};
+use crate::opengl::{self, Filter, RenderError};
+use crate::quirks::Profile;
+use crate::savestate;
+use crate::search;
 use crate::state::State;
+use crate::symbols::{self, Symbols};
+use crate::watch::Watch;
 use glium;
 use imgui::*;
 use imgui_glium_renderer::Renderer;
+use std::path::PathBuf;
 use std::rc::Rc;
 
+// Search matches listed in the Memory window, the count shows how many more
+const MAX_LISTED_MATCHES: usize = 64;
+
 #[derive(Copy, Clone, PartialEq)]
 pub enum UiAction {
     None,
     Run,
     Stop,
-    Step,
+    StepBack,
+    FrameStep,
+    StepOver,
+    Reset,
+    Skip,
+    QuirksChanged,
+    StepN,
+    DumpRam,
+    LoadSymbols,
+    OpenRom,
+    SaveState,
+    LoadState,
+    Screenshot,
+    CopyScreen,
+    Quit,
 }
 
 #[derive(Copy, Clone, PartialEq, Debug, Default)]
@@ -24,6 +53,54 @@ pub struct Gui {
     imgui: imgui::Context,
     renderer: Renderer,
     game_display_texture_id: imgui::TextureId,
+    follow_pc: bool,
+    timer_start: [u8; 2], // DT and ST values the countdown bars start from
+    sprite_rows: i32,     // Bytes shown in the Sprite @ I window
+    pub step_count: i32,  // Instructions Step N runs
+    pub audio: AudioSettings,
+    last_v: [u8; 16], // V registers and I before the last step
+    last_i: u16,
+    changed_v: [bool; 16], // Registers the last step changed, highlighted
+    changed_i: bool,
+    pub number_base: NumberBase, // How the Registers window shows values
+    last_instruction_count: u64,
+    show_memory: bool,
+    show_trace: bool,
+    show_keypad: bool,
+    show_key_bindings: bool,
+    show_stats: bool,
+    show_profiler: bool,
+    show_opcodes: bool,
+    show_watch: bool,
+    show_next_instruction: bool,
+    show_open_rom: bool,
+    pub running: bool,
+    pub show_grid: bool,
+    pub display_frozen: bool, // Keep showing the last image while the program runs
+    pub persistence: bool,    // Pixels fade out instead of going dark at once
+    pub auto_persistence: bool, // Persistence turns itself on when the ROM flickers
+    pinned_frame: Option<DisplaySnapshot>, // Reference frame for the diff overlay
+    show_diff: bool,
+    pub filter: Filter, // How the pixels are scaled up to the Display window
+    pub grid_color: [f32; 4],
+    pub display_size: [f32; 2], // Size of the screen image, taller with non-square pixels
+    pub step_held: bool,        // Step button is held down, main repeats the step
+    pub state_slot: u8,         // Slot Save State and Load State use
+    pub profile: Option<Profile>,
+    pub symbols: Symbols,
+    pub symbols_path: ImString,
+    pub watches: Vec<Watch>,
+    pub search_input: ImString, // Hex bytes typed into the Memory window
+    pub search_matches: Vec<usize>,
+    pub search_len: usize, // Bytes each match covers
+    pub search_message: String,
+    pub watch_input: ImString, // Expression typed into the Watch window
+    pub rom_path: ImString,    // Path typed into the Open ROM window
+    pub rom_message: String,   // Why the last ROM couldn't be opened or reset
+    pub recent_roms: Vec<PathBuf>,
+    pub keymap: KeyMap,
+    pub rebinding: Option<u8>, // CHIP-8 key the next key press gets bound to
+    pub keymap_message: String, // Why the last rebind was refused
     pub ui_action: UiAction,
 }
 
@@ -40,6 +117,54 @@ impl Gui {
             imgui: imgui,
             renderer: renderer,
             game_display_texture_id: imgui::TextureId::from(0),
+            follow_pc: true,
+            timer_start: [0; 2],
+            sprite_rows: 5,
+            step_count: 10,
+            audio: AudioSettings::default(),
+            last_v: [0; 16],
+            last_i: 0,
+            changed_v: [false; 16],
+            changed_i: false,
+            number_base: NumberBase::Hex,
+            last_instruction_count: 0,
+            show_memory: false,
+            show_trace: false,
+            show_keypad: true,
+            show_key_bindings: false,
+            show_stats: false,
+            show_profiler: false,
+            show_opcodes: false,
+            show_watch: false,
+            show_next_instruction: false,
+            show_open_rom: false,
+            running: false,
+            show_grid: false,
+            display_frozen: false,
+            persistence: false,
+            auto_persistence: false,
+            pinned_frame: None,
+            show_diff: false,
+            grid_color: DEFAULT_GRID_COLOR,
+            display_size: opengl::display_size(1.0),
+            filter: Filter::Nearest,
+            step_held: false,
+            state_slot: 0,
+            profile: None,
+            symbols: Symbols::new(),
+            symbols_path: ImString::with_capacity(256),
+            watches: Vec::new(),
+            search_input: ImString::with_capacity(64),
+            search_matches: Vec::new(),
+            search_len: 0,
+            search_message: String::new(),
+            watch_input: ImString::with_capacity(64),
+            rom_path: ImString::with_capacity(256),
+            recent_roms: Vec::new(),
+            keymap: KeyMap::default(),
+            rebinding: None,
+            keymap_message: String::new(),
+            rom_message: String::new(),
             ui_action: UiAction::None,
         }
     }
@@ -47,14 +172,14 @@ impl Gui {
     pub fn render(
         &mut self,
         target: &mut glium::Frame,
-        state: &State,
+        state: &mut State,
         game_display: glium::Texture2d,
-    ) {
+    ) -> Result<(), RenderError> {
         // Draw GUI
         self.renderer
             .textures()
             .replace(self.game_display_texture_id, Rc::new(game_display));
-        self.draw_gui(state, target);
+        self.draw_gui(state, target)
     }
 
     pub fn update_mouse_state(&mut self, mouse_state: &mut MouseState) {
@@ -64,10 +189,403 @@ impl Gui {
         mouse_state.wheel = 0.0;
     }
 
-    fn draw_gui(&mut self, state: &State, target: &mut glium::Frame) {
-        let mut ui_action = self.ui_action;
+    // Works out which registers changed, once per executed step
+    fn track_registers(&mut self, state: &State) {
+        if state.instruction_count == self.last_instruction_count {
+            return;
+        }
+        for i in 0..16 {
+            self.changed_v[i] = state.v[i] != self.last_v[i];
+        }
+        self.changed_i = state.i != self.last_i;
+        self.last_v = state.v;
+        self.last_i = state.i;
+        self.last_instruction_count = state.instruction_count;
+    }
+
+    fn draw_gui(
+        &mut self,
+        state: &mut State,
+        target: &mut glium::Frame,
+    ) -> Result<(), RenderError> {
+        self.track_registers(state);
+        let changed_v = self.changed_v;
+        let changed_i = self.changed_i;
+        let mut number_base = self.number_base;
+        let mut filter = self.filter;
+        let mut ui_action = UiAction::None;
+        let mut show_memory = self.show_memory;
+        let mut show_trace = self.show_trace;
+        let mut show_keypad = self.show_keypad;
+        let mut show_key_bindings = self.show_key_bindings;
+        let keymap = &self.keymap;
+        let mut rebinding = self.rebinding;
+        let keymap_message = &mut self.keymap_message;
+        let rom_message = &self.rom_message;
+        let mut show_stats = self.show_stats;
+        let mut show_profiler = self.show_profiler;
+        let mut show_opcodes = self.show_opcodes;
+        let mut show_watch = self.show_watch;
+        let watches = &mut self.watches;
+        let search_input = &mut self.search_input;
+        let search_matches = &mut self.search_matches;
+        let search_len = &mut self.search_len;
+        let search_message = &mut self.search_message;
+        // Address of a clicked search match, the Memory and Code windows
+        // scroll to it this frame
+        let mut go_to_addr: Option<usize> = None;
+        let watch_input = &mut self.watch_input;
+        let mut show_next_instruction = self.show_next_instruction;
+        let mut show_open_rom = self.show_open_rom;
+        let rom_path = &mut self.rom_path;
+        let recent_roms = &self.recent_roms;
+        let mut follow_pc = self.follow_pc;
+        let mut timer_start = self.timer_start;
+        let mut step_held = false;
+        let mut show_grid = self.show_grid;
+        let mut display_frozen = self.display_frozen;
+        let mut persistence = self.persistence;
+        let mut auto_persistence = self.auto_persistence;
+        let pinned_frame = &mut self.pinned_frame;
+        let mut show_diff = self.show_diff;
+        let grid_color = self.grid_color;
+        let display_size = self.display_size;
+        let mut sprite_rows = self.sprite_rows;
+        let mut state_slot = self.state_slot;
+        let mut step_count = self.step_count;
+        let mut audio = self.audio;
+        let symbols = &self.symbols;
+        let symbols_path = &mut self.symbols_path;
+        let running = self.running;
+        let mut profile = self.profile;
         let game_display_texture_id = self.game_display_texture_id;
         let ui = self.imgui.frame();
+
+        // Menu items trigger the same actions as the buttons
+        ui.main_menu_bar(|| {
+            ui.menu(im_str!("File")).build(|| {
+                if MenuItem::new(im_str!("Open ROM...")).build(&ui) {
+                    show_open_rom = true;
+                }
+                ui.menu(im_str!("Open Recent"))
+                    .enabled(!recent_roms.is_empty())
+                    .build(|| {
+                        // ROMs that were moved or deleted are greyed out
+                        for path in recent_roms.iter() {
+                            let label = ImString::new(path.to_string_lossy());
+                            if MenuItem::new(&label).enabled(path.exists()).build(&ui) {
+                                *rom_path = label;
+                                ui_action = UiAction::OpenRom;
+                            }
+                        }
+                    });
+                let save_label = ImString::new(format!("Save State (slot {})", state_slot));
+                if MenuItem::new(&save_label).build(&ui) {
+                    ui_action = UiAction::SaveState;
+                }
+                let load_label = ImString::new(format!("Load State (slot {})", state_slot));
+                if MenuItem::new(&load_label).build(&ui) {
+                    ui_action = UiAction::LoadState;
+                }
+                ui.menu(im_str!("State Slot")).build(|| {
+                    for slot in 0..savestate::SLOTS {
+                        let label = ImString::new(format!("Slot {}", slot));
+                        if MenuItem::new(&label)
+                            .selected(slot == state_slot)
+                            .build(&ui)
+                        {
+                            state_slot = slot;
+                        }
+                    }
+                });
+                if MenuItem::new(im_str!("Screenshot")).build(&ui) {
+                    ui_action = UiAction::Screenshot;
+                }
+                if MenuItem::new(im_str!("Copy Screen")).build(&ui) {
+                    ui_action = UiAction::CopyScreen;
+                }
+                if MenuItem::new(im_str!("Dump RAM")).build(&ui) {
+                    ui_action = UiAction::DumpRam;
+                }
+                ui.separator();
+                if MenuItem::new(im_str!("Quit")).build(&ui) {
+                    ui_action = UiAction::Quit;
+                }
+            });
+            ui.menu(im_str!("Run")).build(|| {
+                if MenuItem::new(im_str!("Run")).build(&ui) {
+                    ui_action = UiAction::Run;
+                }
+                if MenuItem::new(im_str!("Stop")).build(&ui) {
+                    ui_action = UiAction::Stop;
+                }
+                if MenuItem::new(im_str!("Step")).build(&ui) {
+                    step_held = true;
+                }
+                if MenuItem::new(im_str!("Step Over")).build(&ui) {
+                    ui_action = UiAction::StepOver;
+                }
+                if MenuItem::new(im_str!("Step Back")).build(&ui) {
+                    ui_action = UiAction::StepBack;
+                }
+                if MenuItem::new(im_str!("Frame Step")).build(&ui) {
+                    ui_action = UiAction::FrameStep;
+                }
+                ui.separator();
+                if MenuItem::new(im_str!("Reset")).build(&ui) {
+                    ui_action = UiAction::Reset;
+                }
+            });
+            ui.menu(im_str!("View")).build(|| {
+                MenuItem::new(im_str!("Memory")).build_with_ref(&ui, &mut show_memory);
+                MenuItem::new(im_str!("Trace")).build_with_ref(&ui, &mut show_trace);
+                MenuItem::new(im_str!("Keypad")).build_with_ref(&ui, &mut show_keypad);
+                MenuItem::new(im_str!("Key Bindings")).build_with_ref(&ui, &mut show_key_bindings);
+                MenuItem::new(im_str!("Stats")).build_with_ref(&ui, &mut show_stats);
+                MenuItem::new(im_str!("Profiler")).build_with_ref(&ui, &mut show_profiler);
+                MenuItem::new(im_str!("Opcodes")).build_with_ref(&ui, &mut show_opcodes);
+                MenuItem::new(im_str!("Watch")).build_with_ref(&ui, &mut show_watch);
+                MenuItem::new(im_str!("Next Instruction"))
+                    .build_with_ref(&ui, &mut show_next_instruction);
+                MenuItem::new(im_str!("Grid")).build_with_ref(&ui, &mut show_grid);
+                MenuItem::new(im_str!("Freeze Display")).build_with_ref(&ui, &mut display_frozen);
+                // Fading pixels are left in the vertices when it's turned off
+                if MenuItem::new(im_str!("Persistence")).build_with_ref(&ui, &mut persistence) {
+                    state.display.dirty = true;
+                }
+                MenuItem::new(im_str!("Auto Persistence"))
+                    .build_with_ref(&ui, &mut auto_persistence);
+                // Presenting less often merges sprites redrawn over a few ticks
+                ui.menu(im_str!("Present Every")).build(|| {
+                    for interval in 1..=4 {
+                        let label = if interval == 1 {
+                            ImString::new("1 vblank")
+                        } else {
+                            ImString::new(format!("{} vblanks", interval))
+                        };
+                        if MenuItem::new(&label)
+                            .selected(state.display.present_interval == interval)
+                            .build(&ui)
+                        {
+                            state.display.present_interval = interval;
+                        }
+                    }
+                });
+                if MenuItem::new(im_str!("Draw Order"))
+                    .build_with_ref(&ui, &mut state.display.draw_logging)
+                {
+                    state.display.clear_draw_log();
+                }
+            });
+        });
+
+        if show_open_rom {
+            imgui::Window::new(im_str!("Open ROM"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    ui.input_text(im_str!("##rom_path"), rom_path).build();
+                    if ui.button(im_str!("Open"), [0.0, 0.0]) {
+                        ui_action = UiAction::OpenRom;
+                        show_open_rom = false;
+                    }
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Cancel"), [0.0, 0.0]) {
+                        show_open_rom = false;
+                    }
+                });
+        }
+
+        if show_memory {
+            imgui::Window::new(im_str!("Memory"))
+                .size([420.0, 300.0], imgui::Condition::FirstUseEver)
+                .build(&ui, || {
+                    ui.input_text(im_str!("##search"), search_input).build();
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Find"), [0.0, 0.0]) {
+                        match search::parse_hex_bytes(search_input.to_str()) {
+                            Ok(pattern) => {
+                                *search_matches = search::find_bytes(&state.ram, &pattern);
+                                *search_len = pattern.len();
+                                *search_message = format!("{} matches", search_matches.len());
+                            }
+                            Err(why) => {
+                                search_matches.clear();
+                                *search_message = why;
+                            }
+                        }
+                    }
+                    if !search_message.is_empty() {
+                        ui.text_disabled(im_str!("{}", search_message));
+                    }
+                    // Click an address to scroll to it, 8 to a line
+                    for (i, addr) in search_matches.iter().take(MAX_LISTED_MATCHES).enumerate() {
+                        if i % 8 != 0 {
+                            ui.same_line(0.0);
+                        }
+                        ui.text(im_str!("{:04X}", addr));
+                        if ui.is_item_clicked(MouseButton::Left) {
+                            go_to_addr = Some(*addr);
+                        }
+                    }
+                    ui.separator();
+
+                    let mut highlighted = vec![false; state.ram.len()];
+                    for addr in search_matches.iter() {
+                        for matched in highlighted.iter_mut().skip(*addr).take(*search_len) {
+                            *matched = true;
+                        }
+                    }
+                    for (row, bytes) in state.ram.chunks(16).enumerate() {
+                        let hex: Vec<String> =
+                            bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                        let line = im_str!("{:04X}: {}", row * 16, hex.join(" "));
+                        ui.text(&line);
+                        if go_to_addr.map_or(false, |addr| addr / 16 == row) {
+                            ui.set_scroll_here_y();
+                        }
+
+                        // Matched bytes are marked over the text, the font is
+                        // monospaced so each character is as wide as the next
+                        let matched: Vec<usize> = (0..bytes.len())
+                            .filter(|offset| highlighted[row * 16 + offset])
+                            .collect();
+                        if matched.is_empty() {
+                            continue;
+                        }
+                        let [left, top] = ui.get_item_rect_min();
+                        let [width, height] = ui.get_item_rect_size();
+                        let char_width = width / line.to_str().chars().count() as f32;
+                        let draw_list = ui.get_window_draw_list();
+                        for offset in matched {
+                            // After "0000: " and 3 characters per byte
+                            let x = left + char_width * (6 + offset * 3) as f32;
+                            draw_list
+                                .add_rect(
+                                    [x, top],
+                                    [x + char_width * 2.0, top + height],
+                                    [1.0, 1.0, 0.0, 0.35],
+                                )
+                                .filled(true)
+                                .build();
+                        }
+                    }
+                });
+        }
+
+        // Newest first
+        if show_trace {
+            imgui::Window::new(im_str!("Trace"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    for addr in state.trace.iter().rev() {
+                        if let Ok(instruction) = state.instruction_at(*addr as usize) {
+                            ui.text(im_str!("{:04X}: {}", addr, instruction.code));
+                        }
+                    }
+                });
+        }
+
+        if show_stats {
+            imgui::Window::new(im_str!("Stats"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    ui.text(im_str!("Instructions: {}", state.instruction_count));
+                    ui.text(im_str!("FPS: {:.1}", ui.io().framerate));
+                    ui.text(im_str!("ROM CRC: {:08x}", state.rom_crc));
+                });
+        }
+        if show_profiler {
+            imgui::Window::new(im_str!("Profiler"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+                        state.family_counts = [0; instruction::FAMILY_COUNT];
+                    }
+                    let profile = state.profile();
+                    let total: u64 = profile.iter().map(|(_, count)| count).sum();
+                    for (pattern, count) in profile.iter() {
+                        ui.text(im_str!(
+                            "{}: {:>10} {:5.1}%",
+                            pattern,
+                            count,
+                            *count as f64 * 100.0 / total as f64
+                        ));
+                    }
+                });
+        }
+        // Unchecked instructions fail like illegal ones when executed
+        if show_opcodes {
+            imgui::Window::new(im_str!("Opcodes"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    if ui.button(im_str!("Enable All"), [0.0, 0.0]) {
+                        state.enabled_ops = EnabledOps::all();
+                    }
+                    for (pattern, mnemonic) in Instruction::supported_opcodes() {
+                        let mut enabled = state.enabled_ops.is_pattern_enabled(pattern);
+                        let label = ImString::new(format!("{} {}", pattern, mnemonic));
+                        if ui.checkbox(&label, &mut enabled) {
+                            let _ = state.enabled_ops.set(pattern, enabled);
+                        }
+                    }
+                });
+        }
+        // Values are taken again every frame, so they follow stepping
+        if show_watch {
+            imgui::Window::new(im_str!("Watch"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    ui.input_text(im_str!("##watch"), watch_input).build();
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Add"), [0.0, 0.0])
+                        && !watch_input.to_str().trim().is_empty()
+                    {
+                        watches.push(Watch::new(watch_input.to_str()));
+                        watch_input.clear();
+                    }
+                    ui.text_disabled(im_str!("V0-VF, I, PC, SP, DT, ST, [addr] or [I]"));
+                    let mut removed = None;
+                    for (i, watch) in watches.iter().enumerate() {
+                        if ui.button(&im_str!("X##watch{}", i), [0.0, 0.0]) {
+                            removed = Some(i);
+                        }
+                        ui.same_line(0.0);
+                        ui.text(im_str!("{}: {}", watch.text, watch.value_text(state)));
+                    }
+                    if let Some(i) = removed {
+                        watches.remove(i);
+                    }
+                });
+        }
+        if show_next_instruction {
+            imgui::Window::new(im_str!("Next Instruction"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    let instruction = match state.current_instruction() {
+                        Ok(instruction) => instruction,
+                        Err(error) => {
+                            ui.text(im_str!("{:04X}: {}", state.pc, error));
+                            return;
+                        }
+                    };
+                    ui.text(im_str!(
+                        "{:04X}: {:04X} {}",
+                        state.pc,
+                        instruction.opcode,
+                        instruction.code
+                    ));
+                    ui.text(im_str!("Operands: {:?}", instruction.operands()));
+                    ui.text(instruction::describe(instruction.opcode));
+                    ui.separator();
+                    let effects = instruction::preview(state, instruction.opcode);
+                    if effects.is_empty() {
+                        ui.text_disabled(im_str!("No preview"));
+                    }
+                    for effect in effects.iter() {
+                        ui.text(im_str!("{}", effect));
+                    }
+                });
+        }
         let display_window_style_token = ui.push_style_vars(&[
             StyleVar::WindowPadding([0.0, 0.0]),
             StyleVar::WindowRounding(0.0),
@@ -76,39 +594,195 @@ impl Gui {
         imgui::Window::new(im_str!("Display"))
             .title_bar(false)
             .resizable(false)
-            .size([400.0, 200.0], imgui::Condition::Always)
+            .size(display_size, imgui::Condition::Always)
             .build(&ui, || {
-                Image::new(game_display_texture_id, [400.0, 200.0]).build(&ui);
+                Image::new(game_display_texture_id, display_size).build(&ui);
+
+                // Drawn over the image, the emulated pixels are left alone
+                if show_grid {
+                    let [left, top] = ui.get_item_rect_min();
+                    let [width, height] = ui.get_item_rect_size();
+                    let draw_list = ui.get_window_draw_list();
+                    for column in 1..64 {
+                        let x = left + width * column as f32 / 64.0;
+                        draw_list
+                            .add_line([x, top], [x, top + height], grid_color)
+                            .build();
+                    }
+                    for row in 1..32 {
+                        let y = top + height * row as f32 / 32.0;
+                        draw_list
+                            .add_line([left, y], [left + width, y], grid_color)
+                            .build();
+                    }
+                }
+
+                // Pixels that differ from the pinned frame, green for newly
+                // lit, red for cleared and yellow for other planes
+                if let (true, Some(pinned)) = (show_diff, pinned_frame.as_ref()) {
+                    let [left, top] = ui.get_item_rect_min();
+                    let [width, height] = ui.get_item_rect_size();
+                    let (pixel_width, pixel_height) = (width / 64.0, height / 32.0);
+                    let draw_list = ui.get_window_draw_list();
+                    for (x, y, change) in state.display.diff(pinned) {
+                        let color = match change {
+                            PixelDiff::Lit => [0.0, 1.0, 0.0, 0.5],
+                            PixelDiff::Cleared => [1.0, 0.0, 0.0, 0.5],
+                            _ => [1.0, 1.0, 0.0, 0.5],
+                        };
+                        let min = [left + pixel_width * x as f32, top + pixel_height * y as f32];
+                        let max = [min[0] + pixel_width, min[1] + pixel_height];
+                        draw_list.add_rect(min, max, color).filled(true).build();
+                    }
+                }
+
+                // Outlines every sprite of the last frame numbered in the
+                // order they were drawn, sprites that wrap are cut at the edge
+                if state.display.draw_logging {
+                    let [left, top] = ui.get_item_rect_min();
+                    let [width, height] = ui.get_item_rect_size();
+                    let (pixel_width, pixel_height) = (width / 64.0, height / 32.0);
+                    let draw_list = ui.get_window_draw_list();
+                    for (i, rect) in state.display.shown_draws.iter().enumerate() {
+                        let min = [
+                            left + pixel_width * rect.x as f32,
+                            top + pixel_height * rect.y as f32,
+                        ];
+                        let max = [
+                            (min[0] + pixel_width * rect.width as f32).min(left + width),
+                            (min[1] + pixel_height * rect.height as f32).min(top + height),
+                        ];
+                        draw_list.add_rect(min, max, [0.0, 1.0, 1.0, 1.0]).build();
+                        draw_list.add_text(
+                            [min[0] + 2.0, min[1] + 1.0],
+                            [0.0, 1.0, 1.0, 1.0],
+                            format!("{}", i + 1),
+                        );
+                    }
+                }
             });
 
         display_window_style_token.pop(&ui);
 
         imgui::Window::new(im_str!("Registers"))
-            .size([100.0, 0.0], imgui::Condition::Always)
+            .size([0.0, 0.0], imgui::Condition::Always)
             .build(&ui, || {
+                let labels: Vec<ImString> = NumberBase::ALL
+                    .iter()
+                    .map(|base| ImString::new(base.name()))
+                    .collect();
+                let names: Vec<&ImStr> = labels.iter().map(|label| label.as_ref()).collect();
+                let mut selected = NumberBase::ALL
+                    .iter()
+                    .position(|base| *base == number_base)
+                    .unwrap();
+                if ComboBox::new(im_str!("##base")).build_simple_string(&ui, &mut selected, &names)
+                {
+                    number_base = NumberBase::ALL[selected];
+                }
+                let byte = |value: u8| numbase::format_value(value as u16, number_base, 8);
+                let word = |value: u16| numbase::format_value(value, number_base, 16);
+
+                // Registers the last step changed are yellow
+                let highlight = [1.0, 1.0, 0.0, 1.0];
                 for i in 0..16 {
-                    ui.text(im_str!("V{:01X}: {:02X}", i, state.v[i]));
+                    let text = im_str!("V{:01X}: {}", i, byte(state.v[i]));
+                    if changed_v[i] {
+                        ui.text_colored(highlight, text);
+                    } else {
+                        ui.text(text);
+                    }
                 }
                 ui.separator();
-                ui.text(im_str!("I: {:04X}", state.i));
-                ui.text(im_str!("PC: {:04X}", state.pc));
+                let text = im_str!("I: {}", word(state.i));
+                if changed_i {
+                    ui.text_colored(highlight, text);
+                } else {
+                    ui.text(text);
+                }
+                ui.text(im_str!("PC: {}", word(state.pc)));
                 ui.text(im_str!("SP: {:02X}", state.sp));
-                ui.text(im_str!("DT: {:02X}", state.dt));
-                ui.text(im_str!("ST: {:02X}", state.st));
+                ui.text(im_str!("DT: {}", byte(state.dt)));
+                ui.text(im_str!("ST: {}", byte(state.st)));
+            });
+
+        imgui::Window::new(im_str!("Timers"))
+            .size([160.0, 0.0], imgui::Condition::Always)
+            .build(&ui, || {
+                let timers = [("DT", state.dt), ("ST", state.st)];
+                for (i, (label, value)) in timers.iter().enumerate() {
+                    if *value > timer_start[i] || *value == 0 {
+                        timer_start[i] = *value;
+                    }
+                    let fraction = if timer_start[i] > 0 {
+                        *value as f32 / timer_start[i] as f32
+                    } else {
+                        0.0
+                    };
+                    ProgressBar::new(fraction)
+                        .overlay_text(&im_str!("{}: {}", label, value))
+                        .build(&ui);
+                }
+
+                ui.checkbox(im_str!("Freeze"), &mut state.timers_frozen);
+                // Only the output, ST keeps counting down
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Mute"), &mut audio.muted);
+                ui.slider_float(im_str!("Volume"), &mut audio.volume, 0.0, 1.0)
+                    .build();
+
+                // Timers can only be set while paused
+                if !running {
+                    ui.separator();
+                    let mut dt = state.dt as i32;
+                    let mut st = state.st as i32;
+                    if ui.input_int(im_str!("DT"), &mut dt).build() {
+                        state.dt = dt.max(0).min(255) as u8;
+                    }
+                    if ui.input_int(im_str!("ST"), &mut st).build() {
+                        state.st = st.max(0).min(255) as u8;
+                    }
+                }
+            });
+
+        // Clicking a pixel flips the bit in RAM
+        imgui::Window::new(im_str!("Sprite @ I"))
+            .size([0.0, 0.0], imgui::Condition::Always)
+            .build(&ui, || {
+                ui.text(im_str!("I: {:04X}", state.i));
+                if ui.input_int(im_str!("Rows"), &mut sprite_rows).build() {
+                    sprite_rows = sprite_rows.max(1).min(16);
+                }
+                ui.separator();
+
+                for row in 0..sprite_rows as usize {
+                    let addr = state.i as usize + row;
+                    let byte = match state.ram.get(addr) {
+                        Some(byte) => *byte,
+                        None => break,
+                    };
+                    for bit in 0..8 {
+                        let mut lit = byte & (0b10000000 >> bit) != 0;
+                        if ui.checkbox(&im_str!("##sprite{}_{}", row, bit), &mut lit) {
+                            state.ram[addr] ^= 0b10000000 >> bit;
+                        }
+                        ui.same_line(0.0);
+                    }
+                    ui.text(im_str!("{:02X}", byte));
+                }
             });
 
         imgui::Window::new(im_str!("Stack"))
             .size([100.0, 0.0], imgui::Condition::Always)
             .build(&ui, || {
-                for i in 0..15 {
-                    ui.text(im_str!("{:01X}: {:04X}", i, state.stack[i]));
+                for (i, value) in state.stack.iter().enumerate() {
+                    ui.text(im_str!("{:01X}: {:04X}", i, value));
                 }
             });
 
         imgui::Window::new(im_str!("Control"))
             .size([0.0, 0.0], imgui::Condition::Always)
             .build(&ui, || {
-                ui_action = UiAction::None;
                 let mut x = 8f32;
                 if ui.button(im_str!("Run"), [0.0, 20.0]) {
                     ui_action = UiAction::Run;
@@ -120,54 +794,262 @@ impl Gui {
                 }
                 x += ui.get_item_rect_size()[0] + 8.0;
                 ui.same_line(x);
-                if ui.button(im_str!("Step"), [0.0, 20.0]) {
-                    ui_action = UiAction::Step;
+                // Steps on press and repeats while held rather than on click
+                ui.button(im_str!("Step"), [0.0, 20.0]);
+                step_held |= ui.is_item_active();
+                x += ui.get_item_rect_size()[0] + 8.0;
+                ui.same_line(x);
+                if ui.button(im_str!("Step Back"), [0.0, 20.0]) {
+                    ui_action = UiAction::StepBack;
+                }
+                x += ui.get_item_rect_size()[0] + 8.0;
+                ui.same_line(x);
+                if ui.button(im_str!("Frame Step"), [0.0, 20.0]) {
+                    ui_action = UiAction::FrameStep;
+                }
+                x += ui.get_item_rect_size()[0] + 8.0;
+                ui.same_line(x);
+                if ui.button(im_str!("Dump RAM"), [0.0, 20.0]) {
+                    ui_action = UiAction::DumpRam;
+                }
+                x += ui.get_item_rect_size()[0] + 8.0;
+                ui.same_line(x);
+                if ui.button(im_str!("Invert"), [0.0, 20.0]) {
+                    state.display.invert();
+                }
+                if ui
+                    .input_int(im_str!("##step_count"), &mut step_count)
+                    .build()
+                {
+                    step_count = step_count.max(1);
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Step N"), [0.0, 20.0]) {
+                    ui_action = UiAction::StepN;
+                }
+                ui.checkbox(im_str!("Show grid"), &mut show_grid);
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Freeze"), &mut display_frozen);
+                ui.same_line(0.0);
+                ui.checkbox(im_str!("Even PC"), &mut state.enforce_alignment);
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Stop when a jump, call or return lands on an odd address");
+                }
+                let labels: Vec<ImString> = Filter::ALL
+                    .iter()
+                    .map(|filter| ImString::new(filter.name()))
+                    .collect();
+                let names: Vec<&ImStr> = labels.iter().map(|label| label.as_ref()).collect();
+                let mut selected = Filter::ALL.iter().position(|f| *f == filter).unwrap();
+                if ComboBox::new(im_str!("Filter")).build_simple_string(&ui, &mut selected, &names)
+                {
+                    filter = Filter::ALL[selected];
+                }
+                if ui.button(im_str!("Pin Frame"), [0.0, 20.0]) {
+                    *pinned_frame = Some(state.display.snapshot());
+                    show_diff = true;
+                }
+                if pinned_frame.is_some() {
+                    ui.same_line(0.0);
+                    ui.checkbox(im_str!("Show diff"), &mut show_diff);
+                    ui.same_line(0.0);
+                    if ui.button(im_str!("Unpin"), [0.0, 20.0]) {
+                        *pinned_frame = None;
+                        show_diff = false;
+                    }
+                }
+                if state.pc % 2 != 0 {
+                    ui.text_colored([1.0, 1.0, 0.0, 1.0], im_str!("PC is odd"));
+                }
+                if !rom_message.is_empty() {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], im_str!("{}", rom_message));
+                }
+                if state.exited {
+                    ui.text(im_str!("Exited"));
+                } else if state.halted {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], im_str!("Program halted"));
+                } else if state.cycle_limit_reached() {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], im_str!("Cycle limit reached"));
+                }
+                // Paused on an instruction that couldn't run, it may be data
+                if let Some(fault) = state.fault {
+                    ui.text_colored(
+                        [1.0, 0.0, 0.0, 1.0],
+                        im_str!("{} at {:04X}", fault, state.pc),
+                    );
+                    if ui.button(im_str!("Skip"), [0.0, 20.0]) {
+                        ui_action = UiAction::Skip;
+                    }
                 }
             });
 
-        imgui::Window::new(im_str!("Keypad"))
+        if show_keypad {
+            imgui::Window::new(im_str!("Keypad"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    for (i, key) in state.keypad.iter().enumerate() {
+                        ui.text(im_str!("{:01X}: {}", i, key));
+                    }
+                });
+        }
+
+        // Rebind waits for the next key press, main binds it
+        if show_key_bindings {
+            imgui::Window::new(im_str!("Key Bindings"))
+                .size([0.0, 0.0], imgui::Condition::Always)
+                .build(&ui, || {
+                    for key in 0..16 {
+                        let name = keymap.key_name(key).unwrap_or("-");
+                        ui.text(im_str!("{:01X}: {:<10}", key, name));
+                        ui.same_line(0.0);
+                        if rebinding == Some(key) {
+                            ui.text_disabled(im_str!("Press a key, Escape cancels"));
+                        } else if ui.button(&im_str!("Rebind##{}", key), [0.0, 0.0]) {
+                            rebinding = Some(key);
+                            keymap_message.clear();
+                        }
+                    }
+                    if !keymap_message.is_empty() {
+                        ui.text_colored([1.0, 0.4, 0.4, 1.0], im_str!("{}", keymap_message));
+                    }
+                });
+        }
+
+        // Quirks take effect on the next executed instruction
+        imgui::Window::new(im_str!("Quirks"))
             .size([0.0, 0.0], imgui::Condition::Always)
             .build(&ui, || {
-                for (i, key) in state.keypad.iter().enumerate() {
-                    ui.text(im_str!("{:01X}: {}", i, key));
+                // Index 0 is for quirks that don't come from a profile
+                let labels: Vec<ImString> = std::iter::once("Custom")
+                    .chain(Profile::ALL.iter().map(|profile| profile.name()))
+                    .map(ImString::new)
+                    .collect();
+                let names: Vec<&ImStr> = labels.iter().map(|label| label.as_ref()).collect();
+                let mut selected = profile.map_or(0, |profile| {
+                    Profile::ALL.iter().position(|p| *p == profile).unwrap() + 1
+                });
+                if ComboBox::new(im_str!("Profile")).build_simple_string(&ui, &mut selected, &names)
+                    && selected > 0
+                {
+                    profile = Some(Profile::ALL[selected - 1]);
+                    state.quirks = Profile::ALL[selected - 1].quirks();
+                    ui_action = UiAction::QuirksChanged;
+                }
+
+                let quirks = &mut state.quirks;
+                let mut changed = ui.checkbox(im_str!("Shift uses Vy"), &mut quirks.shift_uses_vy);
+                changed |= ui.checkbox(
+                    im_str!("Load/store increments I"),
+                    &mut quirks.load_store_increments_i,
+                );
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(
+                        "On: COSMAC VIP, I ends up past the last register\n\
+                         Off: CHIP-48 and Amiga interpreters, I is left alone",
+                    );
+                }
+                changed |= ui.checkbox(im_str!("Bnnn uses Vx"), &mut quirks.jump_uses_vx);
+                changed |= ui.checkbox(im_str!("Fx1E sets VF"), &mut quirks.add_i_sets_vf);
+                changed |= ui.checkbox(im_str!("Wrap sprites X"), &mut quirks.wrap_x);
+                changed |= ui.checkbox(im_str!("Wrap sprites Y"), &mut quirks.wrap_y);
+                changed |= ui.checkbox(im_str!("Display wait"), &mut quirks.display_wait);
+                changed |= ui.checkbox(im_str!("Wrap memory"), &mut quirks.mem_wrap);
+                changed |= ui.checkbox(
+                    im_str!("Protect font area"),
+                    &mut quirks.protect_interpreter_area,
+                );
+                changed |= ui.checkbox(im_str!("5xy2/5xy3 ranges"), &mut quirks.register_ranges);
+                changed |= ui.checkbox(im_str!("F002/Fx3A audio"), &mut quirks.xo_audio);
+                // The quirks no longer match the profile's
+                if changed {
+                    profile = None;
+                    ui_action = UiAction::QuirksChanged;
                 }
             });
 
         imgui::Window::new(im_str!("Code"))
             .size([0.0, 0.0], imgui::Condition::Always)
             .build(&ui, || {
+                ui.checkbox(im_str!("Follow PC"), &mut follow_pc);
+                ui.same_line(0.0);
+                // One-shot scroll, useful when Follow PC is off
+                let go_to_pc = ui.button(im_str!("Go to PC"), [0.0, 0.0]);
+                ui.input_text(im_str!("##symbols"), symbols_path).build();
+                ui.same_line(0.0);
+                if ui.button(im_str!("Load symbols"), [0.0, 0.0]) {
+                    ui_action = UiAction::LoadSymbols;
+                }
+                ui.text_disabled(im_str!("Click a line to toggle a breakpoint (*)"));
+                ui.separator();
+
                 for i in (0x200..(state.ram.len() - 1)).step_by(2) {
-                    let instruction =
-                        Instruction::new(((state.ram[i]) as u16) << 8 | state.ram[i + 1] as u16);
+                    let instruction = match state.instruction_at(i) {
+                        Ok(instruction) => instruction,
+                        Err(_) => break,
+                    };
+                    let code = symbols::annotate(&instruction, symbols);
+                    let marker = if state.breakpoints.contains(&(i as u16)) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    let line =
+                        im_str!("{}{:04X}: {} ({:04X})", marker, i, code, instruction.opcode);
                     if i == state.pc as usize {
                         let token =
                             ui.push_style_colors(&[(StyleColor::Text, [1.0, 0.0, 0.0, 1.0])]);
 
-                        ui.text(im_str!(
-                            "{:04X}: {} ({:04X})",
-                            i,
-                            instruction.code,
-                            instruction.opcode
-                        ));
+                        ui.text(&line);
 
-                        ui.set_scroll_here_y();
+                        if follow_pc || go_to_pc {
+                            ui.set_scroll_here_y();
+                        }
 
                         token.pop(&ui);
                     } else {
-                        ui.text(im_str!(
-                            "{:04X}: {} ({:04X})",
-                            i,
-                            instruction.code,
-                            instruction.opcode
-                        ));
+                        ui.text(&line);
+                    }
+                    if go_to_addr.map_or(false, |addr| addr & !1 == i) {
+                        ui.set_scroll_here_y();
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(instruction::describe(instruction.opcode));
+                    }
+                    if ui.is_item_clicked(MouseButton::Left) {
+                        state.toggle_breakpoint(i as u16);
                     }
                 }
             });
 
         self.ui_action = ui_action;
+        self.follow_pc = follow_pc;
+        self.timer_start = timer_start;
+        self.step_held = step_held;
+        self.show_grid = show_grid;
+        self.display_frozen = display_frozen;
+        self.persistence = persistence;
+        self.auto_persistence = auto_persistence;
+        self.show_diff = show_diff;
+        self.show_memory = show_memory;
+        self.show_trace = show_trace;
+        self.show_keypad = show_keypad;
+        self.show_key_bindings = show_key_bindings;
+        self.rebinding = rebinding;
+        self.show_stats = show_stats;
+        self.show_profiler = show_profiler;
+        self.show_opcodes = show_opcodes;
+        self.show_watch = show_watch;
+        self.show_next_instruction = show_next_instruction;
+        self.show_open_rom = show_open_rom;
+        self.sprite_rows = sprite_rows;
+        self.state_slot = state_slot;
+        self.step_count = step_count;
+        self.audio = audio;
+        self.profile = profile;
+        self.number_base = number_base;
+        self.filter = filter;
 
-        self.renderer
-            .render(target, ui.render())
-            .expect("Rendering failed!");
+        self.renderer.render(target, ui.render())?;
+        Ok(())
     }
 }
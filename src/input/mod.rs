@@ -0,0 +1,127 @@
+use crate::state::State;
+
+// Collects key events between frames and hands them to the machine as one
+// keypad snapshot, so every cycle of a frame sees the same keys. A key pressed
+// and released within one frame still shows up as held for that frame.
+pub struct FrameInput {
+    live: bool, // Events go straight to the keypad as they arrive
+    held: [bool; 16],
+    tapped: [bool; 16], // Pressed since the last snapshot
+    last_press: Option<u8>,
+}
+
+impl FrameInput {
+    pub fn new(live: bool) -> FrameInput {
+        FrameInput {
+            live: live,
+            held: [false; 16],
+            tapped: [false; 16],
+            last_press: None,
+        }
+    }
+
+    pub fn key_event(&mut self, state: &mut State, key: u8, pressed: bool) {
+        let key = key & 0x0F;
+        if self.live {
+            state.set_key(key, pressed);
+            return;
+        }
+        if pressed && !self.held[key as usize] {
+            self.tapped[key as usize] = true;
+            self.last_press = Some(key);
+        }
+        self.held[key as usize] = pressed;
+    }
+
+    // Keys for the coming frame, the held ones and the ones tapped since the
+    // last snapshot
+    pub fn snapshot(&mut self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (key, pressed) in keys.iter_mut().enumerate() {
+            *pressed = self.held[key] || self.tapped[key];
+        }
+        self.tapped = [false; 16];
+        keys
+    }
+
+    // Applies the snapshot before the frame's cycles run. The latest press is
+    // applied last so Fx0A picks it.
+    pub fn apply(&mut self, state: &mut State) {
+        if self.live {
+            return;
+        }
+        let last_press = self.last_press.take();
+        for (key, pressed) in self.snapshot().iter().enumerate() {
+            if Some(key as u8) != last_press {
+                state.set_key(key as u8, *pressed);
+            }
+        }
+        if let Some(key) = last_press {
+            state.set_key(key, true);
+        }
+    }
+
+    // Releases every key, for when the window loses focus and releases would
+    // be missed
+    pub fn release_all(&mut self, state: &mut State) {
+        self.held = [false; 16];
+        self.tapped = [false; 16];
+        self.last_press = None;
+        state.keypad = [false; 16];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let mut state = State::new();
+        let mut input = FrameInput::new(false);
+
+        // Events wait for the next frame
+        input.key_event(&mut state, 5, true);
+        assert_eq!(false, state.keypad[5]);
+        input.apply(&mut state);
+        assert_eq!(true, state.keypad[5]);
+
+        // A tap within one frame is held for that frame only
+        input.key_event(&mut state, 7, true);
+        input.key_event(&mut state, 7, false);
+        input.apply(&mut state);
+        assert_eq!(true, state.keypad[7]);
+        assert_eq!(true, state.keypad[5]);
+        assert_eq!(Some(7), state.held_key());
+        input.apply(&mut state);
+        assert_eq!(false, state.keypad[7]);
+        assert_eq!(true, state.keypad[5]);
+
+        input.key_event(&mut state, 5, false);
+        input.apply(&mut state);
+        assert_eq!([false; 16], state.keypad);
+    }
+
+    #[test]
+    fn latest_press_wins() {
+        let mut state = State::new();
+        let mut input = FrameInput::new(false);
+
+        input.key_event(&mut state, 0xA, true);
+        input.key_event(&mut state, 0x2, true);
+        input.apply(&mut state);
+        assert_eq!(Some(0x2), state.held_key());
+    }
+
+    #[test]
+    fn live() {
+        let mut state = State::new();
+        let mut input = FrameInput::new(true);
+
+        input.key_event(&mut state, 3, true);
+        assert_eq!(true, state.keypad[3]);
+        input.key_event(&mut state, 3, false);
+        input.apply(&mut state);
+        assert_eq!(false, state.keypad[3]);
+    }
+}
@@ -1,31 +1,459 @@
-use crate::state::State;
-use rand::Rng;
+use crate::state::{ExecError, State};
 use std::fmt;
 
-fn get_x(opcode: u16) -> u16 {
+pub fn get_x(opcode: u16) -> u16 {
     (opcode & 0x0F00) >> 8
 }
 
-fn get_y(opcode: u16) -> u16 {
+pub fn get_y(opcode: u16) -> u16 {
     (opcode & 0x00F0) >> 4
 }
 
-fn get_nnn(opcode: u16) -> u16 {
+pub fn get_nnn(opcode: u16) -> u16 {
     opcode & 0x0FFF
 }
 
-fn get_addr(opcode: u16) -> u16 {
+pub fn get_addr(opcode: u16) -> u16 {
     opcode & 0x0FFF
 }
 
-fn get_nibble(opcode: u16) -> u16 {
+pub fn get_nibble(opcode: u16) -> u16 {
     opcode & 0x000F
 }
 
-fn get_byte(opcode: u16) -> u8 {
+pub fn get_byte(opcode: u16) -> u8 {
     (opcode & 0x00FF) as u8
 }
 
+// Decoded operand fields of an instruction, named after the opcode patterns
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operands {
+    None,
+    Addr(u16),                    // nnn
+    Nibble(u8),                   // n in the x position, PLANE n
+    Vx { x: u8 },                 // Ex.., Fx..
+    VxByte { x: u8, byte: u8 },   // xkk
+    Vxy { x: u8, y: u8 },         // xy.
+    Vxyn { x: u8, y: u8, n: u8 }, // xyn
+}
+
+pub fn decode_operands(opcode: u16) -> Operands {
+    let x = get_x(opcode) as u8;
+    let y = get_y(opcode) as u8;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x0000 | 0x00E0 | 0x00EE | 0x00FD => Operands::None,
+            _ => Operands::Addr(get_addr(opcode)),
+        },
+        0x1000 | 0x2000 | 0xA000 | 0xB000 => Operands::Addr(get_addr(opcode)),
+        0x3000 | 0x4000 | 0x6000 | 0x7000 | 0xC000 => Operands::VxByte {
+            x: x,
+            byte: get_byte(opcode),
+        },
+        0x5000 | 0x8000 | 0x9000 => Operands::Vxy { x: x, y: y },
+        0xD000 => Operands::Vxyn {
+            x: x,
+            y: y,
+            n: get_nibble(opcode) as u8,
+        },
+        _ => match opcode & 0xF0FF {
+            0xF000 if opcode == 0xF000 => Operands::None,
+            0xF002 if opcode == 0xF002 => Operands::None,
+            0xF001 => Operands::Nibble(x),
+            _ => Operands::Vx { x: x },
+        },
+    }
+}
+
+// Number of supported opcode patterns, one instruction family each
+pub const FAMILY_COUNT: usize = 42;
+
+// Opcode patterns the interpreter implements with their mnemonics, in
+// opcode order
+const SUPPORTED_OPCODES: [(&str, &str); FAMILY_COUNT] = [
+    ("00E0", "CLS"),
+    ("00EE", "RET"),
+    ("00FD", "EXIT"),
+    ("0nnn", "SYS addr"),
+    ("1nnn", "JMP addr"),
+    ("2nnn", "CALL addr"),
+    ("3xkk", "SE Vx, byte"),
+    ("4xkk", "SNE Vx, byte"),
+    ("5xy0", "SE Vx, Vy"),
+    ("5xy2", "LD [I], Vx-Vy"),
+    ("5xy3", "LD Vx-Vy, [I]"),
+    ("6xkk", "LD Vx, byte"),
+    ("7xkk", "ADD Vx, byte"),
+    ("8xy0", "LD Vx, Vy"),
+    ("8xy1", "OR Vx, Vy"),
+    ("8xy2", "AND Vx, Vy"),
+    ("8xy3", "XOR Vx, Vy"),
+    ("8xy4", "ADD Vx, Vy"),
+    ("8xy5", "SUB Vx, Vy"),
+    ("8xy6", "SHR Vx, Vy"),
+    ("8xy7", "SUBN Vx, Vy"),
+    ("8xyE", "SHL Vx, Vy"),
+    ("9xy0", "SNE Vx, Vy"),
+    ("Annn", "LD I, addr"),
+    ("Bnnn", "JP V0, addr"),
+    ("Cxkk", "RND Vx, byte"),
+    ("Dxyn", "DRW Vx, Vy, nibble"),
+    ("Ex9E", "SKP Vx"),
+    ("ExA1", "SKNP Vx"),
+    ("F000", "LD I, long addr"),
+    ("Fn01", "PLANE n"),
+    ("F002", "AUDIO"),
+    ("Fx07", "LD Vx, DT"),
+    ("Fx0A", "LD Vx, K"),
+    ("Fx15", "LD DT, Vx"),
+    ("Fx18", "LD ST, Vx"),
+    ("Fx1E", "ADD I, Vx"),
+    ("Fx29", "LD F, Vx"),
+    ("Fx33", "LD B, Vx"),
+    ("Fx3A", "PITCH Vx"),
+    ("Fx55", "LD [I], Vx"),
+    ("Fx65", "LD Vx, [I]"),
+];
+
+// The opcode with its operand nibbles zeroed, the same for every instruction
+// of a kind. Cheap enough to count with on every executed instruction.
+pub fn family(opcode: u16) -> u16 {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 | 0x00EE | 0x00FD => opcode,
+            _ => 0x0000,
+        },
+        0x5000 | 0x8000 | 0x9000 => opcode & 0xF00F,
+        0xE000 | 0xF000 => opcode & 0xF0FF,
+        _ => opcode & 0xF000,
+    }
+}
+
+// Index of the family's pattern in the supported opcodes, below FAMILY_COUNT
+pub fn family_index(family: u16) -> Option<usize> {
+    SUPPORTED_OPCODES.iter().position(|(pattern, _)| {
+        pattern.chars().enumerate().all(|(n, p)| {
+            p.is_lowercase() || p.to_digit(16) == Some((family >> (12 - 4 * n) & 0xF) as u32)
+        })
+    })
+}
+
+// Pattern of a family from the supported opcodes, like 8xy4
+pub fn family_pattern(family: u16) -> &'static str {
+    family_index(family).map_or("????", pattern_at)
+}
+
+// Pattern at a family_index
+pub fn pattern_at(index: usize) -> &'static str {
+    SUPPORTED_OPCODES[index].0
+}
+
+// A bit per supported opcode pattern, in SUPPORTED_OPCODES order. Executing
+// an instruction whose bit is cleared fails with ExecError::Disabled, for
+// seeing how a ROM copes with an interpreter that lacks some instructions.
+// Opcodes that aren't supported at all stay illegal either way.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EnabledOps(u64);
+
+impl Default for EnabledOps {
+    fn default() -> EnabledOps {
+        EnabledOps::all()
+    }
+}
+
+impl EnabledOps {
+    pub fn all() -> EnabledOps {
+        EnabledOps(!0)
+    }
+
+    pub fn all_enabled(&self) -> bool {
+        *self == EnabledOps::all()
+    }
+
+    // Looks the family up only when something is disabled, it's checked
+    // before every instruction
+    pub fn is_enabled(&self, opcode: u16) -> bool {
+        self.all_enabled()
+            || family_index(family(opcode)).map_or(true, |index| self.0 & 1 << index != 0)
+    }
+
+    // Pattern as listed by supported_opcodes, like Dxyn
+    pub fn is_pattern_enabled(&self, pattern: &str) -> bool {
+        pattern_index(pattern).map_or(true, |index| self.0 & 1 << index != 0)
+    }
+
+    pub fn set(&mut self, pattern: &str, enabled: bool) -> Result<(), String> {
+        let index =
+            pattern_index(pattern).ok_or_else(|| format!("Unknown opcode pattern {}", pattern))?;
+        if enabled {
+            self.0 |= 1 << index;
+        } else {
+            self.0 &= !(1 << index);
+        }
+        Ok(())
+    }
+}
+
+fn pattern_index(pattern: &str) -> Option<usize> {
+    SUPPORTED_OPCODES
+        .iter()
+        .position(|(supported, _)| supported.eq_ignore_ascii_case(pattern))
+}
+
+// Plain English description of what an opcode does, for the disassembly
+pub fn describe(opcode: u16) -> &'static str {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x0000 => "Zeroed memory, halts the program",
+            0x00E0 => "Clear the display",
+            0x00EE => "Return from a subroutine",
+            0x00FD => "Exit the interpreter",
+            _ => "Call a machine code routine at nnn, jumps there",
+        },
+        0x1000 => "Jump to nnn",
+        0x2000 => "Call the subroutine at nnn",
+        0x3000 => "Skip the next instruction if Vx = kk",
+        0x4000 => "Skip the next instruction if Vx != kk",
+        0x5000 => match opcode & 0xF00F {
+            0x5000 => "Skip the next instruction if Vx = Vy",
+            0x5002 => "Store Vx to Vy in memory starting at I, I is unchanged",
+            0x5003 => "Read Vx to Vy from memory starting at I, I is unchanged",
+            _ => "Unknown instruction",
+        },
+        0x6000 => "Set Vx = kk",
+        0x7000 => "Set Vx = Vx + kk, VF is unchanged",
+        0x8000 => match opcode & 0xF00F {
+            0x8000 => "Set Vx = Vy",
+            0x8001 => "Set Vx = Vx OR Vy",
+            0x8002 => "Set Vx = Vx AND Vy",
+            0x8003 => "Set Vx = Vx XOR Vy",
+            0x8004 => "Set Vx = Vx + Vy, VF = carry",
+            0x8005 => "Set Vx = Vx - Vy, VF = NOT borrow",
+            0x8006 => "Set Vx = Vx >> 1 (Vy >> 1 with the shift quirk), VF = shifted out bit",
+            0x8007 => "Set Vx = Vy - Vx, VF = NOT borrow",
+            0x800E => "Set Vx = Vx << 1 (Vy << 1 with the shift quirk), VF = shifted out bit",
+            _ => "Unknown instruction",
+        },
+        0x9000 => "Skip the next instruction if Vx != Vy",
+        0xA000 => "Set I = nnn",
+        0xB000 => "Jump to nnn + V0",
+        0xC000 => "Set Vx = random byte AND kk",
+        0xD000 => "Draw an n byte sprite from I at Vx, Vy, VF = collision",
+        0xE000 => match opcode & 0xF0FF {
+            0xE09E => "Skip the next instruction if key Vx is pressed",
+            0xE0A1 => "Skip the next instruction if key Vx is not pressed",
+            _ => "Unknown instruction",
+        },
+        _ => match opcode & 0xF0FF {
+            0xF000 if opcode == 0xF000 => "Set I = the 16 bit address in the next word",
+            0xF001 => "Select the drawing planes n",
+            0xF002 if opcode == 0xF002 => "Load the 16 byte audio pattern from I",
+            0xF007 => "Set Vx = DT",
+            0xF00A => "Wait for a key press, store the key in Vx",
+            0xF015 => "Set DT = Vx",
+            0xF018 => "Set ST = Vx",
+            0xF01E => "Set I = I + Vx",
+            0xF029 => "Set I = address of the font sprite for digit Vx",
+            0xF033 => "Store the BCD digits of Vx at I, I + 1 and I + 2",
+            0xF03A => "Set the audio pattern's pitch = Vx",
+            0xF055 => "Store V0 to Vx in memory starting at I",
+            0xF065 => "Read V0 to Vx from memory starting at I",
+            _ => "Unknown instruction",
+        },
+    }
+}
+
+// A change an instruction is about to make, see preview
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Effect {
+    SetV(u8, u8),
+    SetI(u16),
+    SetPc(u16),
+    SetDt(u8),
+    SetSt(u8),
+    Push(u16),
+    ClearDisplay,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Effect::SetV(x, value) => write!(f, "V{:X} will become 0x{:02X}", x, value),
+            Effect::SetI(value) => write!(f, "I will become 0x{:03X}", value),
+            Effect::SetPc(addr) => write!(f, "PC \u{2192} 0x{:03X}", addr),
+            Effect::SetDt(value) => write!(f, "DT will become 0x{:02X}", value),
+            Effect::SetSt(value) => write!(f, "ST will become 0x{:02X}", value),
+            Effect::Push(addr) => write!(f, "0x{:03X} will be pushed on the stack", addr),
+            Effect::ClearDisplay => write!(f, "The display will be cleared"),
+        }
+    }
+}
+
+// What the instruction would change if it ran now, in the order it makes the
+// changes. Worked out from the state without executing anything. Only the
+// common arithmetic, load and jump instructions are covered, the rest give
+// an empty list.
+pub fn preview(state: &State, opcode: u16) -> Vec<Effect> {
+    let x = get_x(opcode) as u8;
+    let vx = state.v[x as usize];
+    let vy = state.v[get_y(opcode) as usize];
+    let byte = get_byte(opcode);
+    let addr = get_addr(opcode);
+    let next = state.pc.wrapping_add(2);
+    let skip_if = |condition: bool| Effect::SetPc(next.wrapping_add(2 * condition as u16));
+    let shift_source = if state.quirks.shift_uses_vy { vy } else { vx };
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => vec![Effect::ClearDisplay, Effect::SetPc(next)],
+            0x00EE if state.sp > 0 => {
+                let addr = state.stack[state.sp as usize - 1];
+                vec![Effect::SetPc(addr.wrapping_add(2))]
+            }
+            _ => vec![],
+        },
+        0x1000 => vec![Effect::SetPc(addr)],
+        0x2000 => vec![Effect::Push(state.pc), Effect::SetPc(addr)],
+        0x3000 => vec![skip_if(vx == byte)],
+        0x4000 => vec![skip_if(vx != byte)],
+        0x5000 if opcode & 0x000F == 0 => vec![skip_if(vx == vy)],
+        0x6000 => vec![Effect::SetV(x, byte), Effect::SetPc(next)],
+        0x7000 => vec![Effect::SetV(x, vx.wrapping_add(byte)), Effect::SetPc(next)],
+        0x8000 => {
+            let mut effects = match opcode & 0x000F {
+                0x0 => vec![Effect::SetV(x, vy)],
+                0x1 => vec![Effect::SetV(x, vx | vy)],
+                0x2 => vec![Effect::SetV(x, vx & vy)],
+                0x3 => vec![Effect::SetV(x, vx ^ vy)],
+                0x4 => {
+                    let (result, carry) = vx.overflowing_add(vy);
+                    vec![Effect::SetV(15, carry as u8), Effect::SetV(x, result)]
+                }
+                0x5 => {
+                    let (result, borrow) = vx.overflowing_sub(vy);
+                    vec![Effect::SetV(x, result), Effect::SetV(15, !borrow as u8)]
+                }
+                0x6 => vec![
+                    Effect::SetV(15, shift_source & 0x01),
+                    Effect::SetV(x, shift_source >> 1),
+                ],
+                0x7 => {
+                    let (result, borrow) = vy.overflowing_sub(vx);
+                    vec![Effect::SetV(x, result), Effect::SetV(15, !borrow as u8)]
+                }
+                0xE => vec![
+                    Effect::SetV(15, (shift_source & 0x80) >> 7),
+                    Effect::SetV(x, shift_source << 1),
+                ],
+                _ => return vec![],
+            };
+            effects.push(Effect::SetPc(next));
+            effects
+        }
+        0x9000 if opcode & 0x000F == 0 => vec![skip_if(vx != vy)],
+        0xA000 => vec![Effect::SetI(addr), Effect::SetPc(next)],
+        0xB000 => {
+            let offset = if state.quirks.jump_uses_vx {
+                vx
+            } else {
+                state.v[0]
+            };
+            vec![Effect::SetPc(offset as u16 + addr)]
+        }
+        0xF000 => {
+            let mut effects = match opcode & 0xF0FF {
+                0xF007 => vec![Effect::SetV(x, state.dt)],
+                0xF015 => vec![Effect::SetDt(vx)],
+                0xF018 => vec![Effect::SetSt(vx)],
+                0xF01E => {
+                    let i = state.i.wrapping_add(vx as u16);
+                    let mut effects = vec![Effect::SetI(i)];
+                    if state.quirks.add_i_sets_vf {
+                        effects.push(Effect::SetV(15, (i > 0xFFF) as u8));
+                    }
+                    effects
+                }
+                0xF029 => vec![Effect::SetI(vx as u16 * 5)],
+                _ => return vec![],
+            };
+            effects.push(Effect::SetPc(next));
+            effects
+        }
+        _ => vec![],
+    }
+}
+
+// Listing of a ROM loaded at origin, one `addr: mnemonic (opcode)` line per
+// word like the Code window. Every word is decoded, data included, and an odd
+// last byte is listed on its own.
+pub fn disassemble(rom: &[u8], origin: usize) -> String {
+    let mut listing = String::new();
+    let words: Vec<&[u8]> = rom.chunks(2).collect();
+    for (i, chunk) in words.iter().enumerate() {
+        let addr = origin + i * 2;
+        if chunk.len() == 2 {
+            let next_word = match words.get(i + 1) {
+                Some(next) if next.len() == 2 => Some((next[0] as u16) << 8 | next[1] as u16),
+                _ => None,
+            };
+            let instruction =
+                Instruction::decode((chunk[0] as u16) << 8 | chunk[1] as u16, next_word);
+            listing.push_str(&format!(
+                "{:04X}: {} ({:04X})\n",
+                addr, instruction.code, instruction.opcode
+            ));
+        } else {
+            listing.push_str(&format!("{:04X}: DATA {:02X}\n", addr, chunk[0]));
+        }
+    }
+    listing
+}
+
+// Nominal cost of an instruction in COSMAC VIP machine cycles (8 clocks at
+// 1.76MHz, ~4.54us each). Figures are averages, DRW and the memory ops vary
+// with their operands and Fx0A waits for a key, so it is given a fetch cost.
+pub fn cycle_cost(opcode: u16) -> u32 {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => 24,
+            _ => 23,
+        },
+        0x1000 | 0x2000 | 0xB000 => 23,
+        0x3000 | 0x4000 | 0xA000 => 12,
+        0x5000 | 0x9000 | 0xE000 => 16,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xC000 => 36,
+        0xD000 => 5007,
+        _ => match opcode & 0xF0FF {
+            0xF01E => 19,
+            0xF029 => 20,
+            0xF033 => 204,
+            0xF055 | 0xF065 => 133,
+            _ => 10,
+        },
+    }
+}
+
+// Records why an instruction failed and returns false to pass on
+fn fault(state: &mut State, error: ExecError) -> bool {
+    state.fault = Some(error);
+    false
+}
+
+// Registers Vx to Vy in the order 5xy2/5xy3 go through them, down when x is
+// the higher one
+fn register_range(opcode: u16) -> Vec<usize> {
+    let (x, y) = (get_x(opcode) as usize, get_y(opcode) as usize);
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
+}
+
 pub struct Instruction {
     pub opcode: u16,
     pub code: String,
@@ -39,6 +467,16 @@ impl fmt::Display for Instruction {
 }
 
 impl Instruction {
+    // Like new, with the word after the opcode for the instructions that take
+    // it as an operand so it shows in their code: F000 NNNN gets LD I, NNNN
+    pub fn decode(opcode: u16, next_word: Option<u16>) -> Instruction {
+        let mut instruction = Instruction::new(opcode);
+        if let (0xF000, Some(addr)) = (opcode, next_word) {
+            instruction.code = format!("LD I, {:04X}", addr);
+        }
+        instruction
+    }
+
     pub fn new(opcode: u16) -> Instruction {
         let opcode_single_id = opcode & 0xF000;
         let opcode_double_id = opcode & 0xF00F;
@@ -51,28 +489,47 @@ impl Instruction {
                     opcode: opcode,
                     code: String::from("CLS"),
                     function: Box::new(|_opcode, state| {
-                        state.display.reset();
-                        true
+                        state.display.clear_planes();
+                        state.advance_pc(2)
                     }),
                 },
                 // 0x00EE - RET
                 0x00EE => Instruction {
                     opcode: opcode,
                     code: String::from("RET"),
+                    function: Box::new(|_opcode, state| match state.pop() {
+                        Ok(addr) => {
+                            state.pc = addr;
+                            state.advance_pc(2)
+                        }
+                        Err(error) => fault(state, error),
+                    }),
+                },
+                // 00FD - EXIT (SUPER-CHIP)
+                0x00FD => Instruction {
+                    opcode: opcode,
+                    code: String::from("EXIT"),
                     function: Box::new(|_opcode, state| {
-                        state.pc = state.pop();
-                        state.pc += 2;
+                        state.exited = true;
+                        state.halted = true;
                         true
                     }),
                 },
+                // 0000 - Zeroed memory, a ROM that ran past its code. Shown as
+                // data and halts instead of jumping into the font as SYS 000.
+                0x0000 => Instruction {
+                    opcode: opcode,
+                    code: String::from("DATA 0000"),
+                    function: Box::new(|opcode, state| {
+                        state.halted = true;
+                        fault(state, ExecError::IllegalOpcode(opcode))
+                    }),
+                },
                 // 0nnn - SYS addr
                 _ => Instruction {
                     opcode: opcode,
                     code: String::from(format!("SYS {:03X}", get_addr(opcode))),
-                    function: Box::new(|opcode, state| {
-                        state.pc = get_addr(opcode);
-                        true
-                    }),
+                    function: Box::new(|opcode, state| state.jump(get_addr(opcode) as usize)),
                 },
             },
             // 1nnn - JMP addr
@@ -80,8 +537,13 @@ impl Instruction {
                 opcode: opcode,
                 code: String::from(format!("JMP {:03X}", get_nnn(opcode))),
                 function: Box::new(|opcode, state| {
-                    state.pc = get_nnn(opcode);
-                    true
+                    let addr = get_nnn(opcode);
+
+                    // A jump to itself is how most ROMs end, treat it as a halt
+                    if addr == state.pc {
+                        state.halted = true;
+                    }
+                    state.jump(addr as usize)
                 }),
             },
             // 2nnn - CALL addr
@@ -89,9 +551,10 @@ impl Instruction {
                 opcode: opcode,
                 code: String::from(format!("CALL {:03X}", get_addr(opcode))),
                 function: Box::new(|opcode, state| {
-                    state.push(state.pc);
-                    state.pc = get_addr(opcode);
-                    true
+                    if let Err(error) = state.push(state.pc) {
+                        return fault(state, error);
+                    }
+                    state.jump(get_addr(opcode) as usize)
                 }),
             },
             // 3xkk - SE Vx, byte
@@ -107,10 +570,10 @@ impl Instruction {
                     let byte = get_byte(opcode);
 
                     if state.v[x as usize] == byte {
-                        state.pc += 2;
+                        state.skip_next()
+                    } else {
+                        state.advance_pc(2)
                     }
-                    state.pc += 2;
-                    true
                 }),
             },
             // 4xkk - SNE Vx, byte
@@ -126,26 +589,82 @@ impl Instruction {
                     let byte = get_byte(opcode);
 
                     if state.v[x as usize] != byte {
-                        state.pc += 2;
+                        state.skip_next()
+                    } else {
+                        state.advance_pc(2)
                     }
-                    state.pc += 2;
-                    true
                 }),
             },
             // 5xkk - SE Vx, Vy
-            0x5000 => Instruction {
-                opcode: opcode,
-                code: String::from(format!("SE V{:01X}, V{:01X}", get_x(opcode), get_y(opcode))),
-                function: Box::new(|opcode, state| {
-                    let x = get_x(opcode);
-                    let y = get_y(opcode);
+            0x5000 => match opcode_double_id {
+                // 5xy0 - SE Vx, Vy
+                0x5000 => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!(
+                        "SE V{:01X}, V{:01X}",
+                        get_x(opcode),
+                        get_y(opcode)
+                    )),
+                    function: Box::new(|opcode, state| {
+                        let x = get_x(opcode);
+                        let y = get_y(opcode);
 
-                    if state.v[x as usize] == state.v[y as usize] {
-                        state.pc += 2;
-                    }
-                    state.pc += 2;
-                    true
-                }),
+                        let skip = state.v[x as usize] == state.v[y as usize];
+                        if skip {
+                            state.skip_next()
+                        } else {
+                            state.advance_pc(2)
+                        }
+                    }),
+                },
+                // 5xy2 - LD [I], Vx-Vy (XO-CHIP)
+                0x5002 => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!(
+                        "LD [I], V{:01X}-V{:01X}",
+                        get_x(opcode),
+                        get_y(opcode)
+                    )),
+                    function: Box::new(|opcode, state| {
+                        if !state.quirks.register_ranges {
+                            return fault(state, ExecError::IllegalOpcode(opcode));
+                        }
+                        for (offset, x) in register_range(opcode).into_iter().enumerate() {
+                            if let Err(error) = state.write(state.i as usize + offset, state.v[x]) {
+                                return fault(state, error);
+                            }
+                        }
+                        state.advance_pc(2)
+                    }),
+                },
+                // 5xy3 - LD Vx-Vy, [I] (XO-CHIP)
+                0x5003 => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!(
+                        "LD V{:01X}-V{:01X}, [I]",
+                        get_x(opcode),
+                        get_y(opcode)
+                    )),
+                    function: Box::new(|opcode, state| {
+                        if !state.quirks.register_ranges {
+                            return fault(state, ExecError::IllegalOpcode(opcode));
+                        }
+                        for (offset, x) in register_range(opcode).into_iter().enumerate() {
+                            match state.read(state.i as usize + offset) {
+                                Ok(value) => state.v[x] = value,
+                                Err(error) => return fault(state, error),
+                            }
+                        }
+                        state.advance_pc(2)
+                    }),
+                },
+                _ => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!("Unknonw instruction: {:04X}", opcode)),
+                    function: Box::new(|opcode, state| {
+                        fault(state, ExecError::IllegalOpcode(opcode))
+                    }),
+                },
             },
             // 6xkk - LD Vx, byte
             0x6000 => Instruction {
@@ -160,8 +679,7 @@ impl Instruction {
                     let byte = get_byte(opcode);
 
                     state.v[x as usize] = byte;
-                    state.pc += 2;
-                    true
+                    state.advance_pc(2)
                 }),
             },
             // 7xkk - ADD Vx, byte
@@ -177,8 +695,7 @@ impl Instruction {
                     let byte = get_byte(opcode);
 
                     state.v[x as usize] = (state.v[x as usize] as u16 + byte as u16) as u8;
-                    state.pc += 2;
-                    true
+                    state.advance_pc(2)
                 }),
             },
             0x8000 => match opcode_double_id {
@@ -195,8 +712,7 @@ impl Instruction {
                         let y = get_y(opcode);
 
                         state.v[x as usize] = state.v[y as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy1 - OR Vx, Vy
@@ -212,8 +728,7 @@ impl Instruction {
                         let y = get_y(opcode);
 
                         state.v[x as usize] |= state.v[y as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy2 - AND Vx, Vy
@@ -229,8 +744,7 @@ impl Instruction {
                         let y = get_y(opcode);
 
                         state.v[x as usize] &= state.v[y as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy3 - XOR Vx, Vy
@@ -246,8 +760,7 @@ impl Instruction {
                         let y = get_y(opcode);
 
                         state.v[x as usize] ^= state.v[y as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy4 - ADD Vx, Vy
@@ -268,8 +781,7 @@ impl Instruction {
                             state.v[15] = 0;
                         }
                         state.v[x as usize] = result as u8;
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy5 - SUB Vx, Vy
@@ -284,29 +796,33 @@ impl Instruction {
                         let x = get_x(opcode);
                         let y = get_y(opcode);
 
-                        if state.v[x as usize] > state.v[y as usize] {
-                            state.v[15] = 1;
-                        } else {
-                            state.v[15] = 0;
-                        }
-
-                        let result: i8 = state.v[x as usize] as i8 - state.v[y as usize] as i8;
-                        state.v[x as usize] = result as u8;
-                        state.pc += 2;
-                        true
+                        // VF = NOT borrow, 1 when Vx >= Vy
+                        let (result, borrow) =
+                            state.v[x as usize].overflowing_sub(state.v[y as usize]);
+                        state.v[x as usize] = result;
+                        state.v[15] = !borrow as u8;
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy6 - SHR Vx {, Vy}
                 0x8006 => Instruction {
                     opcode: opcode,
-                    code: String::from(format!("SHR V{:01X}", get_x(opcode))),
+                    code: String::from(format!(
+                        "SHR V{:01X}, V{:01X}",
+                        get_x(opcode),
+                        get_y(opcode)
+                    )),
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
+                        let source = if state.quirks.shift_uses_vy {
+                            state.v[get_y(opcode) as usize]
+                        } else {
+                            state.v[x as usize]
+                        };
 
-                        state.v[15] = state.v[x as usize] & 0x01;
-                        state.v[x as usize] >>= 1;
-                        state.pc += 2;
-                        true
+                        state.v[15] = source & 0x01;
+                        state.v[x as usize] = source >> 1;
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xy7 - SUBN Vx, Vy
@@ -321,38 +837,40 @@ impl Instruction {
                         let x = get_x(opcode);
                         let y = get_y(opcode);
 
-                        if state.v[y as usize] > state.v[x as usize] {
-                            state.v[15] = 1;
-                        } else {
-                            state.v[15] = 0;
-                        }
-
-                        let result: i8 = state.v[y as usize] as i8 - state.v[x as usize] as i8;
-                        state.v[x as usize] = result as u8;
-                        state.pc += 2;
-                        true
+                        // VF = NOT borrow, 1 when Vy >= Vx
+                        let (result, borrow) =
+                            state.v[y as usize].overflowing_sub(state.v[x as usize]);
+                        state.v[x as usize] = result;
+                        state.v[15] = !borrow as u8;
+                        state.advance_pc(2)
                     }),
                 },
                 // 8xyE - SHL Vx {, Vy}
                 0x800E => Instruction {
                     opcode: opcode,
-                    code: String::from(format!("SHL V{:01X}", get_x(opcode))),
+                    code: String::from(format!(
+                        "SHL V{:01X}, V{:01X}",
+                        get_x(opcode),
+                        get_y(opcode)
+                    )),
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
+                        let source = if state.quirks.shift_uses_vy {
+                            state.v[get_y(opcode) as usize]
+                        } else {
+                            state.v[x as usize]
+                        };
 
-                        state.v[15] = (state.v[x as usize] & 0x80) >> 7;
-                        state.v[x as usize] <<= 1;
-                        state.pc += 2;
-                        true
+                        state.v[15] = (source & 0x80) >> 7;
+                        state.v[x as usize] = source << 1;
+                        state.advance_pc(2)
                     }),
                 },
                 _ => Instruction {
                     opcode: opcode,
                     code: String::from(format!("Unknonw instruction: {:04X}", opcode)),
                     function: Box::new(|opcode, state| {
-                        println!("Unknown instruction: {:04X}", opcode);
-                        println!("State: {:#?}", state);
-                        false
+                        fault(state, ExecError::IllegalOpcode(opcode))
                     }),
                 },
             },
@@ -369,9 +887,10 @@ impl Instruction {
                     let y = get_y(opcode);
 
                     if state.v[x as usize] != state.v[y as usize] {
-                        state.pc += 2;
+                        state.skip_next()
+                    } else {
+                        state.advance_pc(2)
                     }
-                    true
                 }),
             },
             // Annn - LD I, addr
@@ -382,8 +901,7 @@ impl Instruction {
                     let addr = get_addr(opcode);
 
                     state.i = addr;
-                    state.pc += 2;
-                    true
+                    state.advance_pc(2)
                 }),
             },
             // Bnnn - JP V0, addr
@@ -392,9 +910,13 @@ impl Instruction {
                 code: String::from(format!("JP V0, {:03X}", get_addr(opcode))),
                 function: Box::new(|opcode, state| {
                     let addr = get_addr(opcode);
+                    let offset = if state.quirks.jump_uses_vx {
+                        state.v[get_x(opcode) as usize]
+                    } else {
+                        state.v[0]
+                    };
 
-                    state.pc = state.v[0] as u16 + addr;
-                    true
+                    state.jump(offset as usize + addr as usize)
                 }),
             },
             // Cxkk - RND Vx, byte
@@ -409,9 +931,8 @@ impl Instruction {
                     let x = get_x(opcode);
                     let byte = get_byte(opcode);
 
-                    state.v[x as usize] = rand::thread_rng().gen_range(0, 256) as u8 & byte;
-                    state.pc += 2;
-                    true
+                    state.v[x as usize] = state.random_byte() & byte;
+                    state.advance_pc(2)
                 }),
             },
             // Dxyn - DRW Vx, Vy, nibble
@@ -427,16 +948,37 @@ impl Instruction {
                     let x = get_x(opcode);
                     let y = get_y(opcode);
                     let nibble = get_nibble(opcode);
-                    let sprite = &state.ram[(state.i as usize)..(state.i + nibble) as usize];
-
-                    state.v[15] = state.display.display_sprite(
-                        state.v[x as usize],
-                        state.v[y as usize],
-                        sprite,
-                    ) as u8;
-
-                    state.pc += 2;
-                    true
+                    let len = state.display.sprite_len(nibble as usize);
+                    let sprite = match state.read_bytes(state.i as usize, len) {
+                        Ok(sprite) => sprite,
+                        Err(ExecError::AddressOutOfRange(_)) => {
+                            let error = ExecError::SpriteOutOfBounds {
+                                i: state.i,
+                                height: nibble as u8,
+                            };
+                            return fault(state, error);
+                        }
+                        Err(error) => return fault(state, error),
+                    };
+
+                    // The starting position always wraps onto the screen,
+                    // the quirks only decide what happens to the pixels past
+                    // the edges
+                    let vx = state.v[x as usize] % 64;
+                    let vy = state.v[y as usize] % 32;
+                    let plane_mask = state.display.plane_mask;
+                    let result = state.display.draw(
+                        vx,
+                        vy,
+                        &sprite,
+                        plane_mask,
+                        state.quirks.wrap_x,
+                        state.quirks.wrap_y,
+                    );
+                    state.v[15] = result.collided as u8;
+
+                    state.waiting_for_vblank = state.quirks.display_wait;
+                    state.advance_pc(2)
                 }),
             },
             0xE000 => match opcode_tripple_id {
@@ -445,13 +987,13 @@ impl Instruction {
                     opcode: opcode,
                     code: String::from(format!("SKP V{:01X}", get_x(opcode))),
                     function: Box::new(|opcode, state| {
-                        let x = get_x(opcode);
-                        if state.keypad[state.v[x as usize] as usize] {
-                            state.pc += 2;
+                        // Only the low nibble selects the key, like the VIP
+                        let key = state.v[get_x(opcode) as usize] & 0x0F;
+                        if state.keypad[key as usize] {
+                            state.skip_next()
+                        } else {
+                            state.advance_pc(2)
                         }
-
-                        state.pc += 2;
-                        true
                     }),
                 },
                 // ExA1 - SKNP Vx
@@ -459,26 +1001,66 @@ impl Instruction {
                     opcode: opcode,
                     code: String::from(format!("SKNP V{:01X}", get_x(opcode))),
                     function: Box::new(|opcode, state| {
-                        let x = get_x(opcode);
-                        if !state.keypad[state.v[x as usize] as usize] {
-                            state.pc += 2;
+                        // Only the low nibble selects the key, like the VIP
+                        let key = state.v[get_x(opcode) as usize] & 0x0F;
+                        if !state.keypad[key as usize] {
+                            state.skip_next()
+                        } else {
+                            state.advance_pc(2)
                         }
-
-                        state.pc += 2;
-                        true
                     }),
                 },
                 _ => Instruction {
                     opcode: opcode,
                     code: String::from(format!("Unknonw instruction: {:04X}", opcode)),
                     function: Box::new(|opcode, state| {
-                        println!("Unknown instruction: {:04X}", opcode);
-                        println!("State: {:#?}", state);
-                        false
+                        fault(state, ExecError::IllegalOpcode(opcode))
                     }),
                 },
             },
             0xF000 => match opcode_tripple_id {
+                // F000 NNNN - LD I, long addr (XO-CHIP)
+                // The address is stored in the word following the instruction
+                0xF000 if opcode == 0xF000 => Instruction {
+                    opcode: opcode,
+                    code: String::from("LD I, long"),
+                    function: Box::new(|_opcode, state| {
+                        let addr = match state.read_bytes(state.pc as usize + 2, 2) {
+                            Ok(addr) => addr,
+                            Err(error) => return fault(state, error),
+                        };
+                        state.i = (addr[0] as u16) << 8 | addr[1] as u16;
+                        state.advance_pc(4)
+                    }),
+                },
+                // Fn01 - PLANE n (XO-CHIP)
+                0xF001 => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!("PLANE {:01X}", get_x(opcode))),
+                    function: Box::new(|opcode, state| {
+                        state.display.select_plane(get_x(opcode) as u8);
+                        state.advance_pc(2)
+                    }),
+                },
+                // F002 - AUDIO (XO-CHIP)
+                // The pattern is played instead of the beep while ST > 0
+                0xF002 if opcode == 0xF002 => Instruction {
+                    opcode: opcode,
+                    code: String::from("AUDIO"),
+                    function: Box::new(|opcode, state| {
+                        if !state.quirks.xo_audio {
+                            return fault(state, ExecError::IllegalOpcode(opcode));
+                        }
+                        let bytes = match state.read_bytes(state.i as usize, 16) {
+                            Ok(bytes) => bytes,
+                            Err(error) => return fault(state, error),
+                        };
+                        let mut pattern = [0u8; 16];
+                        pattern.copy_from_slice(&bytes);
+                        state.audio_pattern = Some(pattern);
+                        state.advance_pc(2)
+                    }),
+                },
                 // Fx07 - LD Vx, DT
                 0xF007 => Instruction {
                     opcode: opcode,
@@ -486,8 +1068,7 @@ impl Instruction {
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
                         state.v[x as usize] = state.dt;
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx0A - LD Vx, K
@@ -495,12 +1076,10 @@ impl Instruction {
                     opcode: opcode,
                     code: String::from(format!("LD V{:01X}, K", get_x(opcode))),
                     function: Box::new(|opcode, state| {
-                        let x = get_x(opcode);
-                        for (i, k) in state.keypad.iter().enumerate() {
-                            if *k {
-                                state.v[x as usize] = i as u8;
-                                state.pc += 2;
-                            }
+                        // Blocks by not advancing until a key is held
+                        if let Some(key) = state.held_key() {
+                            state.v[get_x(opcode) as usize] = key;
+                            return state.advance_pc(2);
                         }
                         true
                     }),
@@ -512,8 +1091,7 @@ impl Instruction {
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
                         state.dt = state.v[x as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx18 - LD ST, Vx
@@ -523,8 +1101,7 @@ impl Instruction {
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
                         state.st = state.v[x as usize];
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx1E - ADD I, Vx
@@ -533,9 +1110,12 @@ impl Instruction {
                     code: String::from(format!("ADD I, V{:01X}", get_x(opcode))),
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
-                        state.i += state.v[x as usize] as u16;
-                        state.pc += 2;
-                        true
+                        // I can be anywhere in 64K of XO-CHIP RAM
+                        state.i = state.i.wrapping_add(state.v[x as usize] as u16);
+                        if state.quirks.add_i_sets_vf {
+                            state.v[15] = (state.i > 0xFFF) as u8;
+                        }
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx29 - LD F, Vx
@@ -545,8 +1125,7 @@ impl Instruction {
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
                         state.i = (state.v[x as usize] * 5) as u16; // Sprites are 8 x 5
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx33 - LD B, Vx
@@ -557,11 +1136,24 @@ impl Instruction {
                         let x = get_x(opcode);
                         let mut data = state.v[x as usize];
                         for i in (0..3).rev() {
-                            state.ram[(state.i + i) as usize] = data % 10;
+                            if let Err(error) = state.write(state.i as usize + i, data % 10) {
+                                return fault(state, error);
+                            }
                             data /= 10;
                         }
-                        state.pc += 2;
-                        true
+                        state.advance_pc(2)
+                    }),
+                },
+                // Fx3A - PITCH Vx (XO-CHIP)
+                0xF03A => Instruction {
+                    opcode: opcode,
+                    code: String::from(format!("PITCH V{:01X}", get_x(opcode))),
+                    function: Box::new(|opcode, state| {
+                        if !state.quirks.xo_audio {
+                            return fault(state, ExecError::IllegalOpcode(opcode));
+                        }
+                        state.pitch = state.v[get_x(opcode) as usize];
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx55 - LD [I], Vx
@@ -570,11 +1162,15 @@ impl Instruction {
                     code: String::from(format!("LD [I], V{:01X}", get_x(opcode))),
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
-                        for i in 0..(x + 1) {
-                            state.ram[(state.i + i) as usize] = state.v[i as usize];
+                        for i in 0..(x as usize + 1) {
+                            if let Err(error) = state.write(state.i as usize + i, state.v[i]) {
+                                return fault(state, error);
+                            }
                         }
-                        state.pc += 2;
-                        true
+                        if state.quirks.load_store_increments_i {
+                            state.i = state.i.wrapping_add(x + 1);
+                        }
+                        state.advance_pc(2)
                     }),
                 },
                 // Fx65 - LD Vx, [I]
@@ -583,35 +1179,40 @@ impl Instruction {
                     code: String::from(format!("LD V{:01X}, [I]", get_x(opcode))),
                     function: Box::new(|opcode, state| {
                         let x = get_x(opcode);
-                        for i in 0..(x + 1) {
-                            state.v[i as usize] = state.ram[(state.i + i) as usize];
+                        for i in 0..(x as usize + 1) {
+                            match state.read(state.i as usize + i) {
+                                Ok(value) => state.v[i] = value,
+                                Err(error) => return fault(state, error),
+                            }
                         }
-                        state.pc += 2;
-                        true
+                        if state.quirks.load_store_increments_i {
+                            state.i = state.i.wrapping_add(x + 1);
+                        }
+                        state.advance_pc(2)
                     }),
                 },
                 _ => Instruction {
                     opcode: opcode,
                     code: String::from(format!("Unknonw instruction: {:04X}", opcode)),
                     function: Box::new(|opcode, state| {
-                        println!("Unknown instruction: {:04X}", opcode);
-                        println!("State: {:#?}", state);
-                        false
+                        fault(state, ExecError::IllegalOpcode(opcode))
                     }),
                 },
             },
             _ => Instruction {
                 opcode: opcode,
                 code: String::from(format!("Unknonw instruction: {:04X}", opcode)),
-                function: Box::new(|opcode, state| {
-                    println!("Unknown instruction: {:04X}", opcode);
-                    println!("State: {:#?}", state);
-                    false
-                }),
+                function: Box::new(|opcode, state| fault(state, ExecError::IllegalOpcode(opcode))),
             },
         }
     }
 
+    // Pattern and mnemonic of every implemented instruction, lowercase
+    // letters in the pattern are operand nibbles
+    pub fn supported_opcodes() -> Vec<(&'static str, &'static str)> {
+        SUPPORTED_OPCODES.to_vec()
+    }
+
     pub fn parse_chunk(data: Vec<u16>) -> Vec<Instruction> {
         let mut program: Vec<Instruction> = Vec::with_capacity(data.len());
         for opcode in data {
@@ -624,6 +1225,14 @@ impl Instruction {
     pub fn function(&self, state: &mut State) -> bool {
         (self.function)(self.opcode, state)
     }
+
+    pub fn operands(&self) -> Operands {
+        decode_operands(self.opcode)
+    }
+
+    pub fn cycles(&self) -> u32 {
+        cycle_cost(self.opcode)
+    }
 }
 
 #[cfg(test)]
@@ -644,7 +1253,7 @@ mod tests {
         let mut state = State::new();
         let instruction = Instruction::new(0x00EE);
         state.pc = 0xA;
-        state.push(0xB);
+        state.push(0xB).unwrap();
 
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(0xD, state.pc);
@@ -663,6 +1272,19 @@ mod tests {
         assert_eq!(false, state.display.is_clear());
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(true, state.display.is_clear());
+        assert_eq!(0x202, state.pc);
+    }
+
+    #[test]
+    // 00FD - EXIT
+    fn exit() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0x00FD);
+
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(true, state.exited);
+        assert_eq!(true, state.halted);
+        assert_eq!(0x200, state.pc);
     }
 
     #[test]
@@ -673,6 +1295,18 @@ mod tests {
 
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(0xABC, state.pc);
+        assert_eq!(false, state.halted);
+    }
+
+    #[test]
+    // 1nnn - JMP addr, jumping to itself
+    fn jmp_self() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0x1200);
+
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x200, state.pc);
+        assert_eq!(true, state.halted);
     }
 
     #[test]
@@ -686,7 +1320,23 @@ mod tests {
         assert_eq!(0, state.sp);
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(0xABC, state.pc);
-        assert_eq!(0xAAA, state.pop());
+        assert_eq!(Ok(0xAAA), state.pop());
+    }
+
+    #[test]
+    // 2nnn - CALL addr past the stack depth, 00EE - RET with an empty stack
+    fn call_ret_stack_limits() {
+        let mut state = State::new();
+
+        state.set_stack_depth(1);
+        assert_eq!(true, Instruction::new(0x2ABC).function(&mut state));
+        assert_eq!(false, Instruction::new(0x2ABC).function(&mut state));
+        assert_eq!(Some(ExecError::StackOverflow), state.fault);
+        assert_eq!(0xABC, state.pc);
+
+        assert_eq!(true, Instruction::new(0x00EE).function(&mut state));
+        assert_eq!(false, Instruction::new(0x00EE).function(&mut state));
+        assert_eq!(Some(ExecError::StackUnderflow), state.fault);
     }
 
     #[test]
@@ -731,6 +1381,60 @@ mod tests {
         assert_eq!(0x206, state.pc);
     }
 
+    #[test]
+    // 5xy2 - LD [I], Vx-Vy
+    fn ld_i_vx_vy() {
+        let mut state = State::new();
+        state.v[2] = 0x22;
+        state.v[3] = 0x33;
+        state.v[4] = 0x44;
+        state.i = 0x300;
+
+        // Illegal unless the XO-CHIP quirk is on
+        assert_eq!(false, Instruction::new(0x5242).function(&mut state));
+        assert_eq!(Some(ExecError::IllegalOpcode(0x5242)), state.fault);
+        state.quirks.register_ranges = true;
+
+        // Ascending, I is left alone
+        assert_eq!(true, Instruction::new(0x5242).function(&mut state));
+        assert_eq!([0x22, 0x33, 0x44], state.ram[0x300..0x303]);
+        assert_eq!(0x300, state.i);
+        assert_eq!(0x202, state.pc);
+
+        // Descending, V4 ends up first
+        state.i = 0x310;
+        assert_eq!(true, Instruction::new(0x5422).function(&mut state));
+        assert_eq!([0x44, 0x33, 0x22], state.ram[0x310..0x313]);
+        assert_eq!(0x310, state.i);
+    }
+
+    #[test]
+    // 5xy3 - LD Vx-Vy, [I]
+    fn ld_vx_vy_i() {
+        let mut state = State::new();
+        state.quirks.register_ranges = true;
+        state.ram[0x300] = 0xAA;
+        state.ram[0x301] = 0xBB;
+        state.ram[0x302] = 0xCC;
+        state.i = 0x300;
+
+        // Ascending
+        assert_eq!(true, Instruction::new(0x5133).function(&mut state));
+        assert_eq!([0xAA, 0xBB, 0xCC], state.v[1..4]);
+        assert_eq!(0x300, state.i);
+
+        // Descending, the first byte goes to Vx
+        assert_eq!(true, Instruction::new(0x5A83).function(&mut state));
+        assert_eq!([0xCC, 0xBB, 0xAA], state.v[8..11]);
+        assert_eq!(0x300, state.i);
+
+        // A single register when x = y
+        assert_eq!(true, Instruction::new(0x5EE3).function(&mut state));
+        assert_eq!(0xAA, state.v[0xE]);
+        // The other low nibbles are still illegal
+        assert_eq!(false, Instruction::new(0x5121).function(&mut state));
+    }
+
     #[test]
     // 6xkk - LD Vx, byte
     fn ld_vx_byte() {
@@ -834,18 +1538,43 @@ mod tests {
         // with underflow
         state.v[1] = 0xF0;
         state.v[2] = 0xFF;
-        let expected = (state.v[1] as i8 - state.v[2] as i8) as u8;
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(expected, state.v[1]);
+        assert_eq!(0xF1, state.v[1]);
         assert_eq!(0, state.v[15]);
 
         // without underflow
         state.v[1] = 0xFF;
         state.v[2] = 0xF0;
-        let expected = state.v[1] - state.v[2];
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(expected, state.v[1]);
+        assert_eq!(0x0F, state.v[1]);
+        assert_eq!(1, state.v[15]);
+
+        // across the sign bit, signed arithmetic would overflow here
+        state.v[1] = 0x80;
+        state.v[2] = 0x01;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x7F, state.v[1]);
         assert_eq!(1, state.v[15]);
+
+        state.v[1] = 0x7F;
+        state.v[2] = 0x80;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0xFF, state.v[1]);
+        assert_eq!(0, state.v[15]);
+
+        // equal values don't borrow
+        state.v[1] = 0xA0;
+        state.v[2] = 0xA0;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0, state.v[1]);
+        assert_eq!(1, state.v[15]);
+
+        // VF as Vx ends up holding the flag
+        let instruction = Instruction::new(0x8F25); // VF -= V2
+        state.v[15] = 0x10;
+        state.v[2] = 0x20;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0, state.v[15]);
     }
 
     #[test]
@@ -862,6 +1591,21 @@ mod tests {
         assert_eq!(0, state.v[15]);
     }
 
+    #[test]
+    // 8xy6 - SHR Vx {, Vy} with the shift quirk
+    fn shr_vx_vy_quirk() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0x8126); // V1 = V2 >> 1
+
+        state.quirks.shift_uses_vy = true;
+        state.v[1] = 0x10;
+        state.v[2] = 0x03;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(1, state.v[15]);
+        assert_eq!(1, state.v[1]);
+        assert_eq!(3, state.v[2]);
+    }
+
     #[test]
     // 8xy7 - SUBN Vx, Vy
     // Vx = Vy - Vx
@@ -872,17 +1616,35 @@ mod tests {
         // with underflow
         state.v[1] = 0xFF;
         state.v[2] = 0xF0;
-        let expected = (state.v[2] as i8 - state.v[1] as i8) as u8;
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(expected, state.v[1]);
+        assert_eq!(0xF1, state.v[1]);
         assert_eq!(0, state.v[15]);
 
         // without underflow
         state.v[1] = 0xF0;
         state.v[2] = 0xFF;
-        let expected = state.v[2] - state.v[1];
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(expected, state.v[1]);
+        assert_eq!(0x0F, state.v[1]);
+        assert_eq!(1, state.v[15]);
+
+        // across the sign bit, signed arithmetic would overflow here
+        state.v[1] = 0x01;
+        state.v[2] = 0x80;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x7F, state.v[1]);
+        assert_eq!(1, state.v[15]);
+
+        state.v[1] = 0x80;
+        state.v[2] = 0x7F;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0xFF, state.v[1]);
+        assert_eq!(0, state.v[15]);
+
+        // equal values don't borrow
+        state.v[1] = 0xA0;
+        state.v[2] = 0xA0;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0, state.v[1]);
         assert_eq!(1, state.v[15]);
     }
 
@@ -907,11 +1669,11 @@ mod tests {
         let instruction = Instruction::new(0x9010); // V0 != V1
 
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(0x200, state.pc);
+        assert_eq!(0x202, state.pc);
 
         state.v[0] = 0xAA;
         assert_eq!(true, instruction.function(&mut state));
-        assert_eq!(0x202, state.pc);
+        assert_eq!(0x206, state.pc);
     }
 
     #[test]
@@ -933,6 +1695,19 @@ mod tests {
         state.v[0] = 0x0A;
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(0xAAA, state.pc);
+
+        // Bxnn with the jump quirk, x == A
+        state.quirks.jump_uses_vx = true;
+        state.v[0xA] = 0x01;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0xAA1, state.pc);
+
+        // Past the end of RAM goes through the PC overflow policy
+        state.quirks.jump_uses_vx = false;
+        state.v[0] = 0xFF;
+        assert_eq!(false, Instruction::new(0xBFFF).function(&mut state));
+        assert_eq!(Some(ExecError::PcOutOfRange(0x10FE)), state.fault);
+        assert_eq!(0xAA1, state.pc);
     }
 
     // Cxkk - RND Vx, byte
@@ -952,6 +1727,51 @@ mod tests {
         assert_eq!(true, state.display.is_clear());
     }
 
+    #[test]
+    fn drw_sprite_out_of_bounds() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0xD12F); // V1, V2, 15 bytes high
+
+        // Only 4 of the 15 rows are left in RAM
+        state.i = 0xFFC;
+        assert_eq!(false, instruction.function(&mut state));
+        assert_eq!(
+            Some(ExecError::SpriteOutOfBounds {
+                i: 0xFFC,
+                height: 15
+            }),
+            state.fault
+        );
+        assert_eq!(
+            "Sprite 15 rows high at I 0FFC reads past the end of RAM",
+            state.fault.unwrap().to_string()
+        );
+        assert_eq!(true, state.display.is_clear());
+        assert_eq!(0x200, state.pc);
+
+        // With mem_wrap the rows past the end come from the start of RAM
+        state.fault = None;
+        state.quirks.mem_wrap = true;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(None, state.fault);
+    }
+
+    #[test]
+    fn drw_wrapped_origin() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0xD125); // V1, V2, 5 bytes high
+
+        // 70 on a 64 pixel wide screen starts the "0" at x 6, also with
+        // clipping where the pixels past the edge are dropped
+        state.quirks.wrap_x = false;
+        state.v[1] = 70;
+        state.v[2] = 33;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(vec![0, 1, 1, 1, 1, 0], state.display.get_region(5, 1, 6, 1));
+        assert_eq!(1, state.display.data[5][9]);
+        assert_eq!(14, state.display.count_lit_pixels());
+    }
+
     #[test]
     // Ex9E - SKP Vx
     fn skp_vx() {
@@ -980,6 +1800,34 @@ mod tests {
         assert_eq!(0x206, state.pc);
     }
 
+    #[test]
+    // F000 NNNN - LD I, long addr
+    fn ld_i_long() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0xF000);
+
+        state.ram[0x200..0x204].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x1234, state.i);
+        assert_eq!(0x204, state.pc);
+
+        // The address word would be past the end of RAM
+        state.pc = (state.ram.len() - 2) as u16;
+        assert_eq!(false, instruction.function(&mut state));
+        assert_eq!(0x1234, state.i);
+    }
+
+    #[test]
+    // Fn01 - PLANE n
+    fn plane_n() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0xF301);
+
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(3, state.display.plane_mask);
+        assert_eq!(0x202, state.pc);
+    }
+
     #[test]
     // Fx07 - LD Vx, DT
     fn ld_vx_dt() {
@@ -997,11 +1845,32 @@ mod tests {
         let mut state = State::new();
         let instruction = Instruction::new(0xF10A); // V1
 
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x200, state.pc);
+
         state.keypad[0xA] = true;
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(state.v[1], 0xA);
     }
 
+    #[test]
+    fn ld_vx_k_latest_key() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0xF10A); // V1
+
+        state.set_key(7, true);
+        state.set_key(3, true);
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(3, state.v[1]);
+        assert_eq!(0x202, state.pc);
+
+        // Releasing the latest key falls back to the other held key
+        state.set_key(3, false);
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(7, state.v[1]);
+        assert_eq!(0x204, state.pc);
+    }
+
     #[test]
     // Fx15 - LD DT, Vx
     fn ld_dt_vx() {
@@ -1034,6 +1903,11 @@ mod tests {
         state.i = 2;
         assert_eq!(true, instruction.function(&mut state));
         assert_eq!(3, state.i);
+
+        state.i = 0xFFFF;
+        state.v[1] = 2;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(1, state.i);
     }
 
     #[test]
@@ -1047,6 +1921,34 @@ mod tests {
         assert_eq!(state.v[1] * 5, state.i as u8); // Sprites are 8 x 5
     }
 
+    #[test]
+    // F002 - AUDIO, Fx3A - PITCH Vx
+    fn audio_pitch() {
+        let mut state = State::new();
+
+        // Illegal unless the XO-CHIP quirk is on
+        assert_eq!(false, Instruction::new(0xF002).function(&mut state));
+        assert_eq!(Some(ExecError::IllegalOpcode(0xF002)), state.fault);
+        assert_eq!(false, Instruction::new(0xF33A).function(&mut state));
+        assert_eq!(Some(ExecError::IllegalOpcode(0xF33A)), state.fault);
+        state.fault = None;
+        state.quirks.xo_audio = true;
+
+        state.i = 0x300;
+        state.ram[0x300] = 0xAA;
+        state.ram[0x30F] = 0x55;
+        assert_eq!(None, state.audio_pattern);
+        assert_eq!(true, Instruction::new(0xF002).function(&mut state));
+        let pattern = state.audio_pattern.unwrap();
+        assert_eq!(0xAA, pattern[0]);
+        assert_eq!(0x55, pattern[15]);
+
+        state.v[3] = 112;
+        assert_eq!(true, Instruction::new(0xF33A).function(&mut state));
+        assert_eq!(112, state.pitch);
+        assert_eq!(0x204, state.pc);
+    }
+
     #[test]
     // Fx33 - LD B, Vx
     fn ld_b_vx() {
@@ -1093,5 +1995,217 @@ mod tests {
         for i in 0..0xA {
             assert_eq!(i as u8, state.v[i]);
         }
+        assert_eq!(0x256, state.i);
+
+        state.quirks.load_store_increments_i = true;
+        assert_eq!(true, instruction.function(&mut state));
+        assert_eq!(0x256 + 0xB, state.i);
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(Operands::None, Instruction::new(0x00E0).operands());
+        assert_eq!(Operands::Addr(0xABC), Instruction::new(0x1ABC).operands());
+        assert_eq!(Operands::Addr(0x123), Instruction::new(0xA123).operands());
+        assert_eq!(
+            Operands::VxByte { x: 1, byte: 0xAA },
+            Instruction::new(0x61AA).operands()
+        );
+        assert_eq!(
+            Operands::Vxy { x: 1, y: 2 },
+            Instruction::new(0x8124).operands()
+        );
+        assert_eq!(
+            Operands::Vxyn { x: 1, y: 2, n: 5 },
+            Instruction::new(0xD125).operands()
+        );
+        assert_eq!(Operands::Vx { x: 3 }, Instruction::new(0xF333).operands());
+        assert_eq!(Operands::Nibble(2), Instruction::new(0xF201).operands());
+    }
+
+    #[test]
+    fn skp_key_out_of_range() {
+        let mut state = State::new();
+
+        state.v[1] = 0xFF;
+        state.keypad[0xF] = true;
+        assert_eq!(true, Instruction::new(0xE19E).function(&mut state));
+        assert_eq!(0x204, state.pc);
+        assert_eq!(true, Instruction::new(0xE1A1).function(&mut state));
+        assert_eq!(0x206, state.pc);
+    }
+
+    #[test]
+    fn memory_past_end() {
+        let mut state = State::new();
+        let store = Instruction::new(0xF155); // LD [I], V1
+        let load = Instruction::new(0xF165); // LD V1, [I]
+        let draw = Instruction::new(0xD003); // DRW V0, V0, 3
+
+        state.i = 0xFFF;
+        state.v[0] = 0x12;
+        state.v[1] = 0x34;
+        assert_eq!(false, store.function(&mut state));
+        assert_eq!(Some(ExecError::AddressOutOfRange(0x1000)), state.fault);
+        assert_eq!(false, load.function(&mut state));
+        assert_eq!(false, draw.function(&mut state));
+        assert_eq!(0x200, state.pc);
+
+        state.quirks.mem_wrap = true;
+        assert_eq!(true, store.function(&mut state));
+        assert_eq!([0x12, 0x34], [state.ram[0xFFF], state.ram[0]]);
+        state.v = [0; 16];
+        assert_eq!(true, load.function(&mut state));
+        assert_eq!([0x12, 0x34], state.v[0..2]);
+        // Draws 0x12, 0x34 and the font byte at 0x001
+        assert_eq!(true, draw.function(&mut state));
+        assert_eq!(false, state.display.is_clear());
+    }
+
+    #[test]
+    fn zero_opcode() {
+        let mut state = State::new();
+        let instruction = Instruction::new(0x0000);
+
+        assert_eq!("DATA 0000", instruction.code);
+        assert_eq!(false, instruction.function(&mut state));
+        assert_eq!(Some(ExecError::IllegalOpcode(0x0000)), state.fault);
+        assert_eq!(true, state.halted);
+        assert_eq!(0x200, state.pc);
+    }
+
+    #[test]
+    fn shift_disassembly() {
+        assert_eq!("SHR V1, V2", Instruction::new(0x8126).code);
+        assert_eq!("SHL V1, V2", Instruction::new(0x812E).code);
+
+        // The long load shows its address when the word after it is known
+        assert_eq!("LD I, 1234", Instruction::decode(0xF000, Some(0x1234)).code);
+        assert_eq!("LD I, long", Instruction::decode(0xF000, None).code);
+        assert_eq!("CLS", Instruction::decode(0x00E0, Some(0x1234)).code);
+    }
+
+    #[test]
+    fn disassembly_listing() {
+        // CLS; LD V1, 05; JMP 202; an odd byte
+        let listing = disassemble(&[0x00, 0xE0, 0x61, 0x05, 0x12, 0x02, 0xFF], 0x200);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            vec![
+                "0200: CLS (00E0)",
+                "0202: LD V1, 05 (6105)",
+                "0204: JMP 202 (1202)",
+                "0206: DATA FF",
+            ],
+            lines
+        );
+
+        // LD I, long with its address word
+        let listing = disassemble(&[0xF0, 0x00, 0x12, 0x34], 0x200);
+        assert_eq!(Some("0200: LD I, 1234 (F000)"), listing.lines().next());
+    }
+
+    #[test]
+    fn families() {
+        assert_eq!(0x8004, family(0x8AB4));
+        assert_eq!("8xy4", family_pattern(family(0x8AB4)));
+        assert_eq!("00E0", family_pattern(family(0x00E0)));
+        assert_eq!("0nnn", family_pattern(family(0x0123)));
+        assert_eq!("Fx65", family_pattern(family(0xF365)));
+        assert_eq!("Fn01", family_pattern(family(0xF201)));
+        assert_eq!("????", family_pattern(family(0xE0FF)));
+        assert_eq!(Some(FAMILY_COUNT - 1), family_index(family(0xF365)));
+        assert_eq!("Fx65", pattern_at(FAMILY_COUNT - 1));
+    }
+
+    #[test]
+    fn supported_opcodes() {
+        let opcodes = Instruction::supported_opcodes();
+        assert_eq!(42, opcodes.len());
+        for mnemonic in ["DRW", "RND", "SKP", "SKNP", "CALL", "RET"].iter() {
+            assert!(opcodes
+                .iter()
+                .any(|(_, supported)| supported.split(' ').next() == Some(*mnemonic)));
+        }
+
+        // Every pattern decodes to its mnemonic with the operands filled in
+        for (pattern, mnemonic) in opcodes.iter() {
+            let hex: String = pattern
+                .chars()
+                .map(|c| if c.is_ascii_lowercase() { '1' } else { c })
+                .collect();
+            let instruction = Instruction::new(u16::from_str_radix(&hex, 16).unwrap());
+            assert_eq!(
+                mnemonic.split(' ').next(),
+                instruction.code.split(' ').next(),
+                "{}",
+                pattern
+            );
+        }
+    }
+
+    #[test]
+    fn describe_test() {
+        assert_eq!("Set Vx = Vx XOR Vy", describe(0x8123));
+        assert_eq!("Clear the display", describe(0x00E0));
+        assert_eq!("Unknown instruction", describe(0x8128));
+        for (pattern, _) in Instruction::supported_opcodes().iter() {
+            let hex: String = pattern
+                .chars()
+                .map(|c| if c.is_ascii_lowercase() { '1' } else { c })
+                .collect();
+            let opcode = u16::from_str_radix(&hex, 16).unwrap();
+            assert_ne!("Unknown instruction", describe(opcode), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn preview_test() {
+        let mut state = State::new();
+
+        assert_eq!(
+            vec![Effect::SetV(0xA, 0x05), Effect::SetPc(0x202)],
+            preview(&state, 0x6A05)
+        );
+        assert_eq!("VA will become 0x05", Effect::SetV(0xA, 0x05).to_string());
+
+        // ADD V1, V2 with a carry, checked against running it
+        state.v[1] = 0xF0;
+        state.v[2] = 0x20;
+        assert_eq!(
+            vec![
+                Effect::SetV(15, 1),
+                Effect::SetV(1, 0x10),
+                Effect::SetPc(0x202)
+            ],
+            preview(&state, 0x8124)
+        );
+        assert_eq!(true, Instruction::new(0x8124).function(&mut state));
+        assert_eq!((0x10, 1, 0x202), (state.v[1], state.v[15], state.pc));
+
+        // SE V1, 10 skips
+        assert_eq!(vec![Effect::SetPc(0x206)], preview(&state, 0x3110));
+        assert_eq!(vec![Effect::SetPc(0x204)], preview(&state, 0x3111));
+        // Nothing to return to and nothing predictable
+        assert_eq!(Vec::<Effect>::new(), preview(&state, 0x00EE));
+        assert_eq!(Vec::<Effect>::new(), preview(&state, 0xC1FF));
+
+        // ADD I, V1 wraps around like running it does
+        state.i = 0xFFFF;
+        state.v[1] = 2;
+        state.pc = 0x202;
+        assert_eq!(
+            vec![Effect::SetI(1), Effect::SetPc(0x204)],
+            preview(&state, 0xF11E)
+        );
+    }
+
+    #[test]
+    fn cycle_costs() {
+        assert_eq!(6, cycle_cost(0x61AA)); // LD Vx, byte
+        assert_eq!(5007, cycle_cost(0xD125)); // DRW Vx, Vy, nibble
+        assert_eq!(204, cycle_cost(0xF133)); // LD B, Vx
+        assert!(cycle_cost(0xD125) > cycle_cost(0x8124));
+        assert_eq!(cycle_cost(0x00E0), Instruction::new(0x00E0).cycles());
     }
 }
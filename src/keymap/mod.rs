@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Physical keys by their winit VirtualKeyCode name, in keypad order 0-F.
+// The left side of a QWERTY keyboard laid out like the COSMAC VIP keypad:
+// 1 2 3 C    1 2 3 4
+// 4 5 6 D    Q W E R
+// 7 8 9 E    A S D F
+// A 0 B F    Z X C V
+const DEFAULT_LAYOUT: [&str; 16] = [
+    "X", "Key1", "Key2", "Key3", "Q", "W", "E", "A", "S", "D", "Z", "C", "Key4", "R", "F", "V",
+];
+
+// Maps physical key names to CHIP-8 keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyMap {
+    keys: HashMap<String, u8>,
+}
+
+impl Default for KeyMap {
+    fn default() -> KeyMap {
+        let keys = DEFAULT_LAYOUT
+            .iter()
+            .enumerate()
+            .map(|(key, name)| (name.to_string(), key as u8))
+            .collect();
+        KeyMap { keys: keys }
+    }
+}
+
+// Parses `name = key` lines, a TOML table of names to hex keys:
+//     Key1 = "1"
+//     Q = 0x4
+// Lines starting with # and [section] headers are skipped. Shared by the
+// keyboard and controller bindings files.
+pub fn parse_bindings(text: &str) -> Result<Vec<(&str, u8)>, String> {
+    let mut bindings = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('[') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let name = parts.next().unwrap().trim().trim_matches('"');
+        let value = match parts.next() {
+            Some(value) => value.trim().trim_matches('"'),
+            None => return Err(format!("Missing key on line {}", line_no + 1)),
+        };
+        let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+        let key = match u8::from_str_radix(digits, 16) {
+            Ok(key) if key < 16 => key,
+            _ => return Err(format!("Invalid key {} on line {}", value, line_no + 1)),
+        };
+        bindings.push((name, key));
+    }
+    Ok(bindings)
+}
+
+impl KeyMap {
+    // Parses a bindings file of key names, see parse_bindings. Every CHIP-8
+    // key has to be bound exactly once.
+    pub fn parse(text: &str) -> Result<KeyMap, String> {
+        let mut keys = HashMap::new();
+        let mut bound = [false; 16];
+        for (name, key) in parse_bindings(text)? {
+            if keys.contains_key(name) {
+                return Err(format!("{} is bound twice", name));
+            }
+            if bound[key as usize] {
+                return Err(format!("Key {:X} is bound twice", key));
+            }
+            bound[key as usize] = true;
+            keys.insert(name.to_string(), key);
+        }
+
+        if let Some(key) = bound.iter().position(|bound| !bound) {
+            return Err(format!("Key {:X} isn't bound", key));
+        }
+        Ok(KeyMap { keys: keys })
+    }
+
+    pub fn load(path: &Path) -> Result<KeyMap, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => KeyMap::parse(&text).map_err(|why| format!("{}: {}", path.display(), why)),
+            Err(why) => Err(format!("Couldn't read {}: {}", path.display(), why)),
+        }
+    }
+
+    // CHIP-8 key bound to the physical key, if any
+    pub fn resolve(&self, name: &str) -> Option<u8> {
+        self.keys.get(name).cloned()
+    }
+
+    // Physical key bound to the CHIP-8 key
+    pub fn key_name(&self, key: u8) -> Option<&str> {
+        self.keys
+            .iter()
+            .find(|(_, bound)| **bound == key)
+            .map(|(name, _)| name.as_str())
+    }
+
+    // Binds the physical key to the CHIP-8 key in place of its old one. A
+    // physical key already bound to another CHIP-8 key is refused, taking it
+    // would leave that key unbound.
+    pub fn rebind(&mut self, key: u8, name: &str) -> Result<(), String> {
+        match self.resolve(name) {
+            Some(bound) if bound == key => return Ok(()),
+            Some(bound) => return Err(format!("{} is already bound to key {:X}", name, bound)),
+            None => {}
+        }
+        self.keys.retain(|_, bound| *bound != key);
+        self.keys.insert(name.to_string(), key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(names: &[&str]) -> String {
+        names
+            .iter()
+            .enumerate()
+            .map(|(key, name)| format!("{} = \"{:X}\"\n", name, key))
+            .collect()
+    }
+
+    #[test]
+    fn default_layout() {
+        let keymap = KeyMap::default();
+
+        assert_eq!(Some(0x1), keymap.resolve("Key1"));
+        assert_eq!(Some(0x0), keymap.resolve("X"));
+        assert_eq!(Some(0xF), keymap.resolve("V"));
+        assert_eq!(None, keymap.resolve("P"));
+    }
+
+    #[test]
+    fn parse() {
+        let names = [
+            "Numpad0", "Numpad7", "Numpad8", "Numpad9", "Numpad4", "Numpad5", "Numpad6", "Numpad1",
+            "Numpad2", "Numpad3", "A", "B", "C", "D", "E", "F",
+        ];
+        let text = format!("# Numpad layout\n[keys]\n{}", layout(&names));
+        let keymap = KeyMap::parse(&text).unwrap();
+
+        assert_eq!(Some(0x0), keymap.resolve("Numpad0"));
+        assert_eq!(Some(0x1), keymap.resolve("Numpad7"));
+        assert_eq!(Some(0xF), keymap.resolve("F"));
+        assert_eq!(None, keymap.resolve("Key1"));
+        assert_eq!(Ok(keymap), KeyMap::parse(&text.replace("\"F\"", "0xF")));
+    }
+
+    #[test]
+    fn rebind() {
+        let mut keymap = KeyMap::default();
+
+        assert_eq!(Some("A"), keymap.key_name(0x7));
+        assert_eq!(Ok(()), keymap.rebind(0x7, "J"));
+        assert_eq!(Some(0x7), keymap.resolve("J"));
+        assert_eq!(None, keymap.resolve("A"));
+        assert_eq!(Some("J"), keymap.key_name(0x7));
+
+        // Q is key 4's, nothing changes
+        assert!(keymap.rebind(0x7, "Q").is_err());
+        assert_eq!(Some(0x4), keymap.resolve("Q"));
+        assert_eq!(Some("J"), keymap.key_name(0x7));
+        assert_eq!(Ok(()), keymap.rebind(0x7, "J"));
+    }
+
+    #[test]
+    fn bindings() {
+        let text = "# Comment\n[keys]\n\"Q\" = \"4\"\nW = 0xA\n";
+        assert_eq!(Ok(vec![("Q", 0x4), ("W", 0xA)]), parse_bindings(text));
+        assert!(parse_bindings("Q = 0x10").is_err());
+        assert!(parse_bindings("Q").is_err());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let mut names = DEFAULT_LAYOUT;
+
+        // Missing key F
+        assert!(KeyMap::parse(&layout(&names[0..15])).is_err());
+        // The same physical key twice
+        names[15] = "X";
+        assert!(KeyMap::parse(&layout(&names)).is_err());
+        // Key out of range
+        assert!(KeyMap::parse("X = 10").is_err());
+        assert!(KeyMap::parse("X").is_err());
+    }
+}
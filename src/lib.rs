@@ -0,0 +1,38 @@
+#[cfg(feature = "clipboard")]
+extern crate arboard;
+extern crate flate2;
+#[cfg(feature = "gamepad")]
+extern crate gilrs;
+#[macro_use]
+extern crate glium;
+#[macro_use]
+extern crate imgui;
+extern crate imgui_glium_renderer;
+extern crate rand;
+extern crate rodio;
+#[macro_use]
+extern crate serde_json;
+
+pub mod audio;
+pub mod config;
+pub mod cpu;
+pub mod display;
+pub mod flicker;
+pub mod gamepad;
+pub mod gui;
+pub mod input;
+pub mod instruction;
+pub mod keymap;
+pub mod numbase;
+pub mod opengl;
+pub mod quirks;
+pub mod recent;
+pub mod replay;
+pub mod rom;
+pub mod romquirks;
+pub mod savestate;
+pub mod screenshot;
+pub mod search;
+pub mod state;
+pub mod symbols;
+pub mod watch;
@@ -1,83 +1,382 @@
-#[macro_use]
-extern crate glium;
-#[macro_use]
-extern crate imgui;
-extern crate imgui_glium_renderer;
-extern crate rand;
-extern crate rodio;
-
-use rodio::Source;
+extern crate chipster8;
 
 use glium::glutin::{
     dpi::LogicalPosition, ElementState, ElementState::Pressed, Event::WindowEvent, MouseButton,
     MouseScrollDelta, TouchPhase, VirtualKeyCode, WindowEvent::*,
 };
+use imgui::ImString;
 use std::env;
-use std::error::Error;
 use std::fs;
-use std::io::Read;
+use std::io;
+use std::panic;
 use std::path;
+use std::process;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::time::Instant;
 
-mod display;
-mod gui;
-mod instruction;
-mod opengl;
-mod state;
+use chipster8::audio::{AudioSettings, Beeper};
+use chipster8::config::{self, Config};
+use chipster8::cpu::{self, RepeatTimer, Scheduler};
+use chipster8::flicker::{FlickerDetector, Phosphor};
+#[cfg(feature = "gamepad")]
+use chipster8::gamepad::{Gamepad, GamepadMap};
+use chipster8::gui::{Gui, MouseState, UiAction};
+use chipster8::input::FrameInput;
+use chipster8::instruction;
+use chipster8::keymap::KeyMap;
+use chipster8::opengl::{self, Programs, RenderError, Vertex};
+use chipster8::recent::{self, RecentRoms};
+use chipster8::replay::{InputPlayer, InputRecording};
+use chipster8::rom::{self, Byteswap};
+use chipster8::romquirks::{self, RomQuirks};
+use chipster8::savestate::{self, Autosaver, History};
+use chipster8::screenshot;
+use chipster8::state::State;
+use chipster8::symbols;
+
+// Instructions Step Over runs at most waiting for a CALL to return
+const STEP_OVER_CYCLES: usize = 1_000_000;
+const SCREENSHOT_SCALE: usize = 8;
+
+fn load_program(path: &path::Path, state: &mut State, byteswap: Byteswap) -> io::Result<()> {
+    let mut buffer = rom::read(path)?;
+    if rom::needs_swap(&buffer, byteswap) {
+        rom::swap_bytes(&mut buffer);
+        println!("Swapped the byte order of {}", path.display());
+    }
+
+    state
+        .reload(&buffer)
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+    println!(
+        "Read file: {} Total bytes: {} [crc {:08x}]",
+        path.display(),
+        buffer.len(),
+        state.rom_crc
+    );
+    Ok(())
+}
+
+// Fresh machine with the current settings and the ROM loaded again, the state
+// is left alone when the ROM can't be read. The pokes only apply to the ROM
+// given on the command line.
+fn reset_state(state: &mut State, config: &Config, rom_path: &path::Path) -> io::Result<()> {
+    load_program(rom_path, state, config.byteswap)?;
+    if rom_path == config.rom_path.as_path() {
+        apply_pokes(config, state);
+    }
+    Ok(())
+}
+
+// Quirks picked for this ROM in an earlier session win over the profile's
+fn apply_rom_quirks(rom_quirks: &RomQuirks, state: &mut State, gui: &mut Gui) {
+    if let Some(quirks) = rom_quirks.get(state.rom_crc) {
+        state.quirks = quirks;
+        gui.profile = None;
+        println!("Using the quirks saved for this ROM");
+    }
+}
+
+fn window_title(path: &path::Path, state: &State) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    format!("Chipster8 - {} [crc {:08x}]", name, state.rom_crc)
+}
+
+fn apply_pokes(config: &Config, state: &mut State) {
+    for (addr, value) in config.pokes.iter() {
+        match state.poke(*addr, *value) {
+            Err(why) => println!("{}", why),
+            Ok(_) => println!("Poked {:02X} at {:04X}", value, addr),
+        }
+    }
+}
+
+fn print_collisions(state: &mut State) {
+    for collision in state.display.collisions.drain(..) {
+        println!(
+            "Collision at {},{} plane {} drawing sprite at {},{}",
+            collision.x, collision.y, collision.plane, collision.sprite_x, collision.sprite_y
+        );
+    }
+}
+
+fn dump_fault(state: &State) {
+    let ram_path = path::Path::new("fault_ram.bin");
+    let registers_path = path::Path::new("fault_registers.txt");
+
+    match state
+        .dump_ram(ram_path)
+        .and_then(|_| fs::write(registers_path, state.register_summary()))
+    {
+        Err(why) => println!("Couldn't write fault dump: {}", why),
+        Ok(_) => println!(
+            "Wrote fault dump to {} and {}",
+            ram_path.display(),
+            registers_path.display()
+        ),
+    }
+}
+
+// Prints the last machine state the loop copied out before the usual panic
+// message. The hook only reads the copy, the state itself may be borrowed
+// when the panic happens.
+fn install_panic_hook(snapshot: Arc<Mutex<String>>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Ok(report) = snapshot.try_lock() {
+            eprintln!("Machine state as of the last frame:\n{}", *report);
+        }
+        default_hook(info);
+    }));
+}
+
+// Writes the ROM's listing without running it, returns the exit status
+fn write_disassembly(config: &Config, path: &path::Path) -> i32 {
+    let mut rom = match rom::read(&config.rom_path) {
+        Ok(rom) => rom,
+        Err(why) => {
+            println!("Couldn't load {}: {}", config.rom_path.display(), why);
+            return 1;
+        }
+    };
+    if rom::needs_swap(&rom, config.byteswap) {
+        rom::swap_bytes(&mut rom);
+    }
+    match fs::write(path, instruction::disassemble(&rom, 0x200)) {
+        Err(why) => {
+            println!("Couldn't write {}: {}", path.display(), why);
+            1
+        }
+        Ok(_) => {
+            println!("Wrote the disassembly to {}", path.display());
+            0
+        }
+    }
+}
+
+fn load_replay(config: &Config) -> Result<Option<InputPlayer>, String> {
+    match &config.replay_path {
+        Some(path) => InputRecording::load(path).map(|recording| Some(InputPlayer::new(recording))),
+        None => Ok(None),
+    }
+}
+
+// Plays every recorded frame, stopping early like run_cycles. Returns false
+// if an instruction failed.
+fn run_replay(state: &mut State, player: &mut InputPlayer, ips: u32) -> bool {
+    while !state.halted && !state.cycle_limit_reached() && player.next_frame(state) {
+        if !cpu::run_frame(state, ips) {
+            return false;
+        }
+    }
+    true
+}
 
-use gui::{Gui, MouseState, UiAction};
-use instruction::Instruction;
-use state::State;
+// One frame of deterministic mode. The replayed keys replace the keyboard's
+// while the replay lasts and the keys are recorded before the frame runs.
+fn run_logical_frame(
+    state: &mut State,
+    ips: u32,
+    player: &mut Option<InputPlayer>,
+    recording: &mut Option<InputRecording>,
+) -> bool {
+    if let Some(player) = player {
+        let finished = player.finished();
+        if !player.next_frame(state) && !finished {
+            println!("Replay finished after {} frames", player.frames_played());
+        }
+    }
+    if let Some(recording) = recording {
+        recording.record_inputs(state);
+    }
+    cpu::run_frame(state, ips)
+}
 
-fn load_program(path: &path::Path, state: &mut state::State) {
-    let mut file = match fs::File::open(path) {
-        Err(why) => panic!("Couldn't open {}: {}", path.display(), why.description()),
-        Ok(file) => file,
+// Runs the ROM without a window and returns the process exit status
+fn run_headless(config: &Config) -> i32 {
+    let mut state: State = State::with_ram_size(config.ram_size);
+    if let Some(profile) = config.profile {
+        state.quirks = profile.quirks();
+    }
+    state.display.collision_logging = config.log_collisions;
+    state.max_cycles = config.max_cycles;
+    state.enforce_alignment = config.enforce_alignment;
+    state.pc_overflow = config.pc_overflow;
+    state.boot_splash = config.boot_splash;
+    state.set_stack_depth(config.stack_depth);
+    let mut player = match load_replay(config) {
+        Ok(player) => player,
+        Err(why) => {
+            println!("{}", why);
+            return 1;
+        }
     };
-    let file_size = fs::metadata(path).unwrap().len();
+    if config.deterministic {
+        state.set_seed(Some(
+            player
+                .as_ref()
+                .map_or(config.seed, |player| player.recording.seed),
+        ));
+    }
+    if let Err(why) = load_program(&config.rom_path, &mut state, config.byteswap) {
+        println!("Couldn't load {}: {}", config.rom_path.display(), why);
+        return 1;
+    }
+    apply_pokes(config, &mut state);
 
-    let mut buffer = vec![0u8; file_size as usize];
-    let bytes_read = match file.read(&mut buffer) {
-        Err(why) => panic!("Couldn't read {}: {}", path.display(), why.description()),
-        Ok(bytes_read) => bytes_read,
+    let ok = match &mut player {
+        Some(player) => run_replay(&mut state, player, config.ips),
+        None => cpu::run_cycles(&mut state, config.cycles),
     };
+    print_collisions(&mut state);
+    if !ok {
+        if config.dump_on_fault {
+            dump_fault(&state);
+        }
+        return 1;
+    }
 
-    if bytes_read != file_size as usize {
-        panic!(
-            "File size and bytes read missmatch! {} vs {}",
-            file_size, bytes_read
+    if state.exited {
+        println!("Program exited");
+    } else if state.halted {
+        println!("Program halted at {:04X}", state.pc);
+    } else if state.cycle_limit_reached() {
+        println!(
+            "Cycle limit reached at {:04X} after {} instructions",
+            state.pc, state.instruction_count
         );
     }
+    if let Some(expected) = config.verify_hash {
+        let verification = state.display.verify(expected);
+        println!("{}", verification.message());
+        // 1 is taken by faults
+        return if verification.passed() { 0 } else { 2 };
+    }
+    0
+}
 
-    println!("Read file: {} Total bytes: {}", path.display(), bytes_read);
+// Finishes pending work before the window closes
+fn shutdown(state: &State, config: &Config) {
+    let path = config.autosave_path.as_ref().map(|path| path.as_path());
+    match savestate::shutdown(state, path) {
+        Err(why) => println!("Couldn't write autosave: {}", why),
+        Ok(true) => println!("Saved state to {}", path.unwrap().display()),
+        Ok(false) => {}
+    }
+}
 
-    state.ram[0x200..(0x200 + bytes_read)].clone_from_slice(&buffer[0..]);
+// Puts the ROM on top of the File menu's recent list and writes the list out
+fn remember_rom(recent_roms: &mut RecentRoms, rom_path: &path::Path, gui: &mut Gui) {
+    let rom_path = fs::canonicalize(rom_path).unwrap_or_else(|_| rom_path.to_path_buf());
+    recent_roms.add(&rom_path);
+    gui.recent_roms = recent_roms.paths.clone();
+    if let Some(path) = recent::default_path() {
+        if let Err(why) = recent_roms.save(&path) {
+            println!("Couldn't write {}: {}", path.display(), why);
+        }
+    }
 }
 
-fn execute(state: &mut State) -> bool {
-    let instruction = Instruction::new(
-        ((state.ram[state.pc as usize]) as u16) << 8 | state.ram[(state.pc + 1) as usize] as u16,
-    );
+fn load_symbols(gui: &mut Gui) {
+    let path = path::PathBuf::from(gui.symbols_path.to_str());
+    match symbols::load(&path) {
+        Err(why) => println!("{}", why),
+        Ok(symbols) => {
+            println!("Loaded {} symbols from {}", symbols.len(), path.display());
+            gui.symbols = symbols;
+        }
+    }
+}
+
+fn render_frame(
+    display: &glium::Display,
+    gui: &mut Gui,
+    state: &mut State,
+    vertex_buffer: &glium::VertexBuffer<Vertex>,
+    programs: &Programs,
+) -> Result<(), RenderError> {
+    use glium::Surface;
+    let texture = opengl::render_texture(
+        display,
+        vertex_buffer,
+        programs,
+        gui.filter,
+        gui.display_size,
+    )?;
+    let mut target = display.draw();
+    target.clear_color(1.0, 1.0, 1.0, 1.0);
+    // The frame has to be finished even when the GUI failed
+    let gui_result = gui.render(&mut target, state, texture);
+    target.finish()?;
+    gui_result
+}
 
-    if !instruction.function(state) {
-        println!("Failed to execute instruction!");
-        return false;
+// Falls back to the default layout when there's no usable bindings file
+fn load_keymap(config: &Config) -> KeyMap {
+    match &config.keymap_path {
+        Some(path) if path.exists() => match KeyMap::load(path) {
+            Ok(keymap) => {
+                println!("Loaded key bindings from {}", path.display());
+                keymap
+            }
+            Err(why) => {
+                println!("{}, using the default key bindings", why);
+                KeyMap::default()
+            }
+        },
+        Some(path) => {
+            println!(
+                "{} not found, using the default key bindings",
+                path.display()
+            );
+            KeyMap::default()
+        }
+        None => KeyMap::default(),
     }
-    true
 }
 
-fn update_timers(state: &mut State) {
-    if state.dt > 0 {
-        state.dt -= 1;
+// Binds the pressed key to the CHIP-8 key the Key Bindings window is waiting
+// on. Escape cancels, a key that's already taken is refused and waits on. The
+// CHIP-8 key is released, the old key's release won't reach it any more.
+fn rebind_key(
+    gui: &mut Gui,
+    frame_input: &mut FrameInput,
+    state: &mut State,
+    keycode: VirtualKeyCode,
+) {
+    let key = match gui.rebinding {
+        Some(key) => key,
+        None => return,
+    };
+    if keycode == VirtualKeyCode::Escape {
+        gui.rebinding = None;
+        return;
+    }
+    let name = format!("{:?}", keycode);
+    match gui.keymap.rebind(key, &name) {
+        Err(why) => gui.keymap_message = why,
+        Ok(_) => {
+            println!("Bound {} to key {:X}", name, key);
+            frame_input.key_event(state, key, false);
+            gui.rebinding = None;
+        }
     }
+}
 
-    if state.st > 0 {
-        state.audio_output.play();
-        state.st -= 1;
-    } else {
-        state.audio_output.pause();
+#[cfg(feature = "gamepad")]
+fn open_gamepad(config: &Config) -> Option<Gamepad> {
+    if !config.gamepad {
+        return None;
     }
+    let map = match &config.gamepad_map_path {
+        Some(path) => GamepadMap::load(path).unwrap_or_else(|why| {
+            println!("{}, using the default gamepad bindings", why);
+            GamepadMap::default()
+        }),
+        None => GamepadMap::default(),
+    };
+    Gamepad::new(map)
 }
 
 fn is_key_pressed(state: ElementState) -> bool {
@@ -88,112 +387,443 @@ fn is_key_pressed(state: ElementState) -> bool {
 }
 
 fn main() {
-    use glium::Surface;
-    let mut state: State = State::new();
+    let args: Vec<String> = env::args().collect();
+    let config = match Config::from_args(&args[1..]) {
+        Ok(config) => config,
+        Err(why) => {
+            println!("{}\n{}", why, config::USAGE);
+            return;
+        }
+    };
+
+    if let Some(path) = &config.disasm_path {
+        process::exit(write_disassembly(&config, path));
+    }
+    if config.headless {
+        process::exit(run_headless(&config));
+    }
+
+    let mut state: State = State::with_ram_size(config.ram_size);
+    if let Some(profile) = config.profile {
+        state.quirks = profile.quirks();
+    }
     let mut mouse_state = MouseState::default();
+    let mut frame_input = FrameInput::new(config.live_input);
+    #[cfg(feature = "gamepad")]
+    let mut gamepad = open_gamepad(&config);
+    #[cfg(not(feature = "gamepad"))]
+    {
+        if config.gamepad {
+            println!("Built without gamepad support, rebuild with --features gamepad");
+        }
+    }
+    state.display.collision_logging = config.log_collisions;
+    state.display.double_buffered = config.double_buffer;
+    state.display.present_interval = config.present_interval;
+    state.max_cycles = config.max_cycles;
+    state.enforce_alignment = config.enforce_alignment;
+    state.pc_overflow = config.pc_overflow;
+    state.boot_splash = config.boot_splash;
+    state.set_stack_depth(config.stack_depth);
+    // A replay that can't be read is dropped, the ROM still runs
+    let mut player = load_replay(&config).unwrap_or_else(|why| {
+        println!("{}", why);
+        None
+    });
+    let seed = player
+        .as_ref()
+        .map_or(config.seed, |player| player.recording.seed);
+    if config.deterministic {
+        state.set_seed(Some(seed));
+    }
+    let mut recording = config
+        .record_path
+        .as_ref()
+        .map(|_| InputRecording::new(seed));
+    let audio_settings = AudioSettings {
+        muted: config.muted,
+        volume: config.volume,
+    };
+    state.audio_output = Beeper::new(config.tone_hz, audio_settings);
     let (display, mut events_loop) = opengl::create_window();
     let mut gui: Gui = Gui::new(&display);
+    gui.profile = config.profile;
+    gui.grid_color = config.grid_color;
+    gui.audio = audio_settings;
+    gui.state_slot = config.state_slot;
+    gui.display_size = opengl::display_size(config.pixel_aspect);
+    gui.persistence = config.persistence;
+    gui.auto_persistence = config.auto_persistence;
+    gui.keymap = load_keymap(&config);
+
+    // Symbols next to the ROM are picked up automatically
+    let symbols_path = config.rom_path.with_extension("sym");
+    gui.symbols_path = ImString::new(symbols_path.to_string_lossy());
+    if symbols_path.exists() {
+        load_symbols(&mut gui);
+    }
 
     let mut last_frame = Instant::now();
+    let mut last_update = Instant::now();
+    let mut scheduler = Scheduler::new(config.ips);
+    let mut history = History::new();
+    let mut autosaver = config
+        .autosave_interval
+        .map(|seconds| Autosaver::new(Duration::from_secs(seconds)));
+    let crash_snapshot = Arc::new(Mutex::new(String::new()));
+    install_panic_hook(Arc::clone(&crash_snapshot));
     let mut closed = false;
-    let mut simmulation_running = false;
-    let mut simmulation_step = false;
+    let mut simmulation_running = config.start_running;
+    let mut step_repeat = RepeatTimer::new(Duration::from_millis(config.step_repeat_ms));
+    let mut step_key_held = false;
+    let mut simmulation_frame_step = false;
+    let mut resume_on_focus = false;
 
-    let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-    let program = opengl::generate_program(&display);
-    let args: Vec<String> = env::args().collect();
+    let programs = opengl::generate_program(&display);
 
-    if args.len() < 2 {
-        println!("Usage: chipster8 path_to_rom");
+    if let Err(why) = load_program(&config.rom_path, &mut state, config.byteswap) {
+        println!("Couldn't load {}: {}", config.rom_path.display(), why);
         return;
     }
-    load_program(path::Path::new(&args[1]), &mut state);
+    apply_pokes(&config, &mut state);
+    let mut rom_path = config.rom_path.clone();
+    gui.rom_path = ImString::new(rom_path.to_string_lossy());
+    let mut recent_roms =
+        recent::default_path().map_or_else(RecentRoms::new, |path| RecentRoms::load(&path));
+    remember_rom(&mut recent_roms, &rom_path, &mut gui);
+    let rom_quirks_path = romquirks::default_path();
+    let mut rom_quirks = rom_quirks_path
+        .as_ref()
+        .map_or_else(RomQuirks::new, |path| RomQuirks::load(path));
+    apply_rom_quirks(&rom_quirks, &mut state, &mut gui);
+    display
+        .gl_window()
+        .window()
+        .set_title(&window_title(&config.rom_path, &state));
+    if let Some(path) = &config.autosave_path {
+        if path.exists() {
+            match savestate::read(&mut state, path) {
+                Err(why) => println!("Couldn't load autosave {}: {}", path.display(), why),
+                Ok(_) => println!("Resumed from {}", path.display()),
+            }
+        }
+    }
 
+    let mut flicker = FlickerDetector::new();
+    let mut phosphor = Phosphor::new();
+    let mut vertex_buffer = opengl::create_vertex_buffer(&display, &state, &config.palette, None)
+        .expect("Couldn't create the display vertex buffer");
     while !closed {
-        for i in 0..9 {
-            events_loop.poll_events(|event| {
-                if let WindowEvent { event, .. } = event {
-                    match event {
-                        CloseRequested => closed = true,
-                        CursorMoved {
-                            position: LogicalPosition { x, y },
-                            ..
-                        } => mouse_state.pos = [x as f32, y as f32],
-                        MouseInput { state, button, .. } => match button {
-                            MouseButton::Left => mouse_state.pressed[0] = state == Pressed,
-                            MouseButton::Right => mouse_state.pressed[1] = state == Pressed,
-                            MouseButton::Middle => mouse_state.pressed[2] = state == Pressed,
-                            _ => {}
-                        },
-                        MouseWheel {
-                            delta: MouseScrollDelta::LineDelta(_, y),
-                            phase: TouchPhase::Moved,
-                            ..
-                        } => mouse_state.wheel = y,
-                        MouseWheel {
-                            delta: MouseScrollDelta::PixelDelta(pos),
-                            phase: TouchPhase::Moved,
-                            ..
-                        } => mouse_state.wheel = pos.y as f32,
-                        KeyboardInput { input, .. } => match input.virtual_keycode.unwrap() {
-                            VirtualKeyCode::Key1 => state.keypad[1] = is_key_pressed(input.state),
-                            VirtualKeyCode::Key2 => state.keypad[2] = is_key_pressed(input.state),
-                            VirtualKeyCode::Key3 => state.keypad[3] = is_key_pressed(input.state),
-                            VirtualKeyCode::Q => state.keypad[4] = is_key_pressed(input.state),
-                            VirtualKeyCode::W => state.keypad[5] = is_key_pressed(input.state),
-                            VirtualKeyCode::E => state.keypad[6] = is_key_pressed(input.state),
-                            VirtualKeyCode::A => state.keypad[7] = is_key_pressed(input.state),
-                            VirtualKeyCode::S => state.keypad[8] = is_key_pressed(input.state),
-                            VirtualKeyCode::D => state.keypad[9] = is_key_pressed(input.state),
-                            VirtualKeyCode::Z => state.keypad[10] = is_key_pressed(input.state),
-                            VirtualKeyCode::X => state.keypad[0] = is_key_pressed(input.state),
-                            VirtualKeyCode::C => state.keypad[11] = is_key_pressed(input.state),
-                            VirtualKeyCode::Key4 => state.keypad[12] = is_key_pressed(input.state),
-                            VirtualKeyCode::R => state.keypad[13] = is_key_pressed(input.state),
-                            VirtualKeyCode::F => state.keypad[14] = is_key_pressed(input.state),
-                            VirtualKeyCode::V => state.keypad[15] = is_key_pressed(input.state),
-                            _ => (),
-                        },
-                        _ => (),
+        events_loop.poll_events(|event| {
+            if let WindowEvent { event, .. } = event {
+                match event {
+                    CloseRequested => closed = true,
+                    // Keys released while unfocused never reach us, drop them all
+                    Focused(false) => {
+                        frame_input.release_all(&mut state);
+                        if config.pause_on_focus_loss {
+                            resume_on_focus = simmulation_running;
+                            simmulation_running = false;
+                        }
                     }
+                    Focused(true) => {
+                        if resume_on_focus {
+                            simmulation_running = true;
+                            resume_on_focus = false;
+                        }
+                    }
+                    CursorMoved {
+                        position: LogicalPosition { x, y },
+                        ..
+                    } => mouse_state.pos = [x as f32, y as f32],
+                    MouseInput { state, button, .. } => match button {
+                        MouseButton::Left => mouse_state.pressed[0] = state == Pressed,
+                        MouseButton::Right => mouse_state.pressed[1] = state == Pressed,
+                        MouseButton::Middle => mouse_state.pressed[2] = state == Pressed,
+                        _ => {}
+                    },
+                    MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(_, y),
+                        phase: TouchPhase::Moved,
+                        ..
+                    } => mouse_state.wheel = y,
+                    MouseWheel {
+                        delta: MouseScrollDelta::PixelDelta(pos),
+                        phase: TouchPhase::Moved,
+                        ..
+                    } => mouse_state.wheel = pos.y as f32,
+                    // While a key is being rebound the press only picks the key
+                    KeyboardInput { input, .. } if gui.rebinding.is_some() => {
+                        if let (Some(keycode), Pressed) = (input.virtual_keycode, input.state) {
+                            rebind_key(&mut gui, &mut frame_input, &mut state, keycode);
+                        }
+                    }
+                    KeyboardInput { input, .. } => {
+                        if input.virtual_keycode == Some(VirtualKeyCode::F10) {
+                            step_key_held = is_key_pressed(input.state);
+                        }
+                        let key = input
+                            .virtual_keycode
+                            .and_then(|keycode| gui.keymap.resolve(&format!("{:?}", keycode)));
+                        if let Some(key) = key {
+                            frame_input.key_event(&mut state, key, is_key_pressed(input.state));
+                        }
+                    }
+                    _ => (),
                 }
-            });
-            if simmulation_running || simmulation_step {
-                execute(&mut state);
-                if i == 0 {
-                    update_timers(&mut state);
+            }
+        });
+        #[cfg(feature = "gamepad")]
+        {
+            if let Some(gamepad) = &mut gamepad {
+                gamepad.poll(&mut state, &mut frame_input);
+            }
+        }
+        // Every cycle of the frame sees the same keys
+        frame_input.apply(&mut state);
+
+        let now = Instant::now();
+        let elapsed = now - last_update;
+        let (cycles, ticks) = scheduler.advance(elapsed);
+        let steps = step_repeat.update(gui.step_held || step_key_held, elapsed);
+        last_update = now;
+        if steps > 0 {
+            simmulation_running = false;
+        }
+
+        let mut faulted = false;
+        if simmulation_running {
+            faulted = if config.deterministic {
+                !run_logical_frame(&mut state, config.ips, &mut player, &mut recording)
+            } else {
+                !cpu::run_scheduled(&mut state, cycles, ticks)
+            };
+            if state.cycle_limit_reached() {
+                println!(
+                    "Cycle limit reached after {} instructions",
+                    state.instruction_count
+                );
+            }
+            if state.at_breakpoint() {
+                println!("Breakpoint at {:04X}", state.pc);
+            }
+            if state.halted || faulted || state.cycle_limit_reached() || state.at_breakpoint() {
+                simmulation_running = false;
+            }
+        } else if steps > 0 {
+            for _ in 0..steps {
+                history.push(&state);
+                faulted = !cpu::step(&mut state);
+                if faulted {
+                    break;
+                }
+            }
+        } else if simmulation_frame_step {
+            faulted = if config.deterministic {
+                !run_logical_frame(&mut state, config.ips, &mut player, &mut recording)
+            } else {
+                !cpu::run_frame(&mut state, config.ips)
+            };
+            simmulation_frame_step = false;
+        }
+        if faulted && config.dump_on_fault {
+            dump_fault(&state);
+        }
+        if let Some(autosaver) = &mut autosaver {
+            if autosaver.update(simmulation_running && !state.halted, elapsed) {
+                let path = autosaver.next_path(&rom_path);
+                if let Err(why) = savestate::write(&state, &path) {
+                    println!("Couldn't write autosave {}: {}", path.display(), why);
                 }
-                simmulation_step = false;
             }
         }
+        print_collisions(&mut state);
+        if let Ok(mut snapshot) = crash_snapshot.lock() {
+            *snapshot = state.crash_report();
+        }
+        // While paused there's no vblank, show edits and steps straight away
+        if !simmulation_running {
+            state.display.present();
+        }
 
         gui.update_mouse_state(&mut mouse_state);
-        let shape = opengl::generate_display(&state);
-        let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
-        let texture = glium::Texture2d::empty(&display, 400, 200).unwrap();
-        texture.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
-        texture
-            .as_surface()
-            .draw(
-                &vertex_buffer,
-                &indices,
-                &program,
-                &glium::uniforms::EmptyUniforms,
-                &Default::default(),
-            )
-            .unwrap();
-        let mut target = display.draw();
-        target.clear_color(1.0, 1.0, 1.0, 1.0);
-        gui.render(&mut target, &state, texture);
-        target.finish().unwrap();
+        gui.running = simmulation_running;
+        if let Some(beeper) = &state.audio_output {
+            beeper.set_settings(gui.audio);
+        }
+        flicker.observe(state.display.front());
+        if gui.auto_persistence && !gui.persistence && flicker.is_flickering() {
+            println!("The ROM flickers, turning on persistence");
+            gui.persistence = true;
+        }
+        // Fading pixels need new vertices every frame
+        if gui.persistence && phosphor.update(state.display.front()) {
+            state.display.dirty = true;
+        }
+        // Pixels are only turned into vertices again when something changed.
+        // A frozen display keeps the old vertices, dirty stays set so the
+        // current image shows up as soon as it's unfrozen.
+        if state.display.dirty && !gui.display_frozen {
+            let fade = if gui.persistence {
+                Some(&phosphor)
+            } else {
+                None
+            };
+            match opengl::create_vertex_buffer(&display, &state, &config.palette, fade) {
+                Ok(buffer) => {
+                    vertex_buffer = buffer;
+                    state.display.dirty = false;
+                }
+                Err(why) => println!("{}", why),
+            }
+        }
+        // A failed frame is dropped, the next one starts from scratch
+        if let Err(why) = render_frame(&display, &mut gui, &mut state, &vertex_buffer, &programs) {
+            println!("Skipped frame: {}", why);
+        }
 
         match gui.ui_action {
             UiAction::Run => simmulation_running = true,
             UiAction::Stop => simmulation_running = false,
-            UiAction::Step => {
+            UiAction::StepBack => {
+                simmulation_running = false;
+                if !history.step_back(&mut state) {
+                    println!("Nothing to step back to");
+                }
+            }
+            UiAction::FrameStep => {
+                simmulation_running = false;
+                simmulation_frame_step = true;
+            }
+            UiAction::StepOver => {
+                simmulation_running = false;
+                history.push(&state);
+                if !cpu::step_over(&mut state, STEP_OVER_CYCLES) && config.dump_on_fault {
+                    dump_fault(&state);
+                }
+            }
+            UiAction::StepN => {
+                simmulation_running = false;
+                // Step Back undoes the whole batch
+                history.push(&state);
+                let (steps, ok) = cpu::step_n(&mut state, gui.step_count as usize);
+                if state.at_breakpoint() && steps < gui.step_count as usize {
+                    println!("Breakpoint at {:04X} after {} steps", state.pc, steps);
+                }
+                if !ok && config.dump_on_fault {
+                    dump_fault(&state);
+                }
+            }
+            UiAction::Skip => {
+                simmulation_running = false;
+                history.push(&state);
+                if !cpu::skip(&mut state) {
+                    if let Some(fault) = state.fault {
+                        println!("Couldn't skip: {}", fault);
+                    }
+                }
+            }
+            UiAction::QuirksChanged => {
+                rom_quirks.set(state.rom_crc, state.quirks);
+                if let Some(path) = &rom_quirks_path {
+                    if let Err(why) = rom_quirks.save(path) {
+                        println!("Couldn't save the ROM quirks {}: {}", path.display(), why);
+                    }
+                }
+            }
+            UiAction::Reset => {
                 simmulation_running = false;
-                simmulation_step = true;
+                match reset_state(&mut state, &config, &rom_path) {
+                    Err(why) => {
+                        gui.rom_message =
+                            format!("Couldn't reload {}: {}", rom_path.display(), why);
+                        println!("{}", gui.rom_message);
+                    }
+                    Ok(_) => {
+                        gui.rom_message.clear();
+                        history.clear();
+                        // The old frames say nothing about the new run
+                        flicker.reset();
+                        phosphor = Phosphor::new();
+                        // A recording or replay starts over with the ROM
+                        if let Some(player) = &mut player {
+                            player.rewind();
+                        }
+                        if let Some(recording) = &mut recording {
+                            recording.frames.clear();
+                        }
+                    }
+                }
+            }
+            UiAction::OpenRom => {
+                let path = path::PathBuf::from(gui.rom_path.to_str());
+                match reset_state(&mut state, &config, &path) {
+                    Err(why) => {
+                        gui.rom_message = format!("Couldn't open {}: {}", path.display(), why);
+                        println!("{}", gui.rom_message);
+                    }
+                    Ok(_) => {
+                        gui.rom_message.clear();
+                        simmulation_running = false;
+                        history.clear();
+                        flicker.reset();
+                        phosphor = Phosphor::new();
+                        display
+                            .gl_window()
+                            .window()
+                            .set_title(&window_title(&path, &state));
+                        remember_rom(&mut recent_roms, &path, &mut gui);
+                        apply_rom_quirks(&rom_quirks, &mut state, &mut gui);
+                        rom_path = path;
+                    }
+                }
+            }
+            UiAction::SaveState => {
+                let path = savestate::slot_path(&rom_path, gui.state_slot);
+                match savestate::write(&state, &path) {
+                    Err(why) => println!("Couldn't save state {}: {}", path.display(), why),
+                    Ok(_) => println!("Saved state to {}", path.display()),
+                }
             }
+            UiAction::LoadState => {
+                let path = savestate::slot_path(&rom_path, gui.state_slot);
+                // An empty slot leaves the running program alone
+                if !path.exists() {
+                    println!("Slot {} is empty, no {}", gui.state_slot, path.display());
+                } else {
+                    match savestate::read(&mut state, &path) {
+                        Err(why) => println!("Couldn't load state {}: {}", path.display(), why),
+                        Ok(_) => println!("Loaded state from {}", path.display()),
+                    }
+                }
+            }
+            UiAction::Screenshot => {
+                let path = path::Path::new("screenshot.png");
+                match screenshot::write_png(path, &state.display, &config.palette, SCREENSHOT_SCALE)
+                {
+                    Err(why) => println!("Couldn't write screenshot: {}", why),
+                    Ok(_) => println!("Wrote screenshot to {}", path.display()),
+                }
+            }
+            UiAction::CopyScreen => {
+                #[cfg(feature = "clipboard")]
+                match screenshot::copy_to_clipboard(
+                    &state.display,
+                    &config.palette,
+                    SCREENSHOT_SCALE,
+                ) {
+                    Err(why) => println!("Couldn't copy the screen: {}", why),
+                    Ok(_) => println!("Copied the screen to the clipboard"),
+                }
+                #[cfg(not(feature = "clipboard"))]
+                println!("Built without clipboard support, rebuild with --features clipboard");
+            }
+            UiAction::Quit => closed = true,
+            UiAction::DumpRam => match state.dump_ram(path::Path::new("ram.bin")) {
+                Err(why) => println!("Couldn't dump RAM: {}", why),
+                Ok(_) => println!("Dumped RAM to ram.bin"),
+            },
+            UiAction::LoadSymbols => load_symbols(&mut gui),
             UiAction::None => (),
         }
 
@@ -201,8 +831,22 @@ fn main() {
         let delta = now - last_frame;
         last_frame = now;
 
+        // In deterministic mode the timers follow the frames and this sets
+        // the speed
         if delta < Duration::from_millis(16) {
             ::std::thread::sleep(Duration::from_millis(16) - delta);
         }
     }
+
+    shutdown(&state, &config);
+    if let (Some(recording), Some(path)) = (&recording, &config.record_path) {
+        match recording.save(path) {
+            Err(why) => println!("Couldn't write {}: {}", path.display(), why),
+            Ok(_) => println!(
+                "Recorded {} frames to {}",
+                recording.frames.len(),
+                path.display()
+            ),
+        }
+    }
 }
@@ -0,0 +1,50 @@
+// How the Registers window shows values
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NumberBase {
+    Hex,
+    Dec,
+    Bin,
+}
+
+impl NumberBase {
+    pub const ALL: [NumberBase; 3] = [NumberBase::Hex, NumberBase::Dec, NumberBase::Bin];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NumberBase::Hex => "Hex",
+            NumberBase::Dec => "Dec",
+            NumberBase::Bin => "Bin",
+        }
+    }
+}
+
+// A value of a register width bits wide, padded so every value of that
+// width takes the same room and the columns line up: 8 bit 42 is 2A, " 42"
+// or 00101010
+pub fn format_value(value: u16, base: NumberBase, width: u32) -> String {
+    match base {
+        NumberBase::Hex => format!("{:01$X}", value, (width as usize + 3) / 4),
+        NumberBase::Dec => {
+            let max = (1u32 << width.min(16)) - 1;
+            format!("{:1$}", value, max.to_string().len())
+        }
+        NumberBase::Bin => format!("{:01$b}", value, width as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_value_test() {
+        assert_eq!("2A", format_value(42, NumberBase::Hex, 8));
+        assert_eq!(" 42", format_value(42, NumberBase::Dec, 8));
+        assert_eq!("00101010", format_value(42, NumberBase::Bin, 8));
+
+        assert_eq!("0200", format_value(0x200, NumberBase::Hex, 16));
+        assert_eq!("  512", format_value(0x200, NumberBase::Dec, 16));
+        assert_eq!("0000001000000000", format_value(0x200, NumberBase::Bin, 16));
+        assert_eq!("65535", format_value(0xFFFF, NumberBase::Dec, 16));
+    }
+}
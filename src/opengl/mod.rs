@@ -1,9 +1,17 @@
+use crate::display::Palette;
+use crate::flicker::Phosphor;
 use crate::state::State;
 use glium::glutin;
+use glium::Surface;
+use imgui_glium_renderer::RendererError;
+use std::fmt;
 
 static PIXELSIZE_X: f32 = 2.0 / 64.0;
 static PIXELSIZE_Y: f32 = 2.0 / 32.0;
 
+// Width of the screen in the Display window
+pub const DISPLAY_WIDTH: f32 = 400.0;
+
 #[derive(Copy, Clone)]
 pub struct Vertex {
     position: [f32; 2],
@@ -12,6 +20,98 @@ pub struct Vertex {
 
 implement_vertex!(Vertex, position, color);
 
+// Corner of the quad the screen texture is drawn onto
+#[derive(Copy, Clone)]
+pub struct ScreenVertex {
+    position: [f32; 2],
+    tex_coords: [f32; 2],
+}
+
+implement_vertex!(ScreenVertex, position, tex_coords);
+
+// How the CHIP-8 pixels are scaled up to the Display window
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,  // Hard edged pixels
+    Bilinear, // Pixels blend into their neighbours
+    Crt,      // Hard pixels with dark scanline gaps
+}
+
+impl Filter {
+    pub const ALL: [Filter; 3] = [Filter::Nearest, Filter::Bilinear, Filter::Crt];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "Nearest",
+            Filter::Bilinear => "Bilinear",
+            Filter::Crt => "CRT",
+        }
+    }
+}
+
+// The program drawing the CHIP-8 pixels and one scaling them up per filter,
+// with the quad the filters draw onto
+pub struct Programs {
+    pixels: glium::Program,
+    nearest: glium::Program,
+    bilinear: glium::Program,
+    crt: glium::Program,
+    quad: glium::VertexBuffer<ScreenVertex>,
+}
+
+// Anything that can go wrong while rendering a frame. These are usually
+// transient driver errors, the frame is dropped and the next one tried.
+#[derive(Debug)]
+pub enum RenderError {
+    VertexBuffer(glium::vertex::BufferCreationError),
+    Texture(glium::texture::TextureCreationError),
+    Draw(glium::DrawError),
+    Gui(RendererError),
+    SwapBuffers(glium::SwapBuffersError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenderError::VertexBuffer(why) => write!(f, "Couldn't create vertex buffer: {:?}", why),
+            RenderError::Texture(why) => write!(f, "Couldn't create texture: {:?}", why),
+            RenderError::Draw(why) => write!(f, "Couldn't draw: {}", why),
+            RenderError::Gui(why) => write!(f, "Couldn't render GUI: {:?}", why),
+            RenderError::SwapBuffers(why) => write!(f, "Couldn't swap buffers: {:?}", why),
+        }
+    }
+}
+
+impl From<glium::vertex::BufferCreationError> for RenderError {
+    fn from(why: glium::vertex::BufferCreationError) -> RenderError {
+        RenderError::VertexBuffer(why)
+    }
+}
+
+impl From<glium::texture::TextureCreationError> for RenderError {
+    fn from(why: glium::texture::TextureCreationError) -> RenderError {
+        RenderError::Texture(why)
+    }
+}
+
+impl From<glium::DrawError> for RenderError {
+    fn from(why: glium::DrawError) -> RenderError {
+        RenderError::Draw(why)
+    }
+}
+
+impl From<RendererError> for RenderError {
+    fn from(why: RendererError) -> RenderError {
+        RenderError::Gui(why)
+    }
+}
+
+impl From<glium::SwapBuffersError> for RenderError {
+    fn from(why: glium::SwapBuffersError) -> RenderError {
+        RenderError::SwapBuffers(why)
+    }
+}
+
 pub fn create_window() -> (glium::Display, glutin::EventsLoop) {
     let events_loop = glutin::EventsLoop::new();
     let window_builder = glutin::WindowBuilder::new()
@@ -23,25 +123,136 @@ pub fn create_window() -> (glium::Display, glutin::EventsLoop) {
     (display, events_loop)
 }
 
-pub fn generate_program(display: &glium::Display) -> glium::Program {
+pub fn generate_program(display: &glium::Display) -> Programs {
     let vertex_shader_src = include_str!("shaders/display.vert");
     let fragment_shader_src = include_str!("shaders/display.frag");
-    glium::Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap()
+    let pixels =
+        glium::Program::from_source(display, vertex_shader_src, fragment_shader_src, None).unwrap();
+
+    let screen_shader_src = include_str!("shaders/screen.vert");
+    let filter = |fragment_shader_src| {
+        glium::Program::from_source(display, screen_shader_src, fragment_shader_src, None).unwrap()
+    };
+
+    Programs {
+        pixels: pixels,
+        nearest: filter(include_str!("shaders/nearest.frag")),
+        bilinear: filter(include_str!("shaders/bilinear.frag")),
+        crt: filter(include_str!("shaders/crt.frag")),
+        quad: glium::VertexBuffer::new(display, &screen_quad()).unwrap(),
+    }
+}
+
+pub fn create_vertex_buffer(
+    display: &glium::Display,
+    state: &State,
+    palette: &Palette,
+    phosphor: Option<&Phosphor>,
+) -> Result<glium::VertexBuffer<Vertex>, RenderError> {
+    let shape = generate_display(state, palette, phosphor);
+    Ok(glium::VertexBuffer::new(display, &shape)?)
+}
+
+// Size of the screen in the Display window. The vertices always fill the
+// texture, so the texture's height sets how tall a pixel is compared to its
+// width. Pixels are square at 1.0.
+pub fn display_size(pixel_aspect: f32) -> [f32; 2] {
+    let pixel_width = DISPLAY_WIDTH / 64.0;
+    [DISPLAY_WIDTH, (pixel_width * pixel_aspect * 32.0).round()]
+}
+
+// Draws the CHIP-8 screen into a texture for the Display window. The pixels
+// go into a texture with one texel each first, the filter's shader then
+// scales that up to the size of the window.
+pub fn render_texture(
+    display: &glium::Display,
+    vertex_buffer: &glium::VertexBuffer<Vertex>,
+    programs: &Programs,
+    filter: Filter,
+    size: [f32; 2],
+) -> Result<glium::Texture2d, RenderError> {
+    use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+
+    let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+    let screen = glium::Texture2d::empty(display, 64, 32)?;
+    screen.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+    screen.as_surface().draw(
+        vertex_buffer,
+        &indices,
+        &programs.pixels,
+        &glium::uniforms::EmptyUniforms,
+        &Default::default(),
+    )?;
+
+    let uniforms = uniform! {
+        screen: screen
+            .sampled()
+            .magnify_filter(MagnifySamplerFilter::Linear)
+            .minify_filter(MinifySamplerFilter::Linear),
+        screen_size: [64.0f32, 32.0],
+    };
+    let program = match filter {
+        Filter::Nearest => &programs.nearest,
+        Filter::Bilinear => &programs.bilinear,
+        Filter::Crt => &programs.crt,
+    };
+    let texture = glium::Texture2d::empty(display, size[0] as u32, size[1] as u32)?;
+    texture.as_surface().clear_color(0.0, 0.0, 0.0, 0.0);
+    texture.as_surface().draw(
+        &programs.quad,
+        &indices,
+        program,
+        &uniforms,
+        &Default::default(),
+    )?;
+    Ok(texture)
+}
+
+// Two triangles covering the whole target, the screen texture stretched over
+// them the way up the pixels were drawn
+fn screen_quad() -> [ScreenVertex; 6] {
+    let corner = |x: f32, y: f32| ScreenVertex {
+        position: [x * 2.0 - 1.0, y * 2.0 - 1.0],
+        tex_coords: [x, y],
+    };
+    [
+        corner(0.0, 0.0),
+        corner(1.0, 0.0),
+        corner(0.0, 1.0),
+        corner(1.0, 0.0),
+        corner(1.0, 1.0),
+        corner(0.0, 1.0),
+    ]
 }
 
-pub fn generate_display(state: &State) -> std::vec::Vec<Vertex> {
+// Only reads the front buffer, so with double buffering the vertices never
+// show a frame half drawn: it changes at a vblank, after all of the frame's
+// instructions ran, or when presented every few vblanks only then. With
+// persistence, dark pixels that are still fading are blended from the
+// background towards the color they were lit with.
+pub fn generate_display(
+    state: &State,
+    palette: &Palette,
+    phosphor: Option<&Phosphor>,
+) -> std::vec::Vec<Vertex> {
     let mut vertices = std::vec::Vec::new();
-    let mut color: [f32; 4];
-    for (row_no, row) in state.display.data.iter().enumerate() {
+    for (row_no, row) in state.display.front().iter().enumerate() {
         for (pixel_no, pixel) in row.iter().enumerate() {
             let x = PIXELSIZE_X * pixel_no as f32 - 1.0;
             let y = PIXELSIZE_Y * row_no as f32 - 1.0;
 
-            if *pixel > 0 {
-                color = [1.0, 1.0, 1.0, 1.0];
-            } else {
-                color = [0.0, 0.0, 0.0, 0.0];
-            }
+            let color = match phosphor {
+                Some(phosphor) if *pixel == 0 => {
+                    let (value, level) = phosphor.pixel(pixel_no, row_no);
+                    let lit = palette[(value & 0b11) as usize];
+                    let mut color = palette[0];
+                    for (channel, lit) in color.iter_mut().zip(lit.iter()) {
+                        *channel += (lit - *channel) * level;
+                    }
+                    color
+                }
+                _ => palette[(*pixel & 0b11) as usize],
+            };
 
             vertices.push(Vertex {
                 position: [x, y],
@@ -0,0 +1,10 @@
+#version 140
+
+uniform sampler2D screen;
+in vec2 v_tex_coords;
+out vec4 color;
+
+// The linear sampler blends the four nearest CHIP-8 pixels
+void main() {
+    color = texture(screen, v_tex_coords);
+}
@@ -0,0 +1,16 @@
+#version 140
+
+uniform sampler2D screen;
+uniform vec2 screen_size;
+in vec2 v_tex_coords;
+out vec4 color;
+
+// Hard pixels darkened towards the top and bottom of every row, like the gaps
+// between the scanlines of a CRT
+void main() {
+    vec2 position = v_tex_coords * screen_size;
+    vec2 texel = (floor(position) + 0.5) / screen_size;
+    vec4 pixel = texture(screen, texel);
+    float scanline = sin(fract(position.y) * 3.14159265);
+    color = vec4(pixel.rgb * mix(0.55, 1.0, scanline), pixel.a);
+}
@@ -0,0 +1,12 @@
+#version 140
+
+uniform sampler2D screen;
+uniform vec2 screen_size;
+in vec2 v_tex_coords;
+out vec4 color;
+
+// Samples the middle of the CHIP-8 pixel so the edges stay hard
+void main() {
+    vec2 texel = (floor(v_tex_coords * screen_size) + 0.5) / screen_size;
+    color = texture(screen, texel);
+}
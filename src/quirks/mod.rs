@@ -0,0 +1,265 @@
+// Behaviour differences between CHIP-8 interpreters that ROMs depend on
+// The defaults match what this interpreter has always done
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy into Vx instead of Vx in place
+    pub shift_uses_vy: bool,
+    // Fx55/Fx65 leave I pointing past the last register
+    pub load_store_increments_i: bool,
+    // Bxnn jumps to xnn + Vx instead of nnn + V0
+    pub jump_uses_vx: bool,
+    // Fx1E sets VF when I goes past 0xFFF
+    pub add_i_sets_vf: bool,
+    // Sprites wrap around the left and right screen edges instead of clipping
+    pub wrap_x: bool,
+    // Sprites wrap around the top and bottom screen edges instead of clipping
+    pub wrap_y: bool,
+    // DRW waits for the next 60Hz tick
+    pub display_wait: bool,
+    // Memory accesses through I wrap around the end of RAM instead of failing
+    pub mem_wrap: bool,
+    // Writes below 0x200, where the interpreter and font live, fail
+    pub protect_interpreter_area: bool,
+    // 5xy2/5xy3 store and load a range of registers (XO-CHIP), illegal
+    // opcodes otherwise
+    pub register_ranges: bool,
+    // F002/Fx3A set the audio pattern and pitch (XO-CHIP), illegal opcodes
+    // otherwise
+    pub xo_audio: bool,
+}
+
+// Number of quirk flags, the length of to_flags
+pub const QUIRK_COUNT: usize = 11;
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increments_i: false,
+            jump_uses_vx: false,
+            add_i_sets_vf: false,
+            wrap_x: true,
+            wrap_y: true,
+            display_wait: false,
+            mem_wrap: false,
+            protect_interpreter_area: false,
+            register_ranges: false,
+            xo_audio: false,
+        }
+    }
+}
+
+impl Quirks {
+    // The flags in declaration order, for the save state and per ROM files
+    pub fn to_flags(&self) -> [bool; QUIRK_COUNT] {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.jump_uses_vx,
+            self.add_i_sets_vf,
+            self.wrap_x,
+            self.wrap_y,
+            self.display_wait,
+            self.mem_wrap,
+            self.protect_interpreter_area,
+            self.register_ranges,
+            self.xo_audio,
+        ]
+    }
+
+    pub fn from_flags(flags: [bool; QUIRK_COUNT]) -> Quirks {
+        Quirks {
+            shift_uses_vy: flags[0],
+            load_store_increments_i: flags[1],
+            jump_uses_vx: flags[2],
+            add_i_sets_vf: flags[3],
+            wrap_x: flags[4],
+            wrap_y: flags[5],
+            display_wait: flags[6],
+            mem_wrap: flags[7],
+            protect_interpreter_area: flags[8],
+            register_ranges: flags[9],
+            xo_audio: flags[10],
+        }
+    }
+}
+
+// Named interpreter presets, each setting the whole Quirks struct
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Profile {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    XoChip,
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [
+        Profile::CosmacVip,
+        Profile::Chip48,
+        Profile::SuperChip,
+        Profile::XoChip,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name.to_lowercase().replace(' ', "-").as_str() {
+            "vip" | "cosmac-vip" => Some(Profile::CosmacVip),
+            "chip48" | "chip-48" => Some(Profile::Chip48),
+            "schip" | "super-chip" => Some(Profile::SuperChip),
+            "xochip" | "xo-chip" => Some(Profile::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::CosmacVip => "COSMAC VIP",
+            Profile::Chip48 => "CHIP-48",
+            Profile::SuperChip => "SUPER-CHIP",
+            Profile::XoChip => "XO-CHIP",
+        }
+    }
+
+    // COSMAC VIP: the original interpreter
+    // CHIP-48 and SUPER-CHIP: HP-48 calculators, Bxnn and in place shifts
+    // XO-CHIP: Octo, the VIP behaviour minus clipping and the display wait, I
+    // wraps around the 64KB of RAM
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Profile::CosmacVip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                add_i_sets_vf: false,
+                wrap_x: false,
+                wrap_y: false,
+                display_wait: true,
+                mem_wrap: false,
+                protect_interpreter_area: false,
+                register_ranges: false,
+                xo_audio: false,
+            },
+            Profile::Chip48 | Profile::SuperChip => Quirks {
+                shift_uses_vy: false,
+                load_store_increments_i: false,
+                jump_uses_vx: true,
+                add_i_sets_vf: false,
+                wrap_x: false,
+                wrap_y: false,
+                display_wait: false,
+                mem_wrap: false,
+                protect_interpreter_area: false,
+                register_ranges: false,
+                xo_audio: false,
+            },
+            Profile::XoChip => Quirks {
+                shift_uses_vy: true,
+                load_store_increments_i: true,
+                jump_uses_vx: false,
+                add_i_sets_vf: false,
+                wrap_x: true,
+                wrap_y: true,
+                display_wait: false,
+                mem_wrap: true,
+                protect_interpreter_area: false,
+                register_ranges: true,
+                xo_audio: true,
+            },
+        }
+    }
+
+    // Highest resolution the interpreter supports, the display is currently
+    // always emulated at 64x32
+    pub fn resolution(&self) -> (usize, usize) {
+        match self {
+            Profile::CosmacVip | Profile::Chip48 => (64, 32),
+            Profile::SuperChip | Profile::XoChip => (128, 64),
+        }
+    }
+
+    pub fn ram_size(&self) -> usize {
+        match self {
+            Profile::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+
+    // The VIP interpreter kept 12 return addresses, the later ones 16
+    pub fn stack_depth(&self) -> usize {
+        match self {
+            Profile::CosmacVip => 12,
+            _ => 16,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosmac_vip() {
+        let quirks = Profile::CosmacVip.quirks();
+        assert_eq!(true, quirks.shift_uses_vy);
+        assert_eq!(true, quirks.load_store_increments_i);
+        assert_eq!(false, quirks.jump_uses_vx);
+        assert_eq!(false, quirks.wrap_x);
+        assert_eq!(false, quirks.wrap_y);
+        assert_eq!(true, quirks.display_wait);
+        assert_eq!((64, 32), Profile::CosmacVip.resolution());
+        assert_eq!(0x1000, Profile::CosmacVip.ram_size());
+        assert_eq!(12, Profile::CosmacVip.stack_depth());
+    }
+
+    #[test]
+    fn chip48_and_super_chip() {
+        for profile in [Profile::Chip48, Profile::SuperChip].iter() {
+            let quirks = profile.quirks();
+            assert_eq!(false, quirks.shift_uses_vy);
+            assert_eq!(false, quirks.load_store_increments_i);
+            assert_eq!(true, quirks.jump_uses_vx);
+            assert_eq!(false, quirks.wrap_x);
+            assert_eq!(false, quirks.wrap_y);
+            assert_eq!(false, quirks.display_wait);
+        }
+        assert_eq!((64, 32), Profile::Chip48.resolution());
+        assert_eq!((128, 64), Profile::SuperChip.resolution());
+        assert_eq!(16, Profile::SuperChip.stack_depth());
+    }
+
+    #[test]
+    fn xo_chip() {
+        let quirks = Profile::XoChip.quirks();
+        assert_eq!(true, quirks.shift_uses_vy);
+        assert_eq!(true, quirks.load_store_increments_i);
+        assert_eq!(false, quirks.jump_uses_vx);
+        assert_eq!(true, quirks.wrap_x);
+        assert_eq!(true, quirks.wrap_y);
+        assert_eq!(true, quirks.mem_wrap);
+        assert_eq!(false, quirks.display_wait);
+        assert_eq!(true, quirks.register_ranges);
+        assert_eq!(true, quirks.xo_audio);
+        assert_eq!(0x10000, Profile::XoChip.ram_size());
+    }
+
+    #[test]
+    fn flags() {
+        for profile in Profile::ALL.iter() {
+            let quirks = profile.quirks();
+            assert_eq!(quirks, Quirks::from_flags(quirks.to_flags()));
+        }
+        assert_eq!(true, Profile::CosmacVip.quirks().to_flags()[6]);
+    }
+
+    #[test]
+    fn from_name() {
+        for profile in Profile::ALL.iter() {
+            assert_eq!(
+                Some(*profile),
+                Profile::from_name(&profile.name().to_lowercase())
+            );
+        }
+        assert_eq!(Some(Profile::SuperChip), Profile::from_name("schip"));
+        assert_eq!(None, Profile::from_name("chip-9"));
+    }
+}
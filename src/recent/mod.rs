@@ -0,0 +1,113 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// ROMs remembered in the File menu, older ones are dropped
+pub const MAX_RECENT: usize = 8;
+
+// Recently opened ROMs, newest first, stored one path per line
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RecentRoms {
+    pub paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    pub fn new() -> RecentRoms {
+        RecentRoms { paths: Vec::new() }
+    }
+
+    pub fn parse(text: &str) -> RecentRoms {
+        let mut recent = RecentRoms::new();
+        for line in text.lines().rev() {
+            let line = line.trim();
+            if !line.is_empty() {
+                recent.add(Path::new(line));
+            }
+        }
+        recent
+    }
+
+    // Moves the path to the front, a path opened again isn't listed twice
+    pub fn add(&mut self, path: &Path) {
+        self.paths.retain(|recent| recent != path);
+        self.paths.insert(0, path.to_path_buf());
+        self.paths.truncate(MAX_RECENT);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for path in self.paths.iter() {
+            text.push_str(&path.to_string_lossy());
+            text.push('\n');
+        }
+        text
+    }
+
+    // A missing or unreadable file is an empty list
+    pub fn load(path: &Path) -> RecentRoms {
+        fs::read_to_string(path)
+            .map(|text| RecentRoms::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+}
+
+// Where settings files are kept: %APPDATA% on Windows, $XDG_CONFIG_HOME or
+// ~/.config elsewhere. None when none of them is set.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    dir.map(|dir| dir.join("chipster8"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("recent_roms.txt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add() {
+        let mut recent = RecentRoms::new();
+
+        recent.add(Path::new("pong.ch8"));
+        recent.add(Path::new("tetris.ch8"));
+        recent.add(Path::new("pong.ch8"));
+        assert_eq!(
+            vec![PathBuf::from("pong.ch8"), PathBuf::from("tetris.ch8")],
+            recent.paths
+        );
+
+        for i in 0..MAX_RECENT {
+            recent.add(&PathBuf::from(format!("{}.ch8", i)));
+        }
+        assert_eq!(MAX_RECENT, recent.paths.len());
+        assert_eq!(PathBuf::from("7.ch8"), recent.paths[0]);
+        assert_eq!(false, recent.paths.contains(&PathBuf::from("pong.ch8")));
+    }
+
+    #[test]
+    fn parse() {
+        let recent = RecentRoms::parse("b.ch8\n\n  a.ch8\nb.ch8\n");
+        assert_eq!(
+            vec![PathBuf::from("b.ch8"), PathBuf::from("a.ch8")],
+            recent.paths
+        );
+        assert_eq!("b.ch8\na.ch8\n", recent.to_text());
+        assert_eq!(recent, RecentRoms::parse(&recent.to_text()));
+    }
+}
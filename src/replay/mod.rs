@@ -0,0 +1,278 @@
+use crate::state::State;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Binary recordings start with these, like save states multi byte values are
+// big endian
+const MAGIC: &[u8; 4] = b"C8IN";
+const VERSION: u8 = 1;
+
+// Keypad state of every logical frame of a deterministic run and the seed RND
+// started from, recorded with record_inputs. Replaying it from a reset with
+// the same ROM and settings reproduces the run exactly. Saved as binary to a
+// .bin file, anything else gets text that can be edited by hand: the seed
+// then one frame per line with the keypad as a hex mask, bit n for key n:
+//     seed 2a
+//     0000
+//     0020
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRecording {
+    pub seed: u64,
+    pub frames: Vec<u16>,
+}
+
+// Bit n is set while key n is held
+pub fn keypad_mask(keypad: &[bool; 16]) -> u16 {
+    keypad
+        .iter()
+        .enumerate()
+        .filter(|(_, pressed)| **pressed)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+impl InputRecording {
+    pub fn new(seed: u64) -> InputRecording {
+        InputRecording {
+            seed: seed,
+            frames: Vec::new(),
+        }
+    }
+
+    // Appends the keys held for the frame about to run
+    pub fn record_inputs(&mut self, state: &State) {
+        self.frames.push(keypad_mask(&state.keypad));
+    }
+
+    // Holds the keys recorded for the frame, through set_key so Fx0A sees the
+    // presses. Returns false once the recording is over.
+    pub fn replay_inputs(&self, state: &mut State, frame: usize) -> bool {
+        let mask = match self.frames.get(frame) {
+            Some(mask) => *mask,
+            None => return false,
+        };
+        for key in 0..16 {
+            state.set_key(key, mask & 1 << key != 0);
+        }
+        true
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("seed {:x}\n", self.seed);
+        for mask in self.frames.iter() {
+            text.push_str(&format!("{:04x}\n", mask));
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<InputRecording, String> {
+        let mut lines = text.lines();
+        let seed = lines
+            .next()
+            .map(|line| line.trim())
+            .filter(|line| line.starts_with("seed "))
+            .and_then(|line| u64::from_str_radix(line[5..].trim(), 16).ok())
+            .ok_or_else(|| String::from("Missing seed on line 1"))?;
+        let mut recording = InputRecording::new(seed);
+        for (line_no, line) in lines.enumerate() {
+            let mask = u16::from_str_radix(line.trim(), 16)
+                .map_err(|_| format!("Invalid keys {} on line {}", line.trim(), line_no + 2))?;
+            recording.frames.push(mask);
+        }
+        Ok(recording)
+    }
+
+    // Magic and version, the seed, the frame count and a u16 mask per frame
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17 + self.frames.len() * 2);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.seed.to_be_bytes());
+        data.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        for mask in self.frames.iter() {
+            data.extend_from_slice(&mask.to_be_bytes());
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<InputRecording, String> {
+        if !data.starts_with(MAGIC) {
+            return Err(String::from("Not an input recording"));
+        }
+        if data.len() < 17 {
+            return Err(String::from("Input recording is cut short"));
+        }
+        if data[4] != VERSION {
+            return Err(format!("Unsupported input recording version {}", data[4]));
+        }
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(&data[5..13]);
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&data[13..17]);
+        let count = u32::from_be_bytes(count) as usize;
+        let masks = &data[17..];
+        if masks.len() != count * 2 {
+            return Err(String::from("Input recording is cut short"));
+        }
+
+        let mut recording = InputRecording::new(u64::from_be_bytes(seed));
+        recording.frames = masks
+            .chunks(2)
+            .map(|mask| (mask[0] as u16) << 8 | mask[1] as u16)
+            .collect();
+        Ok(recording)
+    }
+
+    // Either format, binary ones are told apart by their magic
+    pub fn load(path: &Path) -> Result<InputRecording, String> {
+        let data =
+            fs::read(path).map_err(|why| format!("Couldn't read {}: {}", path.display(), why))?;
+        let recording = if data.starts_with(MAGIC) {
+            InputRecording::from_bytes(&data)
+        } else {
+            InputRecording::parse(&String::from_utf8_lossy(&data))
+        };
+        recording.map_err(|why| format!("{}: {}", path.display(), why))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if path
+            .extension()
+            .map_or(false, |extension| extension == "bin")
+        {
+            fs::write(path, self.to_bytes())
+        } else {
+            fs::write(path, self.to_text())
+        }
+    }
+}
+
+// Plays a recording back a logical frame at a time
+pub struct InputPlayer {
+    pub recording: InputRecording,
+    frame: usize, // Next frame to play
+}
+
+impl InputPlayer {
+    pub fn new(recording: InputRecording) -> InputPlayer {
+        InputPlayer {
+            recording: recording,
+            frame: 0,
+        }
+    }
+
+    // Holds the keys of the next frame, false once every frame was played
+    pub fn next_frame(&mut self, state: &mut State) -> bool {
+        if !self.recording.replay_inputs(state, self.frame) {
+            return false;
+        }
+        self.frame += 1;
+        true
+    }
+
+    pub fn frames_played(&self) -> usize {
+        self.frame
+    }
+
+    pub fn finished(&self) -> bool {
+        self.frame >= self.recording.frames.len()
+    }
+
+    // Back to the first frame, for when the ROM is reset
+    pub fn rewind(&mut self) {
+        self.frame = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu;
+
+    // Draws a pixel at a random position every time key 5 is checked held:
+    //     LD V1, 05; LD I, 214; SKNP V1; CALL 20C; JMP 204; (unused)
+    //     RND V0, 3F; RND V2, 1F; DRW V0, V2, 1; RET; sprite
+    const ROM: [u8; 21] = [
+        0x61, 0x05, 0xA2, 0x14, 0xE1, 0xA1, 0x22, 0x0C, 0x12, 0x04, 0x00, 0x00, 0xC0, 0x3F, 0xC2,
+        0x1F, 0xD0, 0x21, 0x00, 0xEE, 0x80,
+    ];
+    const FRAMES: usize = 60;
+
+    fn seeded_state(seed: u64) -> State {
+        let mut state = State::new();
+        state.set_seed(Some(seed));
+        state.reload(&ROM).unwrap();
+        state
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut state = seeded_state(42);
+        let mut recording = InputRecording::new(42);
+        for frame in 0..FRAMES {
+            let held = (10..13).contains(&frame) || frame == 30;
+            state.set_key(5, held);
+            recording.record_inputs(&state);
+            assert_eq!(true, cpu::run_frame(&mut state, cpu::DEFAULT_IPS));
+        }
+        let recorded_hash = state.display.frame_hash();
+        assert_eq!(false, state.display.is_clear());
+
+        let recording = InputRecording::parse(&recording.to_text()).unwrap();
+        let mut state = seeded_state(recording.seed);
+        let mut frame = 0;
+        while recording.replay_inputs(&mut state, frame) {
+            assert_eq!(true, cpu::run_frame(&mut state, cpu::DEFAULT_IPS));
+            frame += 1;
+        }
+        assert_eq!(FRAMES, frame);
+        assert_eq!(recorded_hash, state.display.frame_hash());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let mut recording = InputRecording::new(0x0123_4567_89ab_cdef);
+        recording.frames = vec![0x0000, 0x0020, 0x8001, 0x0020];
+
+        let data = recording.to_bytes();
+        assert_eq!(b"C8IN", &data[0..4]);
+        assert_eq!(Ok(recording.clone()), InputRecording::from_bytes(&data));
+        assert!(InputRecording::from_bytes(&data[0..data.len() - 1]).is_err());
+        assert!(InputRecording::from_bytes(b"seed 2a\n").is_err());
+
+        let path = std::env::temp_dir().join("chipster8_inputs_test.bin");
+        recording.save(&path).unwrap();
+        assert_eq!(b"C8IN", &fs::read(&path).unwrap()[0..4]);
+        let loaded = InputRecording::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(Ok(recording.clone()), loaded);
+
+        // The player holds the keys frame by frame
+        let mut player = InputPlayer::new(recording);
+        let mut state = State::new();
+        let mut held = Vec::new();
+        while player.next_frame(&mut state) {
+            held.push(keypad_mask(&state.keypad));
+        }
+        assert_eq!(vec![0x0000, 0x0020, 0x8001, 0x0020], held);
+        assert_eq!(true, player.finished());
+        assert_eq!(4, player.frames_played());
+        player.rewind();
+        assert_eq!(false, player.finished());
+    }
+
+    #[test]
+    fn parse() {
+        let recording = InputRecording::parse("seed 2a\n0000\n0020\n").unwrap();
+        assert_eq!(42, recording.seed);
+        assert_eq!(vec![0x0000, 0x0020], recording.frames);
+        assert_eq!("seed 2a\n0000\n0020\n", recording.to_text());
+        assert!(InputRecording::parse("0000\n").is_err());
+        assert!(InputRecording::parse("seed 2a\nxyz\n").is_err());
+
+        let mut keypad = [false; 16];
+        keypad[5] = true;
+        keypad[0xF] = true;
+        assert_eq!(0x8020, keypad_mask(&keypad));
+    }
+}
@@ -0,0 +1,121 @@
+use crate::instruction;
+use flate2::read::GzDecoder;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+// First two bytes of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// How many more of the words have to decode after swapping for Auto to swap.
+// Sprites and other data decode as anything, so both orders get some.
+const BYTESWAP_MARGIN: f32 = 0.25;
+
+// Byte order of the 16 bit words in a ROM file. CHIP-8 is big endian, some
+// dumps were saved a word at a time on little endian machines.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Byteswap {
+    Off,
+    On,
+    Auto, // Swap when far more of the words decode that way
+}
+
+// Reads a ROM file, .ch8.gz and other gzipped ROMs are decompressed
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    unpack(fs::read(path)?)
+}
+
+// Decompresses gzipped data, anything else is returned as it is
+pub fn unpack(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !data.starts_with(&GZIP_MAGIC) {
+        return Ok(data);
+    }
+    let mut rom = Vec::new();
+    GzDecoder::new(data.as_slice()).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+// Swaps the bytes of every 2 byte word, an odd last byte stays where it is
+pub fn swap_bytes(rom: &mut [u8]) {
+    for word in rom.chunks_mut(2) {
+        word.reverse();
+    }
+}
+
+// Share of the words that are an instruction. SYS and zeroed words don't
+// count, they're what swapped code and padding mostly decode as.
+pub fn decodable_ratio(rom: &[u8]) -> f32 {
+    let words = rom.len() / 2;
+    if words == 0 {
+        return 0.0;
+    }
+    let decodable = rom
+        .chunks_exact(2)
+        .map(|word| instruction::family((word[0] as u16) << 8 | word[1] as u16))
+        .filter(|family| match instruction::family_pattern(*family) {
+            "0nnn" | "????" => false,
+            _ => true,
+        })
+        .count();
+    decodable as f32 / words as f32
+}
+
+pub fn needs_swap(rom: &[u8], byteswap: Byteswap) -> bool {
+    match byteswap {
+        Byteswap::Off => false,
+        Byteswap::On => true,
+        Byteswap::Auto => {
+            let mut swapped = rom.to_vec();
+            swap_bytes(&mut swapped);
+            decodable_ratio(&swapped) > decodable_ratio(rom) + BYTESWAP_MARGIN
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V0, 01; DRW V0, V0, 1; JP 204
+    const ROM: [u8; 6] = [0x60, 0x01, 0xD0, 0x01, 0x12, 0x04];
+
+    #[test]
+    fn unpack_gzip() {
+        let gzipped = vec![
+            0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x4b, 0x60, 0xbc, 0xc0,
+            0x28, 0xc4, 0x02, 0x00, 0x2d, 0xe0, 0x59, 0xdc, 0x06, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(ROM.to_vec(), unpack(gzipped).unwrap());
+    }
+
+    #[test]
+    fn unpack_raw() {
+        assert_eq!(ROM.to_vec(), unpack(ROM.to_vec()).unwrap());
+    }
+
+    #[test]
+    fn byteswapped_rom() {
+        let mut rom = vec![0x01, 0x60, 0x01, 0xD0, 0x04, 0x12];
+        assert_eq!(false, needs_swap(&rom, Byteswap::Off));
+        assert_eq!(true, needs_swap(&rom, Byteswap::Auto));
+        assert_eq!(false, needs_swap(&ROM, Byteswap::Auto));
+
+        swap_bytes(&mut rom);
+        assert_eq!(ROM.to_vec(), rom);
+        assert_eq!(
+            "0200: LD V0, 01 (6001)\n0202: DRW V0, V0, 1 (D001)\n0204: JMP 204 (1204)\n",
+            instruction::disassemble(&rom, 0x200)
+        );
+        assert_eq!(1.0, decodable_ratio(&rom));
+
+        // An odd last byte is left alone
+        let mut odd = vec![0x01, 0x60, 0xFF];
+        swap_bytes(&mut odd);
+        assert_eq!(vec![0x60, 0x01, 0xFF], odd);
+    }
+
+    #[test]
+    fn unpack_corrupt_gzip() {
+        assert!(unpack(vec![0x1f, 0x8b, 0x08, 0x00]).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::quirks::{Quirks, QUIRK_COUNT};
+use crate::recent;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Quirks picked in the GUI for each ROM, keyed by the ROM's CRC-32 so a
+// renamed or moved file keeps them. Stored one ROM per line as the CRC in hex
+// and the quirk flags as 0s and 1s in Quirks::to_flags order:
+//     1a2b3c4d 11000011000
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RomQuirks {
+    by_crc: HashMap<u32, Quirks>,
+}
+
+impl RomQuirks {
+    pub fn new() -> RomQuirks {
+        RomQuirks {
+            by_crc: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, crc: u32) -> Option<Quirks> {
+        self.by_crc.get(&crc).cloned()
+    }
+
+    pub fn set(&mut self, crc: u32, quirks: Quirks) {
+        self.by_crc.insert(crc, quirks);
+    }
+
+    // Lines that don't parse are skipped, a damaged file only loses them
+    pub fn parse(text: &str) -> RomQuirks {
+        let mut rom_quirks = RomQuirks::new();
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let crc = parts
+                .next()
+                .and_then(|crc| u32::from_str_radix(crc, 16).ok());
+            let flags = parts.next().and_then(parse_flags);
+            if let (Some(crc), Some(flags)) = (crc, flags) {
+                rom_quirks.set(crc, Quirks::from_flags(flags));
+            }
+        }
+        rom_quirks
+    }
+
+    // Sorted by CRC so the file doesn't reshuffle on every save
+    pub fn to_text(&self) -> String {
+        let mut crcs: Vec<&u32> = self.by_crc.keys().collect();
+        crcs.sort();
+        let mut text = String::new();
+        for crc in crcs {
+            let flags: String = self.by_crc[crc]
+                .to_flags()
+                .iter()
+                .map(|flag| if *flag { '1' } else { '0' })
+                .collect();
+            text.push_str(&format!("{:08x} {}\n", crc, flags));
+        }
+        text
+    }
+
+    // A missing or unreadable file is an empty store
+    pub fn load(path: &Path) -> RomQuirks {
+        fs::read_to_string(path)
+            .map(|text| RomQuirks::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.to_text())
+    }
+}
+
+// Files from before the last quirks were added have fewer flags, the missing
+// quirks are off
+const OLDEST_FLAG_COUNT: usize = 8;
+
+fn parse_flags(text: &str) -> Option<[bool; QUIRK_COUNT]> {
+    let mut flags = [false; QUIRK_COUNT];
+    if text.len() < OLDEST_FLAG_COUNT || text.len() > flags.len() {
+        return None;
+    }
+    for (flag, c) in flags.iter_mut().zip(text.chars()) {
+        *flag = match c {
+            '0' => false,
+            '1' => true,
+            _ => return None,
+        };
+    }
+    Some(flags)
+}
+
+// Next to the recent ROMs list
+pub fn default_path() -> Option<PathBuf> {
+    recent::config_dir().map(|dir| dir.join("rom_quirks.txt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_and_lookup() {
+        let mut rom_quirks = RomQuirks::new();
+        let mut quirks = Quirks::default();
+        quirks.load_store_increments_i = true;
+
+        rom_quirks.set(0x1234abcd, quirks);
+        assert_eq!(Some(quirks), rom_quirks.get(0x1234abcd));
+        assert_eq!(None, rom_quirks.get(0x1234abce));
+
+        // Setting them again replaces the old ones
+        rom_quirks.set(0x1234abcd, Quirks::default());
+        assert_eq!(Some(Quirks::default()), rom_quirks.get(0x1234abcd));
+    }
+
+    #[test]
+    fn parse_and_to_text() {
+        let mut rom_quirks = RomQuirks::new();
+        let mut quirks = Quirks::default();
+        quirks.shift_uses_vy = true;
+        rom_quirks.set(0xdeadbeef, quirks);
+        rom_quirks.set(0x00000001, Quirks::default());
+
+        let text = rom_quirks.to_text();
+        assert_eq!("00000001 00001100000\ndeadbeef 10001100000\n", text);
+        assert_eq!(rom_quirks, RomQuirks::parse(&text));
+        // Damaged lines are skipped
+        let parsed = RomQuirks::parse("deadbeef 1000\nxyz 10001100000\n00000002 10001100000\n");
+        assert_eq!(None, parsed.get(0xdeadbeef));
+        assert_eq!(Some(quirks), parsed.get(0x2));
+        // Older files without the last quirks
+        assert_eq!(
+            Some(quirks),
+            RomQuirks::parse("00000003 10001100\n").get(0x3)
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let path = std::env::temp_dir().join("chipster8_rom_quirks_test.txt");
+        let mut rom_quirks = RomQuirks::new();
+        rom_quirks.set(0xabc, Quirks::default());
+
+        rom_quirks.save(&path).unwrap();
+        let loaded = RomQuirks::load(&path);
+        let _ = fs::remove_file(&path);
+        assert_eq!(rom_quirks, loaded);
+        assert_eq!(RomQuirks::new(), RomQuirks::load(&path));
+    }
+}
@@ -0,0 +1,474 @@
+use crate::quirks::{Quirks, QUIRK_COUNT};
+use crate::state::State;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Binary snapshot of the machine: registers, timers, stack, quirks, display
+// and RAM. Multi byte values are big endian like the CHIP-8 itself.
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 9;
+
+// Quick save slots, 0 to 9
+pub const SLOTS: u8 = 10;
+
+// Steps that can be undone, older snapshots are dropped
+const HISTORY_LEN: usize = 256;
+
+// Timed autosaves kept per ROM, the oldest is written over
+pub const AUTOSAVE_FILES: usize = 2;
+
+pub fn save(state: &State) -> Vec<u8> {
+    let mut data = Vec::with_capacity(64 + 64 * 32 + state.ram.len());
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+
+    data.extend_from_slice(&state.i.to_be_bytes());
+    data.extend_from_slice(&state.pc.to_be_bytes());
+    data.push(state.sp);
+    data.push(state.dt);
+    data.push(state.st);
+    data.extend_from_slice(&state.v);
+    data.push(state.stack.len() as u8);
+    for value in state.stack.iter() {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.push(state.halted as u8);
+    data.push(state.exited as u8);
+
+    for quirk in state.quirks.to_flags().iter() {
+        data.push(*quirk as u8);
+    }
+
+    data.push(state.display.plane_mask);
+    for row in state.display.data.iter() {
+        data.extend_from_slice(row);
+    }
+
+    // XO-CHIP audio, a flag for whether a pattern was loaded
+    data.push(state.audio_pattern.is_some() as u8);
+    data.extend_from_slice(&state.audio_pattern.unwrap_or([0; 16]));
+    data.push(state.pitch);
+
+    // Deterministic RND, a flag for whether there's a seed
+    let rng = state.rng_state();
+    data.push(rng.is_some() as u8);
+    let (seed, generator) = rng.unwrap_or((0, 0));
+    data.extend_from_slice(&seed.to_be_bytes());
+    data.extend_from_slice(&generator.to_be_bytes());
+
+    data.extend_from_slice(&(state.ram.len() as u32).to_be_bytes());
+    data.extend_from_slice(&state.ram);
+    data
+}
+
+// Restores a snapshot made by save, the state is left untouched on error
+pub fn load(state: &mut State, data: &[u8]) -> Result<(), String> {
+    let mut reader = Reader { data: data, pos: 0 };
+    if reader.take(4)? != MAGIC {
+        return Err(String::from("Not a save state"));
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(format!("Unsupported save state version {}", version));
+    }
+
+    let i = reader.u16()?;
+    let pc = reader.u16()?;
+    let sp = reader.u8()?;
+    let dt = reader.u8()?;
+    let st = reader.u8()?;
+    let mut v = [0u8; 16];
+    v.copy_from_slice(reader.take(16)?);
+    let mut stack = vec![0u16; reader.u8()? as usize];
+    for value in stack.iter_mut() {
+        *value = reader.u16()?;
+    }
+    // SP indexes the stack, past its end every RET and the stack view fail
+    if sp as usize > stack.len() {
+        return Err(format!(
+            "Stack pointer {} is past the {} entry stack",
+            sp,
+            stack.len()
+        ));
+    }
+    let halted = reader.u8()? != 0;
+    let exited = reader.u8()? != 0;
+
+    let mut flags = [false; QUIRK_COUNT];
+    for flag in flags.iter_mut() {
+        *flag = reader.u8()? != 0;
+    }
+    let quirks = Quirks::from_flags(flags);
+
+    let plane_mask = reader.u8()?;
+    let display = reader.take(64 * 32)?;
+    let has_pattern = reader.u8()? != 0;
+    let mut pattern = [0u8; 16];
+    pattern.copy_from_slice(reader.take(16)?);
+    let pitch = reader.u8()?;
+    let has_rng = reader.u8()? != 0;
+    let seed = reader.u64()?;
+    let generator = reader.u64()?;
+    let ram_size = reader.u32()? as usize;
+    let ram = reader.take(ram_size)?;
+
+    state.i = i;
+    state.pc = pc;
+    state.sp = sp;
+    state.dt = dt;
+    state.st = st;
+    state.v = v;
+    state.stack = stack;
+    state.halted = halted;
+    state.fault = None;
+    state.exited = exited;
+    state.quirks = quirks;
+    state.display.plane_mask = plane_mask;
+    for (row, saved) in state.display.data.iter_mut().zip(display.chunks(64)) {
+        row.copy_from_slice(saved);
+    }
+    state.display.dirty = true;
+    state.display.present();
+    state.audio_pattern = if has_pattern { Some(pattern) } else { None };
+    state.pitch = pitch;
+    // Without a seed RND keeps to whatever the session uses
+    if has_rng {
+        state.restore_rng(seed, generator);
+    }
+    state.ram = ram.to_vec();
+    Ok(())
+}
+
+pub fn write(state: &State, path: &Path) -> io::Result<()> {
+    fs::write(path, save(state))
+}
+
+// Finishes pending work before the window closes, the autosave is written
+// if there is one. Returns straight away when there's nothing to write.
+pub fn shutdown(state: &State, autosave_path: Option<&Path>) -> io::Result<bool> {
+    match autosave_path {
+        Some(path) => write(state, path).map(|_| true),
+        None => Ok(false),
+    }
+}
+
+pub fn read(state: &mut State, path: &Path) -> io::Result<()> {
+    let data = fs::read(path)?;
+    load(state, &data).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+// Save state file of a slot next to the ROM, pong.ch8 has pong.ch8.state0 to
+// pong.ch8.state9
+pub fn slot_path(rom_path: &Path, slot: u8) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".state{}", slot));
+    rom_path.with_file_name(name)
+}
+
+// Timed autosave file next to the ROM, pong.ch8 has pong.ch8.autosave0 and
+// pong.ch8.autosave1
+pub fn autosave_path(rom_path: &Path, file: usize) -> PathBuf {
+    let mut name = rom_path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".autosave{}", file));
+    rom_path.with_file_name(name)
+}
+
+// Says when the next timed autosave is due. Only running time counts, the
+// time a ROM sits paused or halted doesn't bring the next save closer.
+pub struct Autosaver {
+    pub interval: Duration,
+    elapsed: Duration, // Running time since the last save
+    next_file: usize,
+}
+
+impl Autosaver {
+    pub fn new(interval: Duration) -> Autosaver {
+        Autosaver {
+            interval: interval,
+            elapsed: Duration::from_secs(0),
+            next_file: 0,
+        }
+    }
+
+    // Returns true when a save is due. Saves that fell behind aren't caught
+    // up, one is enough.
+    pub fn update(&mut self, running: bool, elapsed: Duration) -> bool {
+        if !running {
+            return false;
+        }
+        self.elapsed += elapsed;
+        if self.elapsed < self.interval {
+            return false;
+        }
+        self.elapsed = Duration::from_secs(0);
+        true
+    }
+
+    // Where the due save goes, taking the AUTOSAVE_FILES files in turn
+    pub fn next_path(&mut self, rom_path: &Path) -> PathBuf {
+        let path = autosave_path(rom_path, self.next_file);
+        self.next_file = (self.next_file + 1) % AUTOSAVE_FILES;
+        path
+    }
+}
+
+// Snapshots taken before each step so Step Back can undo it, timers and
+// display included
+pub struct History {
+    snapshots: VecDeque<Vec<u8>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            snapshots: VecDeque::with_capacity(HISTORY_LEN),
+        }
+    }
+
+    pub fn push(&mut self, state: &State) {
+        if self.snapshots.len() == HISTORY_LEN {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(save(state));
+    }
+
+    // Restores the state from before the last step, returns false when
+    // there's nothing left to undo
+    pub fn step_back(&mut self, state: &mut State) -> bool {
+        match self.snapshots.pop_back() {
+            Some(snapshot) => load(state, &snapshot).is_ok(),
+            None => false,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.pos + len > self.data.len() {
+            return Err(String::from("Save state is truncated"));
+        }
+        self.pos += len;
+        Ok(&self.data[(self.pos - len)..self.pos])
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok((bytes[0] as u32) << 24
+            | (bytes[1] as u32) << 16
+            | (bytes[2] as u32) << 8
+            | bytes[3] as u32)
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok((self.u32()? as u64) << 32 | self.u32()? as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut state = State::new();
+        state.v[3] = 0x33;
+        state.i = 0x345;
+        state.pc = 0x2AE;
+        state.dt = 12;
+        state.set_stack_depth(24);
+        state.push(0x204).unwrap();
+        state.quirks.shift_uses_vy = true;
+        state.display.display_sprite(5, 6, &[0xF0]);
+        state.ram[0x300] = 0x77;
+        state.audio_pattern = Some([0xF0; 16]);
+        state.pitch = 80;
+
+        let mut restored = State::with_ram_size(0x2000);
+        load(&mut restored, &save(&state)).unwrap();
+        assert_eq!(0x33, restored.v[3]);
+        assert_eq!(0x345, restored.i);
+        assert_eq!(0x2AE, restored.pc);
+        assert_eq!(12, restored.dt);
+        assert_eq!(24, restored.stack.len());
+        assert_eq!(Ok(0x204), restored.pop());
+        assert_eq!(state.quirks, restored.quirks);
+        assert_eq!(state.display.frame_hash(), restored.display.frame_hash());
+        assert_eq!(state.ram, restored.ram);
+        assert_eq!(Some([0xF0; 16]), restored.audio_pattern);
+        assert_eq!(80, restored.pitch);
+    }
+
+    #[test]
+    fn step_back() {
+        let mut state = State::new();
+        let mut history = History::new();
+
+        // LD V0, 01; DRW V0, V0, 1
+        state.load_rom(&[0x60, 0x01, 0xD0, 0x01]).unwrap();
+        state.dt = 5;
+        history.push(&state);
+        assert_eq!(true, crate::cpu::step(&mut state));
+        history.push(&state);
+        assert_eq!(true, crate::cpu::step(&mut state));
+        assert_eq!(3, state.dt);
+        assert_eq!(false, state.display.is_clear());
+
+        assert_eq!(true, history.step_back(&mut state));
+        assert_eq!(4, state.dt);
+        assert_eq!(0x202, state.pc);
+        assert_eq!(true, state.display.is_clear());
+        assert_eq!(true, history.step_back(&mut state));
+        assert_eq!(5, state.dt);
+        assert_eq!(0, state.v[0]);
+        assert_eq!(false, history.step_back(&mut state));
+    }
+
+    #[test]
+    fn rng_round_trip() {
+        let mut state = State::new();
+        state.set_seed(Some(42));
+        state.random_byte();
+        let data = save(&state);
+        let expected: Vec<u8> = (0..4).map(|_| state.random_byte()).collect();
+
+        let mut restored = State::new();
+        assert_eq!(Ok(()), load(&mut restored, &data));
+        assert_eq!(Some(42), restored.seed);
+        let drawn: Vec<u8> = (0..4).map(|_| restored.random_byte()).collect();
+        assert_eq!(expected, drawn);
+    }
+
+    #[test]
+    fn invalid_data() {
+        let mut state = State::new();
+        let mut data = save(&state);
+
+        assert!(load(&mut state, &data[0..20]).is_err());
+        data[0] = b'X';
+        assert!(load(&mut state, &data).is_err());
+
+        // SP past the end of the stack
+        let mut data = save(&state);
+        data[9] = state.stack.len() as u8 + 1;
+        assert!(load(&mut state, &data).is_err());
+        data[9] = state.stack.len() as u8;
+        assert_eq!(Ok(()), load(&mut state, &data));
+    }
+
+    #[test]
+    fn slot_paths() {
+        assert_eq!(
+            PathBuf::from("roms/pong.ch8.state0"),
+            slot_path(Path::new("roms/pong.ch8"), 0)
+        );
+        assert_eq!(
+            PathBuf::from("tetris.state9"),
+            slot_path(Path::new("tetris"), 9)
+        );
+    }
+
+    #[test]
+    fn autosaver() {
+        let mut autosaver = Autosaver::new(Duration::from_secs(30));
+        let frame = Duration::from_secs(10);
+
+        assert_eq!(false, autosaver.update(true, frame));
+        assert_eq!(false, autosaver.update(true, frame));
+        assert_eq!(true, autosaver.update(true, frame));
+        assert_eq!(false, autosaver.update(true, frame));
+        // Paused time doesn't count
+        for _ in 0..10 {
+            assert_eq!(false, autosaver.update(false, frame));
+        }
+        assert_eq!(false, autosaver.update(true, frame));
+        // A long frame saves once
+        assert_eq!(true, autosaver.update(true, Duration::from_secs(100)));
+        assert_eq!(false, autosaver.update(true, frame));
+
+        let rom = Path::new("roms/pong.ch8");
+        assert_eq!(
+            PathBuf::from("roms/pong.ch8.autosave0"),
+            autosaver.next_path(rom)
+        );
+        assert_eq!(
+            PathBuf::from("roms/pong.ch8.autosave1"),
+            autosaver.next_path(rom)
+        );
+        assert_eq!(
+            PathBuf::from("roms/pong.ch8.autosave0"),
+            autosaver.next_path(rom)
+        );
+    }
+
+    #[test]
+    fn slot_round_trip() {
+        let rom_path = std::env::temp_dir().join("chipster8_slot_test.ch8");
+        let mut state = State::new();
+
+        state.v[1] = 0x11;
+        write(&state, &slot_path(&rom_path, 3)).unwrap();
+        state.v[1] = 0x22;
+        write(&state, &slot_path(&rom_path, 4)).unwrap();
+
+        let mut restored = State::new();
+        read(&mut restored, &slot_path(&rom_path, 3)).unwrap();
+        assert_eq!(0x11, restored.v[1]);
+        read(&mut restored, &slot_path(&rom_path, 4)).unwrap();
+        assert_eq!(0x22, restored.v[1]);
+        let _ = fs::remove_file(slot_path(&rom_path, 3));
+        let _ = fs::remove_file(slot_path(&rom_path, 4));
+        assert!(read(&mut restored, &slot_path(&rom_path, 3)).is_err());
+    }
+
+    #[test]
+    fn write_and_read() {
+        let mut state = State::new();
+        let path = std::env::temp_dir().join("chipster8_savestate_test.state");
+
+        state.v[0] = 0xAB;
+        write(&state, &path).unwrap();
+        let mut restored = State::new();
+        read(&mut restored, &path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(0xAB, restored.v[0]);
+    }
+
+    #[test]
+    fn shutdown_writes_autosave() {
+        let mut state = State::new();
+        let path = std::env::temp_dir().join("chipster8_shutdown_test.state");
+        let _ = fs::remove_file(&path);
+
+        // Nothing pending, nothing written
+        assert_eq!(false, shutdown(&state, None).unwrap());
+        assert_eq!(false, path.exists());
+
+        state.v[0] = 0xCD;
+        assert_eq!(true, shutdown(&state, Some(&path)).unwrap());
+        let mut restored = State::new();
+        read(&mut restored, &path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(0xCD, restored.v[0]);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::display::{Display, Palette};
+use crate::state::crc32;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Uncompressed deflate blocks hold at most this many bytes
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Display as 8 bit RGB, each pixel scaled up to a scale x scale square. The
+// palette colors are blended over black by their alpha.
+pub fn render(display: &Display, palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(64 * 32 * scale * scale * 3);
+    for row in display.front().iter() {
+        for _ in 0..scale {
+            for pixel in row.iter() {
+                let color = palette[(*pixel & 0b11) as usize];
+                for _ in 0..scale {
+                    for channel in color[0..3].iter() {
+                        rgb.push((channel * color[3] * 255.0).round() as u8);
+                    }
+                }
+            }
+        }
+    }
+    rgb
+}
+
+// Same as render with an opaque alpha byte after each pixel, what the
+// clipboard takes
+pub fn render_rgba(display: &Display, palette: &Palette, scale: usize) -> Vec<u8> {
+    let rgb = render(display, palette, scale);
+    let mut rgba = Vec::with_capacity(rgb.len() / 3 * 4);
+    for pixel in rgb.chunks(3) {
+        rgba.extend_from_slice(pixel);
+        rgba.push(255);
+    }
+    rgba
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Minimal RGB PNG, the image data is stored without compression which is
+// fine for screenshots this small
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0); // No filter
+        raw.extend_from_slice(row);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(MAX_STORED_BLOCK).count();
+    for (i, block) in raw.chunks(MAX_STORED_BLOCK).enumerate() {
+        zlib.push((i + 1 == blocks) as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit RGB
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"IDAT", &zlib);
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+pub fn write_png(
+    path: &Path,
+    display: &Display,
+    palette: &Palette,
+    scale: usize,
+) -> io::Result<()> {
+    let rgb = render(display, palette, scale);
+    fs::write(path, encode_png(64 * scale, 32 * scale, &rgb))
+}
+
+// Puts the scaled up screen on the system clipboard as an image
+#[cfg(feature = "clipboard")]
+pub fn copy_to_clipboard(display: &Display, palette: &Palette, scale: usize) -> Result<(), String> {
+    let image = arboard::ImageData {
+        width: 64 * scale,
+        height: 32 * scale,
+        bytes: render_rgba(display, palette, scale).into(),
+    };
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_image(image))
+        .map_err(|why| why.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DEFAULT_PALETTE;
+
+    #[test]
+    fn adler32_test() {
+        assert_eq!(1, adler32(&[]));
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn render_test() {
+        let mut display = Display::new();
+
+        display.display_sprite(1, 0, &[0x80]);
+        let rgb = render(&display, &DEFAULT_PALETTE, 2);
+        assert_eq!(64 * 32 * 4 * 3, rgb.len());
+        // Pixel 1 covers columns 2 and 3 of the first two rows
+        assert_eq!([0, 0, 0], rgb[3..6]);
+        assert_eq!([255, 255, 255, 255, 255, 255], rgb[6..12]);
+        assert_eq!([255, 255, 255], rgb[(128 + 2) * 3..(128 + 3) * 3]);
+    }
+
+    #[test]
+    fn render_rgba_test() {
+        let mut display = Display::new();
+
+        display.display_sprite(0, 0, &[0x80]);
+        let rgba = render_rgba(&display, &DEFAULT_PALETTE, 2);
+        assert_eq!(64 * 32 * 4 * 4, rgba.len());
+        assert_eq!([255, 255, 255, 255, 255, 255, 255, 255], rgba[0..8]);
+        assert_eq!([0, 0, 0, 255], rgba[8..12]);
+    }
+
+    #[test]
+    fn encode_png_test() {
+        let png = encode_png(2, 1, &[255, 0, 0, 0, 255, 0]);
+
+        assert_eq!(b"\x89PNG\r\n\x1a\n", &png[0..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!([0, 0, 0, 2, 0, 0, 0, 1], png[16..24]);
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+        // Scanline of a filter byte and 6 color bytes in one stored block
+        assert_eq!([0x78, 0x01, 1, 7, 0, 0xF8, 0xFF, 0], png[41..49]);
+    }
+}
@@ -0,0 +1,60 @@
+// Parses a byte sequence typed as hex digits, like "A2 1E" or "a21e". Spaces
+// are ignored, every byte takes two digits.
+pub fn parse_hex_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.split_whitespace().collect();
+    if digits.is_empty() {
+        return Err(String::from("Nothing to search for"));
+    }
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid hex bytes: {}", text.trim()));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(format!("Odd number of hex digits: {}", text.trim()));
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|pos| u8::from_str_radix(&digits[pos..pos + 2], 16).unwrap())
+        .collect())
+}
+
+// Offset of every match of the pattern in the data, lowest first. Matches
+// can overlap, AA AA is found twice in AA AA AA.
+pub fn find_bytes(data: &[u8], pattern: &[u8]) -> Vec<usize> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    data.windows(pattern.len())
+        .enumerate()
+        .filter(|(_, window)| *window == pattern)
+        .map(|(offset, _)| offset)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_bytes_test() {
+        assert_eq!(Ok(vec![0xA2, 0x1E]), parse_hex_bytes("A2 1E"));
+        assert_eq!(Ok(vec![0xA2, 0x1E]), parse_hex_bytes(" a21e "));
+        assert!(parse_hex_bytes("A2 1").is_err());
+        assert!(parse_hex_bytes("XY").is_err());
+        assert!(parse_hex_bytes("é1").is_err());
+        assert!(parse_hex_bytes("  ").is_err());
+    }
+
+    #[test]
+    fn find_bytes_test() {
+        let data = [0x00, 0xA2, 0x1E, 0xAA, 0xAA, 0xAA, 0xA2, 0x1E];
+
+        assert_eq!(vec![1, 6], find_bytes(&data, &[0xA2, 0x1E]));
+        assert_eq!(vec![3, 4, 5], find_bytes(&data, &[0xAA]));
+        // Overlapping matches are all found
+        assert_eq!(vec![3, 4], find_bytes(&data, &[0xAA, 0xAA]));
+        assert_eq!(vec![0], find_bytes(&data, &data));
+        assert_eq!(Vec::<usize>::new(), find_bytes(&data, &[0x1E, 0x00]));
+        assert_eq!(Vec::<usize>::new(), find_bytes(&data, &[]));
+        assert_eq!(Vec::<usize>::new(), find_bytes(&[0xA2], &[0xA2, 0x1E]));
+    }
+}
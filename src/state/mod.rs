@@ -1,6 +1,141 @@
+use crate::audio::{AudioPattern, AudioSettings, Beeper, DEFAULT_FREQUENCY, DEFAULT_PITCH};
 use crate::display::Display;
-use rodio;
+use crate::instruction::{self, EnabledOps, Instruction, FAMILY_COUNT};
+use crate::quirks::Quirks;
+use rand::Rng;
+use serde_json::Value;
+use std::collections::{BTreeSet, VecDeque};
 use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_RAM_SIZE: usize = 0x1000;
+// Return addresses CALL can nest, what CHIP-48 and SUPER-CHIP have
+pub const DEFAULT_STACK_DEPTH: usize = 16;
+// SP is a byte
+pub const MAX_STACK_DEPTH: usize = 255;
+// Addresses kept in the execution trace
+pub const TRACE_LEN: usize = 64;
+// Of those, the ones shown in the crash report
+const CRASH_TRACE_LEN: usize = 8;
+
+// What happens when PC is advanced past the end of RAM
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PcOverflow {
+    Wrap,  // Continue from the start of RAM
+    Error, // Fault with PcOutOfRange
+}
+
+impl PcOverflow {
+    pub fn from_name(name: &str) -> Option<PcOverflow> {
+        match name {
+            "wrap" => Some(PcOverflow::Wrap),
+            "error" => Some(PcOverflow::Error),
+            _ => None,
+        }
+    }
+}
+
+// What the interpreter leaves on the screen before the ROM's first
+// instruction. Some ROMs were written against interpreters that drew during
+// their own boot and never clear the screen themselves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BootSplash {
+    Blank, // A cleared screen, what almost every interpreter shows
+    Font,  // The 16 font digits in two rows, like a self test screen
+}
+
+impl BootSplash {
+    pub fn from_name(name: &str) -> Option<BootSplash> {
+        match name {
+            "blank" => Some(BootSplash::Blank),
+            "font" => Some(BootSplash::Font),
+            _ => None,
+        }
+    }
+}
+
+// Random numbers for RND that are the same for the same seed, xorshift64*.
+// Written out here rather than taken from rand so a recorded replay keeps
+// drawing the same numbers whatever version of rand is built in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        // Xorshift never leaves 0
+        let state = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+        SeededRng { state: state }
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+// Why an instruction failed, kept in State::fault for the caller to report
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExecError {
+    AddressOutOfRange(usize),
+    IllegalOpcode(u16),
+    StackOverflow,
+    StackUnderflow,
+    MisalignedPc(u16),
+    PcOutOfRange(usize),
+    ProtectedWrite(usize),
+    Disabled(u16),
+    SpriteOutOfBounds { i: u16, height: u8 }, // DRW read past the end of RAM
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::AddressOutOfRange(addr) => write!(f, "Address {:04X} is out of RAM", addr),
+            ExecError::IllegalOpcode(opcode) => write!(f, "Illegal opcode {:04X}", opcode),
+            ExecError::StackOverflow => write!(f, "Stack overflow"),
+            ExecError::StackUnderflow => write!(f, "Return with an empty stack"),
+            ExecError::MisalignedPc(addr) => write!(f, "Jump to the odd address {:04X}", addr),
+            ExecError::PcOutOfRange(addr) => write!(f, "PC {:04X} is past the end of RAM", addr),
+            ExecError::ProtectedWrite(addr) => {
+                write!(f, "Write to {:04X} in the protected interpreter area", addr)
+            }
+            ExecError::Disabled(opcode) => write!(f, "Opcode {:04X} is disabled", opcode),
+            ExecError::SpriteOutOfBounds { i, height } => write!(
+                f,
+                "Sprite {} rows high at I {:04X} reads past the end of RAM",
+                height, i
+            ),
+        }
+    }
+}
+
+// Standard CRC-32 (IEEE), the checksum ROM databases list dumps by
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
 
 // #[derive(Debug)]
 pub struct State {
@@ -10,11 +145,32 @@ pub struct State {
     pub dt: u8,
     pub st: u8,
     pub v: [u8; 16],
-    pub stack: [u16; 16],
+    pub stack: Vec<u16>, // As many entries as the configured depth
     pub keypad: [bool; 16],
+    pub last_key: Option<u8>, // Most recently pressed key, what Fx0A stores
+    pub halted: bool,         // Set when the program jumps to itself or exits
+    pub exited: bool,         // Set by the SUPER-CHIP EXIT instruction
+    pub waiting_for_vblank: bool, // DRW is waiting for the next tick (display_wait quirk)
+    pub instruction_count: u64, // Instructions executed since the state was created
+    pub max_cycles: Option<u64>, // Running stops once instruction_count reaches it
+    pub rom_crc: u32,         // CRC-32 of the loaded ROM
+    pub timers_frozen: bool,  // DT and ST stop counting down, for debugging
+    pub enforce_alignment: bool, // A jump, call or return to an odd address faults
+    pub pc_overflow: PcOverflow, // What advance_pc does at the end of RAM
+    pub boot_splash: BootSplash, // Drawn by reset, before the ROM runs
+    pub enabled_ops: EnabledOps, // Instructions that can run, the rest fail
+    pub seed: Option<u64>,    // RND draws from a SeededRng, see set_seed
+    rng: Option<SeededRng>,
+    pub breakpoints: BTreeSet<u16>, // Running and Step N stop when PC gets to one
+    pub family_counts: [u64; FAMILY_COUNT], // Executed instructions by instruction::family_index
+    pub fault: Option<ExecError>,   // Why the last instruction failed
+    pub trace: VecDeque<u16>,       // Addresses of the last executed instructions, newest last
+    pub quirks: Quirks,
     pub display: Display,
-    pub ram: [u8; 4095],
-    pub audio_output: rodio::Sink,
+    pub ram: Vec<u8>,
+    pub audio_pattern: Option<AudioPattern>, // XO-CHIP sample buffer, set by F002
+    pub pitch: u8,                           // XO-CHIP playback rate, set by Fx3A
+    pub audio_output: Option<Beeper>,        // None when there's no audio device
 }
 
 impl fmt::Debug for State {
@@ -28,6 +184,23 @@ impl fmt::Debug for State {
             .field("v", &self.v)
             .field("stack", &self.stack)
             .field("keypad", &self.keypad)
+            .field("last_key", &self.last_key)
+            .field("halted", &self.halted)
+            .field("exited", &self.exited)
+            .field("waiting_for_vblank", &self.waiting_for_vblank)
+            .field("instruction_count", &self.instruction_count)
+            .field("max_cycles", &self.max_cycles)
+            .field("rom_crc", &format_args!("{:08x}", self.rom_crc))
+            .field("timers_frozen", &self.timers_frozen)
+            .field("enforce_alignment", &self.enforce_alignment)
+            .field("pc_overflow", &self.pc_overflow)
+            .field("enabled_ops", &self.enabled_ops)
+            .field("breakpoints", &self.breakpoints)
+            .field("fault", &self.fault)
+            .field("trace", &self.trace)
+            .field("quirks", &self.quirks)
+            .field("audio_pattern", &self.audio_pattern)
+            .field("pitch", &self.pitch)
             .field("display", &format_args!("\n{:?}", &self.display))
             .finish()
     }
@@ -35,24 +208,106 @@ impl fmt::Debug for State {
 
 impl State {
     pub fn new() -> State {
-        State {
+        State::with_ram_size(DEFAULT_RAM_SIZE)
+    }
+
+    // Extended interpreters (XO-CHIP) address up to 64KB
+    pub fn with_ram_size(ram_size: usize) -> State {
+        let mut state = State {
             v: [0; 16],
             i: 0,
             pc: 0x200,
             sp: 0,
-            stack: [0; 16],
+            stack: vec![0; DEFAULT_STACK_DEPTH],
             dt: 0,
             st: 0,
             display: Display::new(),
             keypad: [false; 16],
-            ram: [0; 0xFFF],
-            audio_output: rodio::Sink::new(&rodio::default_output_device().unwrap()),
+            last_key: None,
+            halted: false,
+            exited: false,
+            waiting_for_vblank: false,
+            instruction_count: 0,
+            max_cycles: None,
+            rom_crc: 0,
+            timers_frozen: false,
+            enforce_alignment: false,
+            pc_overflow: PcOverflow::Error,
+            boot_splash: BootSplash::Blank,
+            enabled_ops: EnabledOps::all(),
+            seed: None,
+            rng: None,
+            breakpoints: BTreeSet::new(),
+            family_counts: [0; FAMILY_COUNT],
+            fault: None,
+            trace: VecDeque::with_capacity(TRACE_LEN),
+            quirks: Quirks::default(),
+            ram: vec![0; ram_size],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            audio_output: Beeper::new(DEFAULT_FREQUENCY, AudioSettings::default()),
+        };
+        state.load_font();
+        state
+    }
+
+    // Back to the power-on state for loading a ROM. Settings survive: the
+    // quirks, RAM and stack size, cycle limit, display options and the audio
+    // device. The screen, keypad and trace of the previous ROM are cleared so
+    // nothing of it lingers.
+    pub fn reset(&mut self) {
+        self.v = [0; 16];
+        self.i = 0;
+        self.pc = 0x200;
+        self.sp = 0;
+        for value in self.stack.iter_mut() {
+            *value = 0;
         }
-        .fill_ram()
-        .fill_sound()
+        self.dt = 0;
+        self.st = 0;
+        self.keypad = [false; 16];
+        self.last_key = None;
+        self.halted = false;
+        self.exited = false;
+        self.waiting_for_vblank = false;
+        self.instruction_count = 0;
+        self.family_counts = [0; FAMILY_COUNT];
+        self.rng = self.seed.map(SeededRng::new);
+        self.rom_crc = 0;
+        self.fault = None;
+        self.trace.clear();
+        self.audio_pattern = None;
+        self.pitch = DEFAULT_PITCH;
+        if let Some(audio_output) = &self.audio_output {
+            audio_output.set_playing(false);
+        }
+        self.display.reset();
+        self.display.select_plane(1);
+        self.display.collisions.clear();
+        for byte in self.ram.iter_mut() {
+            *byte = 0;
+        }
+        self.load_font();
+        self.draw_boot_splash();
+        self.display.present();
+        self.display.clear_draw_log();
     }
 
-    fn fill_ram(mut self) -> Self {
+    fn draw_boot_splash(&mut self) {
+        match self.boot_splash {
+            BootSplash::Blank => {}
+            BootSplash::Font => {
+                for digit in 0..16 {
+                    let sprite = self.ram[digit * 5..digit * 5 + 5].to_vec();
+                    let x = (digit % 8) * 8;
+                    let y = (digit / 8) * 8;
+                    self.display.display_sprite(x as u8, y as u8, &sprite);
+                }
+            }
+        }
+    }
+
+    fn load_font(&mut self) {
         let character_data = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
             0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -72,27 +327,386 @@ impl State {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
         self.ram[0..80].copy_from_slice(&character_data);
+    }
+
+    // Resets the machine and loads the ROM, how every ROM gets started. The
+    // machine is left alone when the ROM doesn't fit.
+    pub fn reload(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.check_rom_fits(rom)?;
+        self.reset();
+        self.load_rom(rom)
+    }
+
+    // Copies the ROM to where programs start, fails if it doesn't fit
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), String> {
+        self.check_rom_fits(rom)?;
+        self.ram[0x200..(0x200 + rom.len())].copy_from_slice(rom);
+        self.rom_crc = crc32(rom);
+        Ok(())
+    }
+
+    fn check_rom_fits(&self, rom: &[u8]) -> Result<(), String> {
+        if 0x200 + rom.len() > self.ram.len() {
+            return Err(format!(
+                "ROM doesn't fit in RAM, {} bytes with {} available",
+                rom.len(),
+                self.ram.len().saturating_sub(0x200)
+            ));
+        }
+        Ok(())
+    }
+
+    // Updates a key from the input layer, remembering the latest press
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0x0F;
+        if pressed && !self.keypad[key as usize] {
+            self.last_key = Some(key);
+        }
+        self.keypad[key as usize] = pressed;
+    }
+
+    // True once max_cycles instructions have run, the run loops stop here
+    pub fn cycle_limit_reached(&self) -> bool {
+        self.max_cycles
+            .map_or(false, |max_cycles| self.instruction_count >= max_cycles)
+    }
+
+    // Key Fx0A should take: the latest press if it's still held, otherwise
+    // the lowest held key
+    pub fn held_key(&self) -> Option<u8> {
+        match self.last_key {
+            Some(key) if self.keypad[key as usize] => Some(key),
+            _ => self.keypad.iter().position(|key| *key).map(|key| key as u8),
+        }
+    }
+
+    // Big endian opcode stored at addr, an error when it runs past the end
+    // of RAM
+    pub fn opcode_at(&self, addr: usize) -> Result<u16, ExecError> {
+        match self.ram.get(addr..addr + 2) {
+            Some(word) => Ok((word[0] as u16) << 8 | word[1] as u16),
+            None => Err(ExecError::PcOutOfRange(addr)),
+        }
+    }
+
+    // Decoded with the word after it, for the operand of F000 NNNN
+    pub fn instruction_at(&self, addr: usize) -> Result<Instruction, ExecError> {
+        let next_word = match self.ram.get(addr + 2..addr + 4) {
+            Some(word) => Some((word[0] as u16) << 8 | word[1] as u16),
+            None => None,
+        };
+        Ok(Instruction::decode(self.opcode_at(addr)?, next_word))
+    }
+
+    // Instruction the next step executes
+    pub fn current_instruction(&self) -> Result<Instruction, ExecError> {
+        self.instruction_at(self.pc as usize)
+    }
+
+    pub fn current_mnemonic(&self) -> String {
+        match self.current_instruction() {
+            Ok(instruction) => instruction.code,
+            Err(error) => error.to_string(),
+        }
+    }
+
+    // Maps an address onto RAM. Addresses past the end wrap around with the
+    // mem_wrap quirk and are an error otherwise.
+    pub fn ram_index(&self, addr: usize) -> Result<usize, ExecError> {
+        if addr < self.ram.len() {
+            Ok(addr)
+        } else if self.quirks.mem_wrap {
+            Ok(addr % self.ram.len())
+        } else {
+            Err(ExecError::AddressOutOfRange(addr))
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> Result<u8, ExecError> {
+        Ok(self.ram[self.ram_index(addr)?])
+    }
+
+    // Every write a program makes goes through here. With the
+    // protect_interpreter_area quirk the font and interpreter stay untouched.
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), ExecError> {
+        let index = self.ram_index(addr)?;
+        if self.quirks.protect_interpreter_area && index < 0x200 {
+            return Err(ExecError::ProtectedWrite(index));
+        }
+        self.ram[index] = value;
+        Ok(())
+    }
+
+    pub fn read_bytes(&self, addr: usize, len: usize) -> Result<Vec<u8>, ExecError> {
+        (addr..(addr + len)).map(|addr| self.read(addr)).collect()
+    }
+
+    // Writes a byte to RAM, failing if the address is past the end
+    pub fn poke(&mut self, addr: u16, value: u8) -> Result<(), String> {
+        match self.ram.get_mut(addr as usize) {
+            Some(byte) => {
+                *byte = value;
+                Ok(())
+            }
+            None => Err(format!("Poke address {:04X} is out of RAM", addr)),
+        }
+    }
+
+    // Writes the raw RAM image
+    pub fn dump_ram(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, &self.ram)
+    }
+
+    // Human readable registers and stack, written next to RAM dumps
+    pub fn register_summary(&self) -> String {
+        let mut summary = String::new();
+        for (i, v) in self.v.iter().enumerate() {
+            summary.push_str(&format!("V{:01X}: {:02X}\n", i, v));
+        }
+        summary.push_str(&format!(
+            "I: {:04X}\nPC: {:04X}\nSP: {:02X}\nDT: {:02X}\nST: {:02X}\n",
+            self.i, self.pc, self.sp, self.dt, self.st
+        ));
+        for (i, value) in self.stack.iter().enumerate() {
+            summary.push_str(&format!("Stack {:01X}: {:04X}\n", i, value));
+        }
+        summary
+    }
+
+    // Registers, the fault and the last few executed addresses, for the crash
+    // report
+    pub fn crash_report(&self) -> String {
+        let mut report = self.register_summary();
+        report.push_str(&format!(
+            "Instructions: {}\nFault: {:?}\nTrace:",
+            self.instruction_count, self.fault
+        ));
+        for addr in self.trace.iter().rev().take(CRASH_TRACE_LEN) {
+            report.push_str(&format!(" {:04X}", addr));
+        }
+        report.push('\n');
+        report
+    }
+
+    // Registers, timers, stack and keypad as JSON for external debuggers and
+    // viewers, with the display packed like to_monochrome_bytes as a hex
+    // string. Unlike a save state it leaves out RAM and the quirks.
+    pub fn to_json(&self) -> String {
+        let display: String = self
+            .display
+            .to_monochrome_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        json!({
+            "v": self.v,
+            "i": self.i,
+            "pc": self.pc,
+            "sp": self.sp,
+            "dt": self.dt,
+            "st": self.st,
+            "stack": self.stack,
+            "keypad": self.keypad,
+            "display": display,
+        })
+        .to_string()
+    }
+
+    // A fresh state with what to_json wrote. The display is optional, when
+    // it's there lit pixels end up in the first plane.
+    pub fn from_json(json: &str) -> Result<State, String> {
+        let value: Value =
+            serde_json::from_str(json).map_err(|why| format!("Invalid JSON: {}", why))?;
+        let mut state = State::new();
+
+        for (register, entry) in state.v.iter_mut().zip(json_array(&value, "v", 16)?) {
+            *register = json_number(entry, "v", 0xFF)? as u8;
+        }
+        state.i = json_field(&value, "i", 0xFFFF)? as u16;
+        state.pc = json_field(&value, "pc", 0xFFFF)? as u16;
+        state.dt = json_field(&value, "dt", 0xFF)? as u8;
+        state.st = json_field(&value, "st", 0xFF)? as u8;
+
+        let stack = value
+            .get("stack")
+            .and_then(Value::as_array)
+            .filter(|stack| !stack.is_empty() && stack.len() <= MAX_STACK_DEPTH)
+            .ok_or_else(|| String::from("Missing or invalid stack"))?;
+        state.stack = stack
+            .iter()
+            .map(|entry| json_number(entry, "stack", 0xFFFF).map(|addr| addr as u16))
+            .collect::<Result<Vec<u16>, String>>()?;
+        state.sp = json_field(&value, "sp", state.stack.len() as u64)? as u8;
+
+        for (key, entry) in state
+            .keypad
+            .iter_mut()
+            .zip(json_array(&value, "keypad", 16)?)
+        {
+            *key = entry
+                .as_bool()
+                .ok_or_else(|| String::from("Missing or invalid keypad"))?;
+        }
+
+        if let Some(display) = value.get("display") {
+            let bytes = display
+                .as_str()
+                .filter(|hex| hex.len() == 64 * 32 / 4)
+                .and_then(|hex| {
+                    (0..hex.len())
+                        .step_by(2)
+                        .map(|pos| u8::from_str_radix(hex.get(pos..pos + 2)?, 16).ok())
+                        .collect::<Option<Vec<u8>>>()
+                })
+                .ok_or_else(|| String::from("Missing or invalid display"))?;
+            for (y, row) in state.display.data.iter_mut().enumerate() {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    *pixel = (bytes[y * 8 + x / 8] >> (7 - x % 8)) & 1;
+                }
+            }
+            state.display.dirty = true;
+            state.display.present();
+        }
+        Ok(state)
+    }
+
+    // Executed instructions by opcode pattern, most frequent first
+    pub fn profile(&self) -> Vec<(String, u64)> {
+        let mut profile: Vec<(String, u64)> = self
+            .family_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| (instruction::pattern_at(index).to_string(), *count))
+            .collect();
+        profile.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        profile
+    }
+
+    // Deterministic mode, RND draws the same numbers after every reset. None
+    // goes back to the thread's random numbers.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+        self.rng = seed.map(SeededRng::new);
+    }
+
+    // Seed and generator state of deterministic mode, for save states to
+    // draw the same numbers after they're loaded
+    pub fn rng_state(&self) -> Option<(u64, u64)> {
+        match (self.seed, self.rng) {
+            (Some(seed), Some(rng)) => Some((seed, rng.state)),
+            _ => None,
+        }
+    }
+
+    pub fn restore_rng(&mut self, seed: u64, generator: u64) {
+        self.seed = Some(seed);
+        self.rng = Some(SeededRng { state: generator });
+    }
+
+    // The byte RND masks
+    pub fn random_byte(&mut self) -> u8 {
+        match &mut self.rng {
+            Some(rng) => rng.next_byte(),
+            None => rand::thread_rng().gen_range(0, 256) as u8,
+        }
+    }
+
+    // Moves PC on by n bytes, what every instruction that doesn't jump ends
+    // with. When the next fetch would read past the end of RAM PC wraps to the
+    // start or the instruction faults, as pc_overflow says. Returns false on a
+    // fault like the instruction functions.
+    pub fn advance_pc(&mut self, n: u16) -> bool {
+        self.jump(self.pc as usize + n as usize)
+    }
+
+    // Sets PC for a jump or call, past the end of RAM it's handled like
+    // advance_pc. Bnnn can land up to 0x10FE.
+    pub fn jump(&mut self, addr: usize) -> bool {
+        match self.pc_for(addr) {
+            Ok(pc) => {
+                self.pc = pc;
+                true
+            }
+            Err(error) => {
+                self.fault = Some(error);
+                false
+            }
+        }
+    }
+
+    // Where PC ends up when set to addr, wrapped or an error past the end of
+    // RAM as pc_overflow says
+    pub fn pc_for(&self, addr: usize) -> Result<u16, ExecError> {
+        if addr + 1 < self.ram.len() {
+            return Ok(addr as u16);
+        }
+        match self.pc_overflow {
+            PcOverflow::Wrap => Ok((addr % self.ram.len()) as u16),
+            PcOverflow::Error => Err(ExecError::PcOutOfRange(addr)),
+        }
+    }
 
-        self
+    pub fn at_breakpoint(&self) -> bool {
+        self.breakpoints.contains(&self.pc)
     }
 
-    fn fill_sound(self) -> Self {
-        self.audio_output.append(rodio::source::SineWave::new(392));
-        self.audio_output.pause();
-        self
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    // Steps over the next instruction, for the skip instructions
+    pub fn skip_next(&mut self) -> bool {
+        self.advance_pc(4)
+    }
+
+    // Resizes the stack, entries past the new depth are dropped
+    pub fn set_stack_depth(&mut self, depth: usize) {
+        let depth = depth.max(1).min(MAX_STACK_DEPTH);
+        self.stack.resize(depth, 0);
+        self.sp = self.sp.min(depth as u8);
     }
 
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), ExecError> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(ExecError::StackOverflow);
+        }
         self.stack[self.sp as usize] = value;
         self.sp += 1;
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> u16 {
+    pub fn pop(&mut self) -> Result<u16, ExecError> {
+        if self.sp == 0 {
+            return Err(ExecError::StackUnderflow);
+        }
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Ok(self.stack[self.sp as usize])
     }
 }
 
+// Whole number up to max, for from_json
+fn json_number(value: &Value, name: &str, max: u64) -> Result<u64, String> {
+    value
+        .as_u64()
+        .filter(|number| *number <= max)
+        .ok_or_else(|| format!("Missing or invalid {}", name))
+}
+
+fn json_field(value: &Value, key: &str, max: u64) -> Result<u64, String> {
+    json_number(value.get(key).unwrap_or(&Value::Null), key, max)
+}
+
+fn json_array<'a>(value: &'a Value, key: &str, len: usize) -> Result<&'a Vec<Value>, String> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .filter(|array| array.len() == len)
+        .ok_or_else(|| format!("Missing or invalid {}", key))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -100,7 +714,7 @@ mod test {
     fn push_test() {
         let mut state = State::new();
 
-        state.push(0xABC);
+        state.push(0xABC).unwrap();
         assert_eq!(0, state.sp - 1);
         assert_eq!(0xABC, state.stack[(state.sp - 1) as usize]);
     }
@@ -111,7 +725,347 @@ mod test {
 
         state.stack[0] = 0xABC;
         state.sp = 1;
-        assert_eq!(0xABC, state.pop());
+        assert_eq!(Ok(0xABC), state.pop());
         assert_eq!(0, state.sp);
+        assert_eq!(Err(ExecError::StackUnderflow), state.pop());
+    }
+
+    #[test]
+    fn stack_depth() {
+        let mut state = State::new();
+
+        for i in 0..16 {
+            state.push(i).unwrap();
+        }
+        assert_eq!(Err(ExecError::StackOverflow), state.push(16));
+
+        let mut state = State::new();
+        state.set_stack_depth(32);
+        for i in 0..20 {
+            state.push(i).unwrap();
+        }
+        assert_eq!(20, state.sp);
+        assert_eq!(Ok(19), state.pop());
+        assert_eq!(32, state.stack.len());
+    }
+
+    #[test]
+    fn load_rom_test() {
+        let mut state = State::new();
+
+        state.load_rom(&[0x12, 0x34]).unwrap();
+        assert_eq!([0x12, 0x34], state.ram[0x200..0x202]);
+        assert_eq!(crc32(&[0x12, 0x34]), state.rom_crc);
+
+        // Too big for RAM, nothing changes
+        state.v[1] = 1;
+        let rom = vec![0xAA; 0x1000 - 0x200 + 1];
+        assert_eq!(
+            Err(String::from(
+                "ROM doesn't fit in RAM, 3585 bytes with 3584 available"
+            )),
+            state.load_rom(&rom)
+        );
+        assert!(state.reload(&rom).is_err());
+        assert_eq!(1, state.v[1]);
+        assert_eq!(0x12, state.ram[0x200]);
+    }
+
+    #[test]
+    fn cleared_at_boot() {
+        let mut state = State::new();
+        let blank = |state: &State| {
+            state.display.is_clear()
+                && state
+                    .display
+                    .front()
+                    .iter()
+                    .all(|row| row.iter().all(|p| *p == 0))
+        };
+        assert_eq!(true, blank(&state));
+
+        state.display.double_buffered = true;
+        state.load_rom(&[0x00, 0xE0]).unwrap();
+        assert_eq!(true, blank(&state));
+        state.reload(&[0x00, 0xE0]).unwrap();
+        assert_eq!(true, blank(&state));
+    }
+
+    #[test]
+    fn font_boot_splash() {
+        let mut state = State::new();
+        state.boot_splash = BootSplash::Font;
+        state.display.double_buffered = true;
+
+        state.reload(&[0x00, 0xE0]).unwrap();
+        assert_eq!(false, state.display.is_clear());
+        // 0 in the top left, F as the last digit of the second row
+        assert_eq!(&[1, 1, 1, 1, 0], &state.display.front()[0][0..5]);
+        assert_eq!(&[1, 1, 1, 1, 0], &state.display.front()[8][56..61]);
+        assert_eq!(&[1, 0, 0, 0, 0], &state.display.front()[12][56..61]);
+    }
+
+    #[test]
+    fn reload_test() {
+        let mut state = State::new();
+
+        state.quirks.wrap_x = false;
+        state.set_stack_depth(24);
+        state.display.double_buffered = true;
+        state.load_rom(&[0x12, 0x34, 0x56]).unwrap();
+        state.display.display_sprite(0, 0, &[0xFF]);
+        state.display.present();
+        state.set_key(5, true);
+        state.trace.push_back(0x200);
+        state.v[3] = 7;
+        state.pc = 0x204;
+
+        state.reload(&[0xAB, 0xCD]).unwrap();
+        assert_eq!(true, state.display.is_clear());
+        assert_eq!(
+            true,
+            state
+                .display
+                .front()
+                .iter()
+                .all(|row| row.iter().all(|p| *p == 0))
+        );
+        assert_eq!(true, state.display.dirty);
+        assert_eq!([false; 16], state.keypad);
+        assert_eq!(None, state.held_key());
+        assert_eq!(true, state.trace.is_empty());
+        assert_eq!(0, state.v[3]);
+        assert_eq!(0x200, state.pc);
+        assert_eq!([0xAB, 0xCD, 0x00], state.ram[0x200..0x203]);
+        assert_eq!(0xF0, state.ram[0]);
+
+        assert_eq!(false, state.quirks.wrap_x);
+        assert_eq!(24, state.stack.len());
+        assert_eq!(true, state.display.double_buffered);
+    }
+
+    #[test]
+    fn crc32_test() {
+        assert_eq!(0, crc32(&[]));
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn poke_test() {
+        let mut state = State::new();
+
+        assert_eq!(Ok(()), state.poke(0x200, 0x12));
+        assert_eq!(0x12, state.ram[0x200]);
+        assert!(state.poke(0x1000, 0x12).is_err());
+    }
+
+    #[test]
+    fn protect_interpreter_area() {
+        let mut state = State::new();
+
+        // Allowed by default
+        assert_eq!(Ok(()), state.write(0x100, 0x12));
+        assert_eq!(0x12, state.ram[0x100]);
+
+        state.quirks.protect_interpreter_area = true;
+        assert_eq!(
+            Err(ExecError::ProtectedWrite(0x100)),
+            state.write(0x100, 0x34)
+        );
+        assert_eq!(0x12, state.ram[0x100]);
+        assert_eq!(Ok(()), state.write(0x200, 0x34));
+        assert_eq!(0x34, state.ram[0x200]);
+
+        // LD [I], V1 with I in the font
+        state.load_rom(&[0xF1, 0x55]).unwrap();
+        state.i = 0x1FF;
+        assert_eq!(false, crate::cpu::step(&mut state));
+        assert_eq!(Some(ExecError::ProtectedWrite(0x1FF)), state.fault);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut state = State::new();
+        state.v[3] = 0xAB;
+        state.i = 0x345;
+        state.pc = 0x2F0;
+        state.dt = 7;
+        state.st = 2;
+        assert_eq!(true, state.push(0x204).is_ok());
+        state.keypad[0xA] = true;
+        state.display.display_sprite(62, 31, &[0xC0]);
+
+        let restored = State::from_json(&state.to_json()).unwrap();
+        assert_eq!(state.v, restored.v);
+        assert_eq!(state.i, restored.i);
+        assert_eq!(state.pc, restored.pc);
+        assert_eq!(state.sp, restored.sp);
+        assert_eq!(state.dt, restored.dt);
+        assert_eq!(state.st, restored.st);
+        assert_eq!(state.stack, restored.stack);
+        assert_eq!(state.keypad, restored.keypad);
+        assert_eq!(state.display.frame_hash(), restored.display.frame_hash());
+
+        // The display can be left out, anything else can't
+        let json = r#"{"v":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"i":0,"pc":512,"sp":0,
+            "dt":0,"st":0,"stack":[0,0],"keypad":[false,false,false,false,false,false,
+            false,false,false,false,false,false,false,false,false,false]}"#;
+        assert_eq!(2, State::from_json(json).unwrap().stack.len());
+        assert!(State::from_json(&json.replace(r#""pc":512"#, r#""pc":-1"#)).is_err());
+        assert!(State::from_json(&json.replace(r#""sp":0"#, r#""sp":3"#)).is_err());
+        assert!(State::from_json("{}").is_err());
+        assert!(State::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn json_fields() {
+        let mut state = State::new();
+        state.v[0xF] = 1;
+        state.pc = 0x2A0;
+
+        let value: Value = serde_json::from_str(&state.to_json()).unwrap();
+        assert_eq!(Some(0x2A0), value["pc"].as_u64());
+        assert_eq!(Some(1), value["v"].as_array().unwrap()[15].as_u64());
+        assert_eq!(
+            Some(false),
+            value["keypad"].as_array().unwrap()[0].as_bool()
+        );
+        assert_eq!(Some(512), value["display"].as_str().map(str::len));
+    }
+
+    #[test]
+    fn ram_index_test() {
+        let mut state = State::new();
+
+        assert_eq!(Ok(0xFFF), state.ram_index(0xFFF));
+        assert_eq!(
+            Err(ExecError::AddressOutOfRange(0x1000)),
+            state.ram_index(0x1000)
+        );
+        assert!(state.read_bytes(0xFFE, 3).is_err());
+
+        state.quirks.mem_wrap = true;
+        state.ram[0xFFF] = 0x12;
+        state.ram[0] = 0x34;
+        assert_eq!(Ok(0x001), state.ram_index(0x1001));
+        assert_eq!(Ok(vec![0x12, 0x34]), state.read_bytes(0xFFF, 2));
+        assert_eq!(Ok(()), state.write(0x1002, 0x56));
+        assert_eq!(0x56, state.ram[2]);
+    }
+
+    #[test]
+    fn dump_ram_test() {
+        let mut state = State::new();
+        let path = std::env::temp_dir().join("chipster8_dump_ram_test.bin");
+
+        state.ram[0x200] = 0xAB;
+        state.ram[0xFFF] = 0xCD;
+        state.dump_ram(&path).unwrap();
+        let dump = fs::read(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(state.ram, dump);
+    }
+
+    #[test]
+    fn register_summary_test() {
+        let mut state = State::new();
+
+        state.v[0xA] = 0x12;
+        let summary = state.register_summary();
+        assert!(summary.contains("VA: 12\n"));
+        assert!(summary.contains("PC: 0200\n"));
+    }
+
+    #[test]
+    fn advance_pc() {
+        let mut state = State::new();
+
+        assert_eq!(true, state.advance_pc(2));
+        assert_eq!(0x202, state.pc);
+
+        // The last whole instruction in 4KB starts at FFE
+        state.pc = 0xFFC;
+        assert_eq!(true, state.advance_pc(2));
+        assert_eq!(0xFFE, state.pc);
+        assert_eq!(false, state.advance_pc(2));
+        assert_eq!(Some(ExecError::PcOutOfRange(0x1000)), state.fault);
+        assert_eq!(0xFFE, state.pc);
+
+        state.pc_overflow = PcOverflow::Wrap;
+        assert_eq!(true, state.advance_pc(2));
+        assert_eq!(0x000, state.pc);
+        state.pc = 0xFFE;
+        assert_eq!(true, state.advance_pc(4));
+        assert_eq!(0x002, state.pc);
+    }
+
+    #[test]
+    fn jump() {
+        let mut state = State::new();
+
+        assert_eq!(true, state.jump(0xFFE));
+        assert_eq!(0xFFE, state.pc);
+        assert_eq!(false, state.jump(0x10FE));
+        assert_eq!(Some(ExecError::PcOutOfRange(0x10FE)), state.fault);
+        assert_eq!(0xFFE, state.pc);
+
+        state.pc_overflow = PcOverflow::Wrap;
+        assert_eq!(true, state.jump(0x10FE));
+        assert_eq!(0x0FE, state.pc);
+    }
+
+    #[test]
+    fn skip_next() {
+        let mut state = State::new();
+
+        assert_eq!(true, state.skip_next());
+        assert_eq!(0x204, state.pc);
+
+        state.pc = 0xFFC;
+        assert_eq!(false, state.skip_next());
+        assert_eq!(0xFFC, state.pc);
+        state.pc_overflow = PcOverflow::Wrap;
+        assert_eq!(true, state.skip_next());
+        assert_eq!(0x000, state.pc);
+    }
+
+    #[test]
+    fn crash_report() {
+        let mut state = State::new();
+
+        state.v[0x3] = 0x45;
+        for addr in 0..20 {
+            state.trace.push_back(0x200 + addr * 2);
+        }
+        let report = state.crash_report();
+        assert!(report.contains("V3: 45\n"));
+        assert!(report.contains("Trace: 0226 0224"));
+        assert!(!report.contains("0216"));
+    }
+
+    #[test]
+    fn current_instruction() {
+        let mut state = State::new();
+
+        state.load_rom(&[0x00, 0xE0, 0x61, 0xAA]).unwrap();
+        assert_eq!(0x00E0, state.current_instruction().unwrap().opcode);
+        assert_eq!("CLS", state.current_mnemonic());
+        state.pc = 0x202;
+        assert_eq!("LD V1, AA", state.current_mnemonic());
+        assert_eq!(Ok(0x61AA), state.opcode_at(0x202));
+        // Half an opcode at the end of RAM
+        state.pc = 0xFFF;
+        assert_eq!(Err(ExecError::PcOutOfRange(0xFFF)), state.opcode_at(0xFFF));
+        assert!(state.current_instruction().is_err());
+    }
+
+    #[test]
+    fn ram_size_test() {
+        let mut state = State::with_ram_size(0x10000);
+
+        assert_eq!(0x10000, state.ram.len());
+        state.ram[0xFFFF] = 0xAB;
+        assert_eq!(0xAB, state.ram[0xFFFF]);
+        assert_eq!(DEFAULT_RAM_SIZE, State::new().ram.len());
     }
 }
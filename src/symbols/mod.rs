@@ -0,0 +1,81 @@
+use crate::instruction::{get_addr, Instruction};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Labels by address
+pub type Symbols = HashMap<u16, String>;
+
+// Parses "address = label" lines, the address in hex. Empty lines and lines
+// starting with ; are skipped.
+pub fn parse(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let addr = parts.next().unwrap().trim();
+        let label = match parts.next() {
+            Some(label) if !label.trim().is_empty() => label.trim(),
+            _ => return Err(format!("Missing label on line {}", line_no + 1)),
+        };
+        let addr = addr.trim_start_matches("0x").trim_start_matches("0X");
+        let addr = match u16::from_str_radix(addr, 16) {
+            Ok(addr) => addr,
+            Err(_) => return Err(format!("Invalid address on line {}", line_no + 1)),
+        };
+        symbols.insert(addr, label.to_string());
+    }
+    Ok(symbols)
+}
+
+pub fn load(path: &Path) -> Result<Symbols, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => parse(&text),
+        Err(why) => Err(format!("Couldn't read {}: {}", path.display(), why)),
+    }
+}
+
+// Disassembly with the JMP, CALL and LD I targets replaced by their labels
+pub fn annotate(instruction: &Instruction, symbols: &Symbols) -> String {
+    let mnemonic = match instruction.opcode & 0xF000 {
+        0x1000 => "JMP",
+        0x2000 => "CALL",
+        0xA000 => "LD I,",
+        _ => return instruction.code.clone(),
+    };
+    match symbols.get(&get_addr(instruction.opcode)) {
+        Some(label) => format!("{} {}", mnemonic, label),
+        None => instruction.code.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let symbols = parse("; sprites\n204 = loop\n0x300=sprite\n\n").unwrap();
+
+        assert_eq!(2, symbols.len());
+        assert_eq!("loop", symbols[&0x204]);
+        assert_eq!("sprite", symbols[&0x300]);
+        assert_eq!(true, parse("204").is_err());
+        assert_eq!(true, parse("xyz = loop").is_err());
+    }
+
+    #[test]
+    fn annotate_test() {
+        let symbols = parse("204=loop").unwrap();
+
+        assert_eq!("JMP loop", annotate(&Instruction::new(0x1204), &symbols));
+        assert_eq!("CALL loop", annotate(&Instruction::new(0x2204), &symbols));
+        assert_eq!("LD I, loop", annotate(&Instruction::new(0xA204), &symbols));
+        assert_eq!("JMP 206", annotate(&Instruction::new(0x1206), &symbols));
+        assert_eq!("LD V1, 04", annotate(&Instruction::new(0x6104), &symbols));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::state::State;
+
+// A watch expression: a register, a hex literal or the RAM byte at an
+// address, like V1, I, PC, 0x300, [0x300] or [I]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+    Literal(u16),
+    Memory(Box<Expr>), // Byte at the address the inner expression gives
+}
+
+// Case doesn't matter and literals are hex with or without 0x, like the
+// addresses in the memory view
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let text = text.trim();
+    if text.starts_with('[') && text.ends_with(']') && text.len() >= 2 {
+        return Ok(Expr::Memory(Box::new(parse(&text[1..text.len() - 1])?)));
+    }
+
+    let upper = text.to_uppercase();
+    let expr = match upper.as_str() {
+        "I" => Expr::I,
+        "PC" => Expr::Pc,
+        "SP" => Expr::Sp,
+        "DT" => Expr::Dt,
+        "ST" => Expr::St,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match usize::from_str_radix(&upper[1..], 16) {
+                Ok(x) => Expr::V(x),
+                Err(_) => return Err(format!("Unknown register {}", text)),
+            }
+        }
+        _ => {
+            let digits = upper.trim_start_matches("0X");
+            match u16::from_str_radix(digits, 16) {
+                Ok(value) if !digits.is_empty() => Expr::Literal(value),
+                _ => return Err(format!("Invalid expression {}", text)),
+            }
+        }
+    };
+    Ok(expr)
+}
+
+impl Expr {
+    // Current value, an error for an address outside RAM
+    pub fn evaluate(&self, state: &State) -> Result<u16, String> {
+        let value = match self {
+            Expr::V(x) => state.v[*x] as u16,
+            Expr::I => state.i,
+            Expr::Pc => state.pc,
+            Expr::Sp => state.sp as u16,
+            Expr::Dt => state.dt as u16,
+            Expr::St => state.st as u16,
+            Expr::Literal(value) => *value,
+            Expr::Memory(addr) => {
+                let addr = addr.evaluate(state)? as usize;
+                match state.ram_index(addr) {
+                    Ok(index) => state.ram[index] as u16,
+                    Err(why) => return Err(why.to_string()),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+// An expression as typed, kept with its parse so the panel shows the error
+pub struct Watch {
+    pub text: String,
+    pub expr: Result<Expr, String>,
+}
+
+impl Watch {
+    pub fn new(text: &str) -> Watch {
+        Watch {
+            text: text.trim().to_string(),
+            expr: parse(text),
+        }
+    }
+
+    // What the panel shows after the expression, hex and decimal
+    pub fn value_text(&self, state: &State) -> String {
+        match &self.expr {
+            Ok(expr) => match expr.evaluate(state) {
+                Ok(value) => format!("{:X} ({})", value, value),
+                Err(why) => why,
+            },
+            Err(why) => why.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registers() {
+        let mut state = State::new();
+        state.v[1] = 0x2A;
+        state.v[0xF] = 1;
+        state.i = 0x300;
+
+        assert_eq!(Ok(Expr::V(1)), parse("V1"));
+        assert_eq!(Ok(0x2A), parse("v1").unwrap().evaluate(&state));
+        assert_eq!(Ok(1), parse("VF").unwrap().evaluate(&state));
+        assert_eq!(Ok(0x300), parse(" I ").unwrap().evaluate(&state));
+        assert_eq!(Ok(0x200), parse("PC").unwrap().evaluate(&state));
+        assert!(parse("VG").is_err());
+        assert!(parse("V10").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn memory() {
+        let mut state = State::new();
+        state.ram[0x300] = 0x12;
+        state.ram[0x345] = 0x34;
+        state.i = 0x345;
+
+        assert_eq!(
+            Ok(Expr::Memory(Box::new(Expr::Literal(0x300)))),
+            parse("[0x300]")
+        );
+        assert_eq!(Ok(0x12), parse("[0x300]").unwrap().evaluate(&state));
+        assert_eq!(Ok(0x12), parse("[300]").unwrap().evaluate(&state));
+        assert_eq!(Ok(0x34), parse("[I]").unwrap().evaluate(&state));
+        assert!(parse("[0xFFFF]").unwrap().evaluate(&state).is_err());
+        assert!(parse("[I").is_err());
+
+        let watch = Watch::new("[i]");
+        assert_eq!("34 (52)", watch.value_text(&state));
+        assert_eq!(
+            "Invalid expression XYZ",
+            Watch::new("XYZ").value_text(&state)
+        );
+    }
+}
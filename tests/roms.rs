@@ -0,0 +1,34 @@
+// Runs ROMs headless and compares the final screen against committed snapshots
+// Snapshots are the output of Display::to_monochrome_bytes
+use chipster8::cpu;
+use chipster8::state::State;
+use std::fs;
+use std::path::PathBuf;
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("roms")
+        .join(name)
+}
+
+fn run_rom(name: &str, cycles: usize) -> Vec<u8> {
+    let mut state = State::new();
+    state.load_rom(&fs::read(rom_path(name)).unwrap()).unwrap();
+
+    assert_eq!(true, cpu::run_cycles(&mut state, cycles));
+    state.display.to_monochrome_bytes()
+}
+
+fn assert_snapshot(rom: &str, snapshot: &str, cycles: usize) {
+    let expected = fs::read(rom_path(snapshot)).unwrap();
+    let actual = run_rom(rom, cycles);
+
+    assert_eq!(expected, actual, "{} doesn't match {}", rom, snapshot);
+}
+
+#[test]
+// Draws the hex font and the BCD digits of 0x9C
+fn font() {
+    assert_snapshot("font.ch8", "font.snapshot", 1000);
+}